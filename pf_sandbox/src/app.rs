@@ -8,14 +8,16 @@ use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc;
 use std;
 
-use pf_sandbox_lib::command_line::CommandLine;
 use pf_sandbox_lib::config::Config;
+use pf_sandbox_lib::history::History;
 use pf_sandbox_lib::network::{NetCommandLine, Netplay, NetplayState};
 use pf_sandbox_lib::package::Package;
 use pf_sandbox_lib::package;
+use pf_sandbox_lib::simulation::PlayerSetup;
 use crate::ai;
 use crate::cli::{CLIResults, ContinueFrom};
-use crate::game::{Game, GameState, GameSetup, PlayerSetup};
+use crate::command_line::CommandLine;
+use crate::game::{Game, GameState, GameSetup};
 use crate::input::Input;
 use crate::menu::{Menu, MenuState, ResumeMenu};
 
@@ -58,7 +60,7 @@ pub fn run(mut cli_results: CLIResults, config: Config) {
     #[cfg(any(feature = "wgpu_renderer"))]
     let mut graphics_tx: Option<Sender<GraphicsMessage>> = None;
     let mut net_command_line = NetCommandLine::new();
-    let mut netplay = Netplay::new();
+    let mut netplay = Netplay::new(config.netplay_input_delay, config.netplay_max_rollback);
 
     // CLI options
     let (mut menu, mut game, mut os_input) = {
@@ -145,8 +147,9 @@ pub fn run(mut cli_results: CLIResults, config: Config) {
                 for i in 0..input_len {
                     controllers.push(i);
                     players.push(PlayerSetup {
-                        fighter: cli_results.fighter_names[i % cli_results.fighter_names.len()].clone(),
-                        team:    i
+                        fighter:  cli_results.fighter_names[i % cli_results.fighter_names.len()].clone(),
+                        team:     i,
+                        handicap: cli_results.handicaps.get(i).cloned().unwrap_or_default(),
                     });
                 }
 
@@ -164,8 +167,9 @@ pub fn run(mut cli_results: CLIResults, config: Config) {
                 if let Some(total_players) = cli_results.total_cpu_players {
                     for i in 0..total_players {
                         players.push(PlayerSetup {
-                            fighter: cli_results.fighter_names[(players_len + i) % cli_results.fighter_names.len()].clone(),
-                            team:    players_len + i
+                            fighter:  cli_results.fighter_names[(players_len + i) % cli_results.fighter_names.len()].clone(),
+                            team:     players_len + i,
+                            handicap: cli_results.handicaps.get(players_len + i).cloned().unwrap_or_default(),
                         });
                         controllers.push(input_len + i);
                         ais.push(0);
@@ -179,8 +183,8 @@ pub fn run(mut cli_results: CLIResults, config: Config) {
                 let setup = GameSetup {
                     init_seed:      GameSetup::gen_seed(),
                     input_history:  vec!(),
-                    player_history: vec!(),
-                    stage_history:  vec!(),
+                    player_history: History::new(),
+                    stage_history:  History::new(),
                     stage:          cli_results.stage_name.unwrap(),
                     state:          GameState::Local,
                     controllers,
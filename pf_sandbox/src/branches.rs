@@ -0,0 +1,214 @@
+use pf_sandbox_lib::history::History;
+use pf_sandbox_lib::input::ControllerInput;
+use pf_sandbox_lib::player::Player;
+use pf_sandbox_lib::simulation::SuddenDeath;
+use pf_sandbox_lib::stage::Stage;
+use crate::game::Game;
+use crate::input::Input;
+
+use treeflection::{Node, NodeRunner, NodeToken};
+
+/// A timeline that was replaced by resimulating from an earlier frame.
+/// It is kept so the effect of an edit can be compared against it or so it can be switched back to.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Branch {
+    pub frame:            usize, // the last frame shared with the timeline that replaced it
+    pub player_history:   History<Vec<Player>>, // unlike Game::player_history this includes the state of the final frame
+    pub stage_history:    History<Stage>,
+    pub checksum_history: Vec<Option<u64>>,
+    pub input_history:    Vec<Vec<ControllerInput>>,
+    pub sudden_deaths:    Vec<SuddenDeath>,
+}
+
+impl Branch {
+    /// Captures the entire timeline of the game, including any frames after the current frame
+    pub fn new(game: &Game, input: &Input) -> Branch {
+        let mut player_history = game.player_history.clone();
+        let mut stage_history = game.stage_history.clone();
+        if player_history.len() <= game.sim.current_frame {
+            player_history.push(&game.sim.players);
+            stage_history.push(&game.sim.stage);
+        }
+
+        Branch {
+            frame:            game.sim.current_frame,
+            player_history,
+            stage_history,
+            checksum_history: game.checksum_history.clone(),
+            input_history:    input.get_history(),
+            sudden_deaths:    game.sudden_deaths.clone(),
+        }
+    }
+
+    /// Returns the last frame in the branch
+    pub fn last_frame(&self) -> usize {
+        self.player_history.len() - 1
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum BranchRequest {
+    Switch (usize),
+    SetInput { frame: usize, controller: usize, field: String, value: f32 },
+}
+
+/// Branch commands that need the input history are queued here and handled by the game on its next step.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Branches {
+    pub branches: Vec<Branch>,
+    pub compare:  Option<usize>, // branch whose players are drawn over the current timeline
+    pub request:  Option<BranchRequest>,
+}
+
+impl Branches {
+    pub fn push(&mut self, branch: Branch) {
+        self.branches.push(branch);
+    }
+
+    /// Returns the players of the compared branch at the passed frame
+    pub fn compared_players(&self, frame: usize) -> Option<Vec<Player>> {
+        self.branches.get(self.compare?)?.player_history.get(frame)
+    }
+
+    fn list(&self) -> String {
+        if self.branches.is_empty() {
+            return String::from("There are no branches");
+        }
+
+        self.branches.iter().enumerate().map(|(i, branch)| {
+            let compare = if self.compare == Some(i) { " (comparing)" } else { "" };
+            format!("{}: diverged after frame {}, ends on frame {}{}", i, branch.frame, branch.last_frame(), compare)
+        }).collect::<Vec<_>>().join("\n")
+    }
+
+    fn delete(&mut self, index: usize) {
+        self.branches.remove(index);
+        self.compare = match self.compare {
+            Some(compare) if compare == index => None,
+            Some(compare) if compare > index  => Some(compare - 1),
+            compare                           => compare,
+        };
+    }
+}
+
+/// Sets a button or axis of the controller input by name, bool fields are set when the value is not 0
+pub fn set_input_field(input: &mut ControllerInput, field: &str, value: f32) {
+    let pressed = value != 0.0;
+    match field {
+        "plugged_in" => input.plugged_in = pressed,
+        "a"          => input.a          = pressed,
+        "b"          => input.b          = pressed,
+        "x"          => input.x          = pressed,
+        "y"          => input.y          = pressed,
+        "left"       => input.left       = pressed,
+        "right"      => input.right      = pressed,
+        "down"       => input.down       = pressed,
+        "up"         => input.up         = pressed,
+        "start"      => input.start      = pressed,
+        "z"          => input.z          = pressed,
+        "r"          => input.r          = pressed,
+        "l"          => input.l          = pressed,
+        "stick_x"    => input.stick_x    = value,
+        "stick_y"    => input.stick_y    = value,
+        "c_stick_x"  => input.c_stick_x  = value,
+        "c_stick_y"  => input.c_stick_y  = value,
+        "r_trigger"  => input.r_trigger  = value,
+        "l_trigger"  => input.l_trigger  = value,
+        _ => { }
+    }
+}
+
+const INPUT_FIELDS: [&str; 19] = [
+    "plugged_in", "a", "b", "x", "y", "left", "right", "down", "up", "start", "z", "r", "l",
+    "stick_x", "stick_y", "c_stick_x", "c_stick_y", "r_trigger", "l_trigger",
+];
+
+fn parse_index(args: &[String], i: usize, name: &str) -> Result<usize, String> {
+    match args.get(i).map(|x| x.parse::<usize>()) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(_))    => Err(format!("{} must be a number, was '{}'", name, args[i])),
+        None            => Err(format!("Didn't specify a {}", name.to_lowercase())),
+    }
+}
+
+impl Node for Branches {
+    fn node_step(&mut self, mut runner: NodeRunner) -> String {
+        match runner.step() {
+            NodeToken::Help => {
+                String::from(r#"
+Branches Help
+
+Resimulating from a rewound frame keeps the replaced timeline as a branch.
+
+Commands:
+*   help                                       - display this help
+*   list                                       - list the branches
+*   compare $index                             - draw the players of the branch over the current timeline
+*   compare off                                - stop drawing a branch
+*   switch $index                              - swap the current timeline with the branch
+*   delete $index                              - delete the branch
+*   set_input $frame $controller $field $value - change an input in the history, e.g. set_input 120 0 stick_x -1.0"#)
+            }
+            NodeToken::Custom (action, args) => {
+                match action.as_ref() {
+                    "list" => self.list(),
+                    "compare" if args.get(0).map(|x| x == "off").unwrap_or(false) => {
+                        self.compare = None;
+                        String::from("Stopped comparing branches")
+                    }
+                    "compare" | "switch" | "delete" => {
+                        let index = match parse_index(&args, 0, "Index") {
+                            Ok(index) => index,
+                            Err(err)  => return err,
+                        };
+                        if index >= self.branches.len() {
+                            return format!("There is no branch {}", index);
+                        }
+                        match action.as_ref() {
+                            "compare" => {
+                                self.compare = Some(index);
+                                format!("Comparing against branch {}", index)
+                            }
+                            "switch" => {
+                                self.request = Some(BranchRequest::Switch (index));
+                                format!("Switching to branch {}", index)
+                            }
+                            _ => {
+                                self.delete(index);
+                                format!("Deleted branch {}", index)
+                            }
+                        }
+                    }
+                    "set_input" => {
+                        let frame = match parse_index(&args, 0, "Frame") {
+                            Ok(frame) => frame,
+                            Err(err)  => return err,
+                        };
+                        let controller = match parse_index(&args, 1, "Controller") {
+                            Ok(controller) => controller,
+                            Err(err)       => return err,
+                        };
+                        let field = match args.get(2) {
+                            Some(field) if INPUT_FIELDS.contains(&field.as_ref()) => field.clone(),
+                            Some(field) => return format!("'{}' is not an input, must be one of: {}", field, INPUT_FIELDS.join(", ")),
+                            None        => return String::from("Didn't specify an input"),
+                        };
+                        let value = match args.get(3).map(|x| x.as_ref()) {
+                            Some("true")  => 1.0,
+                            Some("false") => 0.0,
+                            Some(value) => match value.parse::<f32>() {
+                                Ok(value) => value,
+                                Err(_)    => return format!("Value must be a number or true/false, was '{}'", value),
+                            }
+                            None => return String::from("Didn't specify a value"),
+                        };
+                        self.request = Some(BranchRequest::SetInput { frame, controller, field, value });
+                        format!("Setting {} of controller {} on frame {}", args[2], controller, frame)
+                    }
+                    _ => format!("Branches cannot '{}'", action)
+                }
+            }
+            action => format!("Branches cannot '{:?}'", action)
+        }
+    }
+}
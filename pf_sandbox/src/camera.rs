@@ -1,6 +1,6 @@
 use pf_sandbox_lib::fighter::Fighter;
+use pf_sandbox_lib::player::Player;
 use pf_sandbox_lib::stage::Stage;
-use crate::player::Player;
 
 use winit::event::VirtualKeyCode;
 use winit_input_helper::Camera as CameraWinitInputHelper;
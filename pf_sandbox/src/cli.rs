@@ -1,8 +1,12 @@
+use pf_sandbox_lib::package::Package;
 use pf_sandbox_lib::package;
+use pf_sandbox_lib::player::Handicap;
+use crate::replays;
 
 use getopts::Options;
 use std::env;
 use std::net::IpAddr;
+use std::process;
 
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} [options] [package_dir]\nIf no arguments are given the GUI menu is used instead. (excluding -g)", program);
@@ -15,10 +19,14 @@ pub fn cli() -> CLIResults {
 
     let mut opts = Options::new();
     opts.optflag("l", "list", "List available packages and close");
+    opts.optopt("A", "analyze",        "Print a legality report for every stage in the package and close", "PACKAGE");
+    opts.optflag("S", "save",          "Used with --analyze, save whether each stage is legal to the package");
+    opts.optopt("V", "verify-replays", "Resimulate every replay of the package, print the first frame each replay diverges from the checksums and cache it was recorded with and close. Exits with 1 if a replay diverged, 2 if a replay failed to load, 3 if a replay has no checksums or cache or there are no replays", "PACKAGE");
     opts.optopt("s", "stage",          "Use the stage specified", "NAME");
     opts.optopt("f", "fighters",       "Use the fighters specified", "NAME1,NAME2,NAME3...");
     opts.optopt("h", "humanplayers",   "Number of human players in the game", "NUM_HUMAN_PLAYERS");
     opts.optopt("c", "cpuplayers",     "Number of CPU players in the game", "NUM_CPU_PLAYERS");
+    opts.optopt("H", "handicaps",      "Handicap of each player in order, unlisted players have no handicap", "DAMAGE:TAKEN:DEALT:KNOCKBACK:STOCKS,...");
     opts.optopt("a", "address",        "IP Address of other client to start netplay with", "IP_ADDRESS");
     opts.optopt("n", "netplayplayers", "Search for a netplay game with the specified number of players", "NUM_PLAYERS");
     opts.optopt("r", "netplayregion",  "Search for a netplay game with the specified region", "REGION");
@@ -47,6 +55,26 @@ pub fn cli() -> CLIResults {
         return results;
     }
 
+    if let Some(package) = matches.opt_str("A") {
+        package::print_stage_analysis(&package, matches.opt_present("S"));
+        results.continue_from = ContinueFrom::Close;
+        return results;
+    }
+
+    if let Some(package) = matches.opt_str("V") {
+        match Package::open(&package) {
+            Some(package) => {
+                let verification = replays::verify_replays(&package);
+                println!("{}", verification.report());
+                process::exit(verification.exit_code());
+            }
+            None => {
+                println!("Could not load package: {}", package);
+                process::exit(2);
+            }
+        }
+    }
+
     if matches.free.len() > 1 {
         print_usage(program, opts);
         results.continue_from = ContinueFrom::Close;
@@ -88,6 +116,20 @@ pub fn cli() -> CLIResults {
         }
     }
 
+    if let Some(handicaps) = matches.opt_str("H") {
+        for handicap in handicaps.split(",") {
+            if let Some(handicap) = parse_handicap(handicap) {
+                results.continue_from = ContinueFrom::Game;
+                results.handicaps.push(handicap);
+            }
+            else {
+                print_usage(program, opts);
+                results.continue_from = ContinueFrom::Close;
+                return results;
+            }
+        }
+    }
+
     if let Some(stage) = matches.opt_str("s") {
         results.stage_name = Some(stage);
         results.continue_from = ContinueFrom::Game;
@@ -137,12 +179,28 @@ pub fn cli() -> CLIResults {
     results
 }
 
+/// Parses a handicap of the form DAMAGE:TAKEN:DEALT:KNOCKBACK:STOCKS
+fn parse_handicap(text: &str) -> Option<Handicap> {
+    let values: Vec<&str> = text.split(":").collect();
+    if values.len() != 5 {
+        return None;
+    }
+    Some(Handicap {
+        starting_damage:       values[0].parse().ok()?,
+        damage_taken_ratio:    values[1].parse().ok()?,
+        damage_dealt_ratio:    values[2].parse().ok()?,
+        knockback_taken_ratio: values[3].parse().ok()?,
+        extra_stocks:          values[4].parse().ok()?,
+    })
+}
+
 pub struct CLIResults {
     pub graphics_backend:  GraphicsBackendChoice,
     pub package:           Option<String>,
     pub max_human_players: Option<usize>,
     pub total_cpu_players: Option<usize>,
     pub fighter_names:     Vec<String>,
+    pub handicaps:         Vec<Handicap>,
     pub stage_name:        Option<String>,
     pub address:           Option<IpAddr>,
     pub continue_from:     ContinueFrom,
@@ -158,6 +216,7 @@ impl CLIResults {
             max_human_players: None,
             total_cpu_players: None,
             fighter_names:     vec!(),
+            handicaps:         vec!(),
             stage_name:        None,
             address:           None,
             continue_from:     ContinueFrom::Menu,
@@ -0,0 +1,116 @@
+use pf_sandbox_lib::player::{DebugPlayer, RenderFighter};
+use pf_sandbox_lib::stage::DebugStage;
+
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Toggles debug displays from the keyboard.
+/// Lives here rather than in pf_sandbox_lib so that the simulation does not depend on winit.
+pub trait DebugStep {
+    fn step(&mut self, os_input: &WinitInputHelper<()>);
+}
+
+impl DebugStep for DebugPlayer {
+    fn step(&mut self, os_input: &WinitInputHelper<()>) {
+        if os_input.key_pressed(VirtualKeyCode::F1) {
+            self.physics = !self.physics;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F2) {
+            if os_input.held_shift() {
+                self.input_diff = !self.input_diff;
+            }
+            else {
+                self.input = !self.input;
+            }
+        }
+        if os_input.key_pressed(VirtualKeyCode::F3) {
+            self.action = !self.action;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F4) {
+            self.frame = !self.frame;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F5) {
+            self.stick_vector = !self.stick_vector;
+            self.c_stick_vector = !self.c_stick_vector;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F6) {
+            self.di_vector = !self.di_vector;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F7) {
+            self.hitbox_vectors = !self.hitbox_vectors;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F8) {
+            self.ecb = !self.ecb;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F9) {
+            self.fighter = match self.fighter {
+                RenderFighter::Normal => {
+                    RenderFighter::OnionSkin
+                }
+                RenderFighter::OnionSkin=> {
+                    RenderFighter::Debug
+                }
+                RenderFighter::Debug => {
+                    RenderFighter::None
+                }
+                RenderFighter::None => {
+                    RenderFighter::Normal
+                }
+            };
+        }
+        if os_input.key_pressed(VirtualKeyCode::F10) {
+            self.cam_area = !self.cam_area;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F11) {
+            *self = DebugPlayer {
+                physics:        true,
+                input:          true,
+                input_diff:     true,
+                action:         true,
+                frame:          true,
+                stick_vector:   true,
+                c_stick_vector: true,
+                di_vector:      true,
+                hitbox_vectors: true,
+                ecb:            true,
+                fighter:        RenderFighter::Debug,
+                cam_area:       true,
+            }
+        }
+        if os_input.key_pressed(VirtualKeyCode::F12) {
+            *self = DebugPlayer::default();
+        }
+    }
+}
+
+impl DebugStep for DebugStage {
+    fn step(&mut self, os_input: &WinitInputHelper<()>) {
+        if os_input.key_pressed(VirtualKeyCode::F1) {
+            self.blast = !self.blast;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F2) {
+            self.camera = !self.camera;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F3) {
+            self.spawn_points = !self.spawn_points;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F4) {
+            self.respawn_points = !self.respawn_points;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F5) {
+            self.hazards = !self.hazards;
+        }
+        if os_input.key_pressed(VirtualKeyCode::F11) {
+            *self = DebugStage {
+                blast:          true,
+                camera:         true,
+                spawn_points:   true,
+                respawn_points: true,
+                hazards:        true,
+            }
+        }
+        if os_input.key_pressed(VirtualKeyCode::F12) {
+            *self = DebugStage::default();
+        }
+    }
+}
@@ -1,58 +1,59 @@
+use crate::branches::{Branch, Branches, BranchRequest};
+use crate::branches;
 use crate::camera::Camera;
-use crate::collision::collision_check;
+use crate::command_line::CommandLine;
+use crate::debug::DebugStep;
 use crate::graphics::{GraphicsMessage, Render, RenderType};
 use crate::input::Input;
 use crate::menu::ResumeMenu;
-use crate::player::{Player, RenderPlayer, DebugPlayer, StepContext};
 use crate::replays::Replay;
 use crate::replays;
-use crate::results::{GameResults, RawPlayerResult, PlayerResult};
+use crate::results::GameResults;
+use crate::savestates::{Savestate, Savestates, SavestateRequest};
+use crate::savestates;
 
-use pf_sandbox_lib::command_line::CommandLine;
 use pf_sandbox_lib::config::Config;
-use pf_sandbox_lib::fighter::{ActionFrame, CollisionBox, LinkType, Action};
+use pf_sandbox_lib::fighter::{ActionFrame, CollisionBox, LinkType, Action, HitBox};
 use pf_sandbox_lib::geometry::Rect;
+use pf_sandbox_lib::history::History;
 use pf_sandbox_lib::input::{PlayerInput, ControllerInput};
 use pf_sandbox_lib::network::Netplay;
 use pf_sandbox_lib::package::Package;
-use pf_sandbox_lib::rules::Goal;
-use pf_sandbox_lib::stage::{Stage, DebugStage, SpawnPoint, Surface, Floor};
+use pf_sandbox_lib::player::{Player, RenderPlayer, DebugPlayer};
+use pf_sandbox_lib::rules::Pause;
+use pf_sandbox_lib::simulation::{Simulation, SimulationSetup, SimulationState, PlayerSetup, SuddenDeath};
+use pf_sandbox_lib::stage::{Stage, DebugStage, SpawnPoint, Surface, Floor, Hazard};
 
-use rand_chacha::rand_core::SeedableRng;
-use rand_chacha::ChaChaRng;
-use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::fmt;
+use std::mem;
 use std::time::Duration;
 use chrono::Local;
-use num_traits::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
 
 use treeflection::{Node, NodeRunner, NodeToken};
 use winit::event::VirtualKeyCode;
 use winit_input_helper::WinitInputHelper;
-use byteorder::{LittleEndian, WriteBytesExt};
 
 #[NodeActions(
     NodeAction(function="save_replay", return_string),
     NodeAction(function="reset_deadzones", return_string),
     NodeAction(function="copy_stage_to_package", return_string),
     NodeAction(function="copy_package_to_stage", return_string),
+    NodeAction(function="checksum", return_string),
 )]
 #[derive(Clone, Default, Serialize, Deserialize, Node)]
 pub struct Game {
-    pub package:                Package,
+    pub sim:                    Simulation,
     pub config:                 Config,
-    pub init_seed:              u64,
     pub state:                  GameState,
-    pub player_history:         Vec<Vec<Player>>,
-    pub stage_history:          Vec<Stage>,
-    pub current_frame:          usize,
+    pub player_history:         History<Vec<Player>>,
+    pub stage_history:          History<Stage>,
+    pub checksum_history:       Vec<Option<u64>>, // checksum of the state after each frame was simulated, index f - 1 holds frame f. None if config.store_checksums was disabled
+    pub sudden_deaths:          Vec<SuddenDeath>, // every sudden death in the timeline, including those that start after the current frame
     pub saved_frame:            usize,
-    pub stage:                  Stage,
-    pub players:                Vec<Player>,
     pub debug_stage:            DebugStage,
     pub debug_players:          Vec<DebugPlayer>,
-    pub selected_controllers:   Vec<usize>,
     pub selected_ais:           Vec<usize>,
     pub selected_stage:         String,
     pub edit:                   Edit,
@@ -62,46 +63,49 @@ pub struct Game {
     copied_frame:               Option<ActionFrame>,
     pub camera:                 Camera,
     pub tas:                    Vec<ControllerInput>,
+    pub savestates:             Savestates,
+    pub branches:               Branches,
+    pending_results:            Option<GameState>, // results of a netplay game that finished on a frame simulated with predicted inputs
+    pending_results_frame:      usize,
     save_replay:                bool,
     reset_deadzones:            bool,
+    pause_hold_frames:          u64,
 }
 
-/// Frame 0 refers to the initial state of the game.
-/// Any changes occur in the proceeding frames i.e. frames 1, 2, 3 ...
+const SAVESTATE_KEYS: [VirtualKeyCode; 9] = [
+    VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4, VirtualKeyCode::Key5, VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+];
 
 /// All previous frame state is used to calculate the next frame, then the current_frame is incremented.
 
 impl Game {
     pub fn new(package: Package, config: Config, setup: GameSetup) -> Game {
-        let stage = package.stages[setup.stage.as_ref()].clone();
-
-        // generate players
-        let mut players:       Vec<Player>      = vec!();
-        let mut debug_players: Vec<DebugPlayer> = vec!();
-        {
-            for (i, player) in setup.players.iter().enumerate() {
-                // Stage can have less spawn points then players
-                let fighter = player.fighter.clone();
-                let team = player.team;
-                players.push(Player::new(fighter, team, i, &stage, &package));
-                debug_players.push(Default::default());
-            }
-        }
+        let debug_players = setup.players.iter().map(|_| Default::default()).collect();
+        let max_bytes = config.history_max_mb.map(|x| x * 1024 * 1024);
+        let mut player_history = setup.player_history;
+        let mut stage_history = setup.stage_history;
+        player_history.set_max_bytes(max_bytes);
+        stage_history.set_max_bytes(max_bytes);
+        let sim = Simulation::new(package, SimulationSetup {
+            init_seed:   setup.init_seed,
+            stage:       setup.stage.clone(),
+            players:     setup.players,
+            controllers: setup.controllers,
+        });
 
         Game {
-            package:                package,
+            sim:                    sim,
             config:                 config,
-            init_seed:              setup.init_seed,
             state:                  setup.state,
-            player_history:         setup.player_history,
-            stage_history:          setup.stage_history,
-            current_frame:          0,
+            player_history:         player_history,
+            stage_history:          stage_history,
+            checksum_history:       vec!(),
+            sudden_deaths:          vec!(),
             saved_frame:            0,
-            stage:                  stage,
-            players:                players,
             debug_stage:            Default::default(),
             debug_players:          debug_players,
-            selected_controllers:   setup.controllers,
             selected_ais:           setup.ais,
             selected_stage:         setup.stage,
             edit:                   Edit::Stage,
@@ -111,8 +115,13 @@ impl Game {
             copied_frame:           None,
             camera:                 Camera::new(),
             tas:                    vec!(),
+            savestates:             Savestates::default(),
+            branches:               Branches::default(),
+            pending_results:        None,
+            pending_results_frame:  0,
             save_replay:            false,
             reset_deadzones:        false,
+            pause_hold_frames:      0,
         }
     }
 
@@ -123,10 +132,18 @@ impl Game {
         }
 
         if self.save_replay {
-            replays::save_replay(&Replay::new(self, input), &self.package);
+            replays::save_replay(&Replay::new(self, input), &self.sim.package);
             self.save_replay = false;
         }
 
+        if let Some(request) = self.savestates.request.take() {
+            self.handle_savestate_request(request, input);
+        }
+
+        if let Some(request) = self.branches.request.take() {
+            self.handle_branch_request(request, input);
+        }
+
         {
             let state = self.state.clone();
             match state {
@@ -134,6 +151,7 @@ impl Game {
                 GameState::Netplay               => { self.step_netplay(input, netplay); }
                 GameState::ReplayForwards        => { self.step_replay_forwards(input, netplay); }
                 GameState::ReplayBackwards       => { self.step_replay_backwards(input); }
+                GameState::ResimulateForwards    => { self.step_replay_forwards_from_input(input, netplay); }
                 GameState::StepThenPause         => { self.step_local(input, netplay); self.state = GameState::Paused; }
                 GameState::StepForwardThenPause  => { self.step_replay_forwards(input, netplay); self.state = GameState::Paused; }
                 GameState::StepBackwardThenPause => { self.step_replay_backwards(input); self.state = GameState::Paused; }
@@ -143,25 +161,26 @@ impl Game {
 
             if !os_input_blocked {
                 match state {
-                    GameState::Local           => { self.step_local_os_input(os_input); }
-                    GameState::ReplayForwards  => { self.step_replay_forwards_os_input(os_input); }
-                    GameState::ReplayBackwards => { self.step_replay_backwards_os_input(os_input); }
-                    GameState::Paused          => { self.step_pause_os_input(input, os_input, netplay); }
-                    GameState::Quit (_)        => { unreachable!(); }
+                    GameState::Local              => { self.step_local_os_input(os_input); }
+                    GameState::ReplayForwards     => { self.step_replay_forwards_os_input(os_input); }
+                    GameState::ResimulateForwards => { self.step_replay_forwards_os_input(os_input); }
+                    GameState::ReplayBackwards    => { self.step_replay_backwards_os_input(os_input); }
+                    GameState::Paused             => { self.step_pause_os_input(input, os_input, netplay); }
+                    GameState::Quit (_)           => { unreachable!(); }
 
                     GameState::Netplay              | GameState::StepThenPause |
                     GameState::StepForwardThenPause | GameState::StepBackwardThenPause => { }
                 }
                 self.camera.update_os_input(os_input);
             }
-            self.camera.update(os_input, &self.players, &self.package.fighters, &self.stage);
+            self.camera.update(os_input, &self.sim.players, &self.sim.package.fighters, &self.sim.stage);
 
             self.generate_debug(input, netplay);
         }
 
         self.set_context();
 
-        debug!("current_frame: {}", self.current_frame);
+        debug!("current_frame: {}", self.sim.current_frame);
         self.state.clone()
     }
 
@@ -176,15 +195,159 @@ impl Game {
     }
 
     pub fn copy_stage_to_package(&mut self) -> String {
-        self.package.stages[self.selected_stage.as_ref()] = self.stage.clone();
+        self.sim.package.stages[self.selected_stage.as_ref()] = self.sim.stage.clone();
         String::from("Current stage state copied to package")
     }
 
     pub fn copy_package_to_stage(&mut self) -> String {
-        self.stage = self.package.stages[self.selected_stage.as_ref()].clone();
+        self.sim.stage = self.sim.package.stages[self.selected_stage.as_ref()].clone();
         String::from("Package copied to current stage state")
     }
 
+    pub fn checksum(&mut self) -> String {
+        format!("Frame {} checksum: {:016x}", self.sim.current_frame, self.sim.checksum())
+    }
+
+    fn handle_savestate_request(&mut self, request: SavestateRequest, input: &mut Input) {
+        if let GameState::Netplay = self.state {
+            warn!("Savestates cannot be used during netplay");
+            return;
+        }
+
+        match request {
+            SavestateRequest::Save (slot) => {
+                savestates::save_savestate(&Savestate::new(self, input), slot, &self.sim.package);
+            }
+            SavestateRequest::Load (slot) => {
+                match savestates::load_savestate(slot, &self.sim.package) {
+                    Ok(savestate) => {
+                        if let Err(err) = self.load_savestate(savestate, input) {
+                            warn!("Failed to load savestate slot {}: {}", slot, err);
+                        }
+                    }
+                    Err(err) => {
+                        warn!("Failed to load savestate slot {}: {}", slot, err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Restores the game to the moment the savestate was taken and pauses it.
+    fn load_savestate(&mut self, savestate: Savestate, input: &mut Input) -> Result<(), String> {
+        if let Some(player) = savestate.players.iter().find(|x| !self.sim.package.fighters.contains_key(&x.fighter)) {
+            return Err(format!("The fighter '{}' is not in the package", player.fighter));
+        }
+        if let Some(controller) = savestate.controllers.iter().find(|x| **x >= input.controllers_len()) {
+            return Err(format!("Controller {} is not plugged in", controller));
+        }
+
+        self.sim.init_seed     = savestate.init_seed;
+        self.sim.current_frame = savestate.current_frame;
+        self.sim.stage         = savestate.stage;
+        self.sim.players       = savestate.players;
+        self.sim.controllers   = savestate.controllers;
+        self.sudden_deaths     = savestate.sudden_deaths;
+        self.sim.sudden_deaths = self.sudden_deaths_until(self.sim.current_frame);
+        self.sim.stage.rebuild_surface_grid();
+        self.player_history   = savestate.player_history;
+        self.stage_history    = savestate.stage_history;
+        self.checksum_history = savestate.checksum_history;
+        self.selected_ais     = savestate.selected_ais;
+        self.selected_stage   = savestate.selected_stage;
+        self.debug_players.resize(self.sim.players.len(), Default::default());
+        input.set_history(savestate.input_history);
+
+        if let Edit::Fighter (player) | Edit::Player (player) = self.edit {
+            if player >= self.sim.players.len() {
+                self.edit = Edit::Stage;
+            }
+        }
+        self.state = GameState::Paused;
+        self.update_frame();
+        Ok(())
+    }
+
+    fn handle_branch_request(&mut self, request: BranchRequest, input: &mut Input) {
+        if let GameState::Netplay = self.state {
+            warn!("Branches cannot be used during netplay");
+            return;
+        }
+
+        match request {
+            BranchRequest::Switch (index) => {
+                if index < self.branches.branches.len() {
+                    let current = Branch::new(self, input);
+                    let branch = std::mem::replace(&mut self.branches.branches[index], current);
+                    self.load_branch(branch, input);
+                }
+            }
+            BranchRequest::SetInput { frame, controller, field, value } => {
+                // the input used to simulate a frame is stored at the index before it
+                let mut history = input.get_history();
+                match history.get_mut(frame.wrapping_sub(1)).and_then(|x| x.get_mut(controller)) {
+                    Some(controller_input) => {
+                        branches::set_input_field(controller_input, &field, value);
+                        input.set_history(history);
+                    }
+                    None => {
+                        warn!("There is no input for controller {} on frame {}", controller, frame);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the current timeline with the branch, staying on the current frame if the branch reaches it.
+    fn load_branch(&mut self, branch: Branch, input: &mut Input) {
+        let frame = self.sim.current_frame.min(branch.last_frame()).max(branch.player_history.first_frame());
+        self.sudden_deaths    = branch.sudden_deaths;
+        self.player_history   = branch.player_history;
+        self.stage_history    = branch.stage_history;
+        self.checksum_history = branch.checksum_history;
+        input.set_history(branch.input_history);
+
+        self.load_history_frame(frame);
+        self.state = GameState::Paused;
+    }
+
+    /// Keeps the current timeline as a branch if frames after the current frame are about to be overwritten.
+    fn branch_future(&mut self, input: &Input) {
+        if self.player_history.len() > self.sim.current_frame + 1 {
+            let branch = Branch::new(self, input);
+            self.branches.push(branch);
+        }
+    }
+
+    /// Moves the game to a frame stored in history, returns false if the frame is not stored.
+    fn load_history_frame(&mut self, frame: usize) -> bool {
+        match (self.player_history.get(frame), self.stage_history.get(frame)) {
+            (Some(players), Some(stage)) => {
+                self.sim.players       = players;
+                self.sim.stage         = stage;
+                self.sim.current_frame = frame;
+                self.sim.stage.rebuild_surface_grid();
+                self.sim.sudden_deaths = self.sudden_deaths_until(frame);
+                self.update_frame();
+                true
+            }
+            _ => false
+        }
+    }
+
+    /// Returns the sudden deaths in the timeline that have started by the frame
+    pub fn sudden_deaths_until(&self, frame: usize) -> Vec<SuddenDeath> {
+        self.sudden_deaths.iter().filter(|x| x.start_frame <= frame).cloned().collect()
+    }
+
+    /// Stores the state of the current frame in history so it is kept when moving to an earlier frame.
+    fn store_current_frame(&mut self) {
+        if self.player_history.len() == self.sim.current_frame {
+            self.player_history.push(&self.sim.players);
+            self.stage_history.push(&self.sim.stage);
+        }
+    }
+
     pub fn check_reset_deadzones(&mut self) -> bool {
         let value = self.reset_deadzones;
         self.reset_deadzones = false;
@@ -194,12 +357,12 @@ impl Game {
     fn set_context(&mut self) {
         match self.edit {
             Edit::Fighter (player) => {
-                let player_fighter  = self.players[player].fighter.as_ref();
-                let player_action   = self.players[player].action as usize;
-                let player_frame    = self.players[player].frame as usize;
+                let player_fighter  = self.sim.players[player].fighter.as_ref();
+                let player_action   = self.sim.players[player].action as usize;
+                let player_frame    = self.sim.players[player].frame as usize;
                 let player_colboxes = self.selector.colboxes_vec();
 
-                let fighters = &mut self.package.fighters;
+                let fighters = &mut self.sim.package.fighters;
                 if let Some(fighter_index) = fighters.key_to_index(player_fighter) {
                     fighters.set_context(fighter_index);
                 }
@@ -223,71 +386,127 @@ impl Game {
                 colboxes.set_context_vec(player_colboxes);
             }
             Edit::Stage => {
-                self.stage.surfaces.set_context_vec(self.selector.surfaces_vec());
-                self.stage.spawn_points.set_context_vec(self.selector.spawn_points.iter().cloned().collect());
-                self.stage.respawn_points.set_context_vec(self.selector.respawn_points.iter().cloned().collect());
+                self.sim.stage.surfaces.set_context_vec(self.selector.surfaces_vec());
+                self.sim.stage.spawn_points.set_context_vec(self.selector.spawn_points.iter().cloned().collect());
+                self.sim.stage.respawn_points.set_context_vec(self.selector.respawn_points.iter().cloned().collect());
+                self.sim.stage.hazards.set_context_vec(self.selector.hazards.iter().cloned().collect());
             }
             _ => { }
         }
     }
 
     fn step_local(&mut self, input: &mut Input, netplay: &Netplay) {
-        self.player_history.push(self.players.clone());
-        self.stage_history.push(self.stage.clone());
-        self.current_frame += 1;
+        // erase any future history, keeping it as a branch
+        self.branch_future(input);
+        self.player_history.truncate(self.sim.current_frame);
+        self.stage_history.truncate(self.sim.current_frame);
+        self.sim.rewind_sudden_deaths(self.sim.current_frame);
 
-        // erase any future history
-        for _ in self.current_frame..self.player_history.len() {
-            self.player_history.pop();
-        }
-        for _ in self.current_frame..self.stage_history.len() {
-            self.stage_history.pop();
-        }
+        self.player_history.push(&self.sim.players);
+        self.stage_history.push(&self.sim.stage);
+        self.sim.current_frame += 1;
 
         // run game loop
-        input.game_update(self.current_frame);
-        let player_inputs = &input.players(self.current_frame, netplay);
+        input.game_update(self.sim.current_frame);
+        let player_inputs = &input.players(self.sim.current_frame, netplay);
         self.step_game(input, player_inputs);
 
         // pause game
-        if input.start_pressed() {
+        if self.pause_requested(input) {
             self.state = GameState::Paused;
         }
     }
 
+    /// Returns true if the controllers are requesting a pause according to the pause rule.
+    /// Keyboard pausing is for debugging and editing, so it is left unaffected by the rule.
+    /// Only applies to local play, replays are always paused by pressing start.
+    fn pause_requested(&mut self, input: &mut Input) -> bool {
+        match self.sim.package.rules.pause {
+            Pause::On => input.start_pressed(),
+            Pause::Off => false,
+            Pause::Hold { frames } => {
+                if input.start_held() {
+                    self.pause_hold_frames += 1;
+                } else {
+                    self.pause_hold_frames = 0;
+                }
+
+                if self.pause_hold_frames >= frames.max(1) {
+                    self.pause_hold_frames = 0;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
     fn step_local_os_input(&mut self, os_input: &WinitInputHelper<()>) {
         if os_input.key_pressed(VirtualKeyCode::Space) || os_input.key_pressed(VirtualKeyCode::Return) {
             self.state = GameState::Paused;
         }
     }
 
+    /// Pausing is refused during netplay regardless of the pause rule, as the peers would need to agree on the frame to pause on.
+    /// When netplay finds a remote input was mispredicted, the game is rolled back to the frame before it and resimulated from player_history/stage_history.
+    /// Particles and the camera are visual only, so the resimulation leaves them as they were.
     fn step_netplay(&mut self, input: &mut Input, netplay: &Netplay) {
         if !netplay.skip_frame() {
-            self.current_frame += 1;
-
-            let start = self.current_frame - netplay.frames_to_step();
-            let end = self.current_frame;
+            let end = self.sim.current_frame + 1;
+            let start = end + 1 - netplay.frames_to_step().min(end);
+
+            let mut particles = None;
+            if start < end {
+                particles = Some(self.sim.players.iter().map(|x| x.particles.clone()).collect::<Vec<_>>());
+                let players = self.player_history.get(start-1).unwrap();
+                let stage = self.stage_history.get(start-1).unwrap();
+                self.sim.rewind(start-1, players, stage);
+            }
+            self.player_history.truncate(start-1);
+            self.stage_history.truncate(start-1);
 
-            self.player_history.truncate(start);
-            self.stage_history.truncate(start);
-            if start != 0 {
-                self.players = self.player_history.get(start-1).unwrap().clone();
-                self.stage   = self.stage_history.get(start-1).unwrap().clone();
+            // the frame the game finished on is being resimulated, so it may no longer finish there
+            if self.pending_results.is_some() && start <= self.pending_results_frame {
+                self.pending_results = None;
             }
 
-            input.netplay_update();
+            input.netplay_update(end, netplay);
+
+            for frame in start..=end {
+                if frame == end {
+                    if let Some(particles) = particles.take() {
+                        for (player, particles) in self.sim.players.iter_mut().zip(particles) {
+                            player.particles = particles;
+                        }
+                    }
+                }
+
+                self.player_history.push(&self.sim.players);
+                self.stage_history.push(&self.sim.stage);
+                self.sim.current_frame = frame;
 
-            for frame in start..end {
                 let player_inputs = &input.players(frame, netplay);
                 self.step_game(input, player_inputs);
+                if let GameState::Quit (_) = self.state {
+                    // Keep simulating until the inputs the game finished with are confirmed, as a mispredicted input may have finished it.
+                    let state = mem::replace(&mut self.state, GameState::Netplay);
+                    if self.pending_results.is_none() {
+                        self.pending_results = Some(state);
+                        self.pending_results_frame = frame;
+                    }
+                }
+            }
 
-                self.player_history.push(self.players.clone());
-                self.stage_history.push(self.stage.clone());
+            if self.pending_results.is_some() && self.pending_results_frame <= input.netplay_confirmed_frame(netplay) {
+                self.state = self.pending_results.take().unwrap();
             }
         }
     }
 
     fn step_pause(&mut self, input: &mut Input) {
+        self.pause_hold_frames = 0;
+        self.sim.stage.rebuild_surface_grid(); // the stage may have been modified through the command line
+
         if input.game_quit_held() {
             self.state = GameState::Quit (ResumeMenu::Unchanged);
         }
@@ -297,10 +516,22 @@ impl Game {
     }
 
     fn step_pause_os_input(&mut self, input: &mut Input, os_input: &WinitInputHelper<()>, netplay: &Netplay) {
-        let players_len = self.players.len();
-
+        let players_len = self.sim.players.len();
+
+        // ctrl + number saves a savestate slot, alt + number loads it
+        if os_input.held_control() || os_input.held_alt() {
+            for (i, key) in SAVESTATE_KEYS.iter().enumerate() {
+                if os_input.key_pressed(*key) {
+                    self.savestates.request = Some(if os_input.held_control() {
+                        SavestateRequest::Save (i + 1)
+                    } else {
+                        SavestateRequest::Load (i + 1)
+                    });
+                }
+            }
+        }
         // set current edit state
-        if os_input.key_pressed(VirtualKeyCode::Key0) {
+        else if os_input.key_pressed(VirtualKeyCode::Key0) {
             self.edit = Edit::Stage;
         }
         else if os_input.key_pressed(VirtualKeyCode::Key1) && players_len >= 1 {
@@ -341,15 +572,22 @@ impl Game {
         }
 
         // game flow control
+        // holding shift resimulates from the input history instead of replaying the stored frames
         if os_input.key_pressed(VirtualKeyCode::J) {
             self.step_replay_backwards(input);
         }
+        else if os_input.key_pressed(VirtualKeyCode::K) && os_input.held_shift() {
+            self.step_replay_forwards_from_input(input, netplay);
+        }
         else if os_input.key_pressed(VirtualKeyCode::K) {
             self.step_replay_forwards(input, netplay);
         }
         else if os_input.key_pressed(VirtualKeyCode::H) {
             self.state = GameState::ReplayBackwards;
         }
+        else if os_input.key_pressed(VirtualKeyCode::L) && os_input.held_shift() {
+            self.state = GameState::ResimulateForwards;
+        }
         else if os_input.key_pressed(VirtualKeyCode::L) {
             self.state = GameState::ReplayForwards;
         }
@@ -357,10 +595,10 @@ impl Game {
             self.step_local(input, netplay);
         }
         else if os_input.key_pressed(VirtualKeyCode::U) {
-            self.saved_frame = self.current_frame;
+            self.saved_frame = self.sim.current_frame;
         }
         else if os_input.key_pressed(VirtualKeyCode::I) {
-            //self.jump_frame(); // TODO: Fix
+            self.jump_frame();
         }
         else if os_input.key_pressed(VirtualKeyCode::Return) {
             self.state = GameState::Local;
@@ -368,12 +606,12 @@ impl Game {
 
         match self.edit {
             Edit::Fighter (player) => {
-                let fighter_string = self.players[player].fighter.clone();
+                let fighter_string = self.sim.players[player].fighter.clone();
                 let fighter = fighter_string.as_ref();
-                let action = self.players[player].action as usize;
-                let action_enum = Action::from_u64(self.players[player].action);
-                let frame  = self.players[player].frame as usize;
-                let land_frame_skip  = self.players[player].land_frame_skip;
+                let action = self.sim.players[player].action as usize;
+                let action_enum = Action::from_u64(self.sim.players[player].action);
+                let frame  = self.sim.players[player].frame as usize;
+                let land_frame_skip  = self.sim.players[player].land_frame_skip;
                 self.debug_players[player].step(os_input);
 
                 // by adding the same amount of frames that are skipped in the player logic,
@@ -388,11 +626,11 @@ impl Game {
                 if self.selector.moving {
                     // undo the operations used to render the player
                     let (raw_d_x, raw_d_y) = os_input.game_mouse_diff(self.camera.for_winit_helper());
-                    let angle = -self.players[player].angle(&self.package.fighters[fighter], &self.stage.surfaces); // rotate by the inverse of the angle
+                    let angle = -self.sim.players[player].angle(&self.sim.package.fighters[fighter], &self.sim.stage.surfaces); // rotate by the inverse of the angle
                     let d_x = raw_d_x * angle.cos() - raw_d_y * angle.sin();
                     let d_y = raw_d_x * angle.sin() + raw_d_y * angle.cos();
-                    let distance = (self.players[player].relative_f(d_x), d_y); // *= -1 is its own inverse
-                    self.package.move_fighter_colboxes(fighter, action, frame, &self.selector.colboxes, distance);
+                    let distance = (self.sim.players[player].relative_f(d_x), d_y); // *= -1 is its own inverse
+                    self.sim.package.move_fighter_colboxes(fighter, action, frame, &self.selector.colboxes, distance);
 
                     // end move
                     if os_input.mouse_pressed(0) {
@@ -402,22 +640,22 @@ impl Game {
                 else {
                     // copy frame
                     if os_input.key_pressed(VirtualKeyCode::V) {
-                        let frame = self.package.fighters[fighter].actions[action].frames[frame].clone();
+                        let frame = self.sim.package.fighters[fighter].actions[action].frames[frame].clone();
                         self.copied_frame = Some(frame);
                     }
                     // paste over current frame
                     if os_input.key_pressed(VirtualKeyCode::B) {
                         let action_frame = self.copied_frame.clone();
                         if let Some(action_frame) = action_frame {
-                            self.package.insert_fighter_frame(fighter, action, frame, action_frame);
-                            self.package.delete_fighter_frame(fighter, action, frame+1);
+                            self.sim.package.insert_fighter_frame(fighter, action, frame, action_frame);
+                            self.sim.package.delete_fighter_frame(fighter, action, frame+1);
                         }
                     }
 
                     // new frame
                     if os_input.key_pressed(VirtualKeyCode::M) {
                         for i in 0..repeat_frames {
-                            self.package.new_fighter_frame(fighter, action, frame + i as usize);
+                            self.sim.package.new_fighter_frame(fighter, action, frame + i as usize);
                         }
                         // We want to step just the players current frame to simplify the animation work flow
                         // However we need to do a proper full step so that the history doesn't get mucked up.
@@ -426,13 +664,13 @@ impl Game {
                     // delete frame
                     if os_input.key_pressed(VirtualKeyCode::N) {
                         let i = 0; //for i in 0..repeat_frames { // TODO: Panic
-                            if self.package.delete_fighter_frame(fighter, action, frame - i as usize) {
+                            if self.sim.package.delete_fighter_frame(fighter, action, frame - i as usize) {
                                 // Correct any players that are now on a nonexistent frame due to the frame deletion.
                                 // This is purely to stay on the same action for usability.
                                 // The player itself must handle being on a frame that has been deleted in order for replays to work.
-                                for any_player in &mut self.players {
+                                for any_player in &mut self.sim.players {
                                     if any_player.fighter == fighter && any_player.action as usize == action
-                                        && any_player.frame as usize == self.package.fighters[fighter].actions[action].frames.len()
+                                        && any_player.frame as usize == self.sim.package.fighters[fighter].actions[action].frames.len()
                                     {
                                         any_player.frame -= 1;
                                     }
@@ -454,15 +692,15 @@ impl Game {
                     }
                     // delete collisionbox
                     if os_input.key_pressed(VirtualKeyCode::D) {
-                        self.package.delete_fighter_colboxes(fighter, action, frame, &self.selector.colboxes);
+                        self.sim.package.delete_fighter_colboxes(fighter, action, frame, &self.selector.colboxes);
                         self.update_frame();
                     }
                     // add collisionbox
                     if os_input.key_pressed(VirtualKeyCode::F) {
                         if let Some((m_x, m_y)) = os_input.game_mouse(self.camera.for_winit_helper()) {
                             let selected = {
-                                let player = &self.players[player];
-                                let (p_x, p_y) = player.public_bps_xy(&self.players, &self.package.fighters, &self.stage.surfaces);
+                                let player = &self.sim.players[player];
+                                let (p_x, p_y) = player.public_bps_xy(&self.sim.players, &self.sim.package.fighters, &self.sim.stage.surfaces);
 
                                 let point = (player.relative_f(m_x - p_x), m_y - p_y);
                                 let new_colbox = CollisionBox::new(point);
@@ -471,7 +709,7 @@ impl Game {
                                     false => LinkType::MeldFirst
                                 };
 
-                                self.package.append_fighter_colbox(fighter, action, frame, new_colbox, &self.selector.colboxes, link_type)
+                                self.sim.package.append_fighter_colbox(fighter, action, frame, new_colbox, &self.selector.colboxes, link_type)
                             };
                             self.update_frame();
                             self.selector.colboxes.insert(selected);
@@ -479,10 +717,10 @@ impl Game {
                     }
                     // resize collisionbox
                     if os_input.key_pressed(VirtualKeyCode::LBracket) {
-                        self.package.resize_fighter_colboxes(fighter, action, frame, &self.selector.colboxes, -0.1);
+                        self.sim.package.resize_fighter_colboxes(fighter, action, frame, &self.selector.colboxes, -0.1);
                     }
                     if os_input.key_pressed(VirtualKeyCode::RBracket) {
-                        self.package.resize_fighter_colboxes(fighter, action, frame, &self.selector.colboxes, 0.1);
+                        self.sim.package.resize_fighter_colboxes(fighter, action, frame, &self.selector.colboxes, 0.1);
                     }
                     // meld link collisionboxes
                     if os_input.key_pressed(VirtualKeyCode::Z) {
@@ -497,27 +735,27 @@ impl Game {
                         // TODO
                     }
                     if os_input.key_pressed(VirtualKeyCode::Comma) {
-                        self.package.fighter_colboxes_send_to_front(fighter, action, frame, &self.selector.colboxes)
+                        self.sim.package.fighter_colboxes_send_to_front(fighter, action, frame, &self.selector.colboxes)
                     }
                     if os_input.key_pressed(VirtualKeyCode::Period) {
-                        self.package.fighter_colboxes_send_to_back(fighter, action, frame, &self.selector.colboxes)
+                        self.sim.package.fighter_colboxes_send_to_back(fighter, action, frame, &self.selector.colboxes)
                     }
                     // set hitbox angle
                     if os_input.key_pressed(VirtualKeyCode::Q) {
                         if let Some((m_x, m_y)) = os_input.game_mouse(self.camera.for_winit_helper()) {
-                            let player = &self.players[player];
-                            let (p_x, p_y) = player.public_bps_xy(&self.players, &self.package.fighters, &self.stage.surfaces);
+                            let player = &self.sim.players[player];
+                            let (p_x, p_y) = player.public_bps_xy(&self.sim.players, &self.sim.package.fighters, &self.sim.stage.surfaces);
 
                             let x = player.relative_f(m_x - p_x);
                             let y = m_y - p_y;
-                            self.package.point_hitbox_angles_to(fighter, action, frame, &self.selector.colboxes, x, y);
+                            self.sim.package.point_hitbox_angles_to(fighter, action, frame, &self.selector.colboxes, x, y);
                         }
                     }
 
                     // handle single selection
                     if let Some((m_x, m_y)) = self.selector.step_single_selection(os_input, &self.camera) {
-                        let (player_x, player_y) = self.players[player].public_bps_xy(&self.players, &self.package.fighters, &self.stage.surfaces);
-                        let frame = self.players[player].relative_frame(&self.package.fighters[fighter], &self.stage.surfaces);
+                        let (player_x, player_y) = self.sim.players[player].public_bps_xy(&self.sim.players, &self.sim.package.fighters, &self.sim.stage.surfaces);
+                        let frame = self.sim.players[player].relative_frame(&self.sim.package.fighters[fighter], &self.sim.stage.surfaces);
 
                         for (i, colbox) in frame.colboxes.iter().enumerate() {
                             let hit_x = colbox.point.0 + player_x;
@@ -546,8 +784,8 @@ impl Game {
 
                     // handle multiple selection
                     if let Some(rect) = self.selector.step_multiple_selection(os_input, &self.camera) {
-                        let (player_x, player_y) = self.players[player].public_bps_xy(&self.players, &self.package.fighters, &self.stage.surfaces);
-                        let frame = self.players[player].relative_frame(&self.package.fighters[fighter], &self.stage.surfaces);
+                        let (player_x, player_y) = self.sim.players[player].public_bps_xy(&self.sim.players, &self.sim.package.fighters, &self.sim.stage.surfaces);
+                        let frame = self.sim.players[player].relative_frame(&self.sim.package.fighters[fighter], &self.sim.stage.surfaces);
 
                         for (i, colbox) in frame.colboxes.iter().enumerate() {
                             let hit_x = colbox.point.0 + player_x;
@@ -572,21 +810,28 @@ impl Game {
                 self.debug_stage.step(os_input);
                 if self.selector.moving {
                     let (d_x, d_y) = os_input.game_mouse_diff(self.camera.for_winit_helper());
-                    for (i, spawn) in self.stage.spawn_points.iter_mut().enumerate() {
+                    for (i, spawn) in self.sim.stage.spawn_points.iter_mut().enumerate() {
                         if self.selector.spawn_points.contains(&i) {
                             spawn.x += d_x;
                             spawn.y += d_y;
                         }
                     }
 
-                    for (i, respawn) in self.stage.respawn_points.iter_mut().enumerate() {
+                    for (i, respawn) in self.sim.stage.respawn_points.iter_mut().enumerate() {
                         if self.selector.respawn_points.contains(&i) {
                             respawn.x += d_x;
                             respawn.y += d_y;
                         }
                     }
 
-                    for (i, surface) in self.stage.surfaces.iter_mut().enumerate() {
+                    for (i, hazard) in self.sim.stage.hazards.iter_mut().enumerate() {
+                        if self.selector.hazards.contains(&i) {
+                            hazard.x += d_x;
+                            hazard.y += d_y;
+                        }
+                    }
+
+                    for (i, surface) in self.sim.stage.surfaces.iter_mut().enumerate() {
                         if self.selector.surfaces.contains(&SurfaceSelection::P1(i)) {
                             surface.x1 += d_x;
                             surface.y1 += d_y;
@@ -605,7 +850,7 @@ impl Game {
                 else {
                     // start move elements
                     if os_input.key_pressed(VirtualKeyCode::A) {
-                        if self.selector.surfaces.len() + self.selector.spawn_points.len() + self.selector.respawn_points.len() > 0 {
+                        if self.selector.surfaces.len() + self.selector.spawn_points.len() + self.selector.respawn_points.len() + self.selector.hazards.len() > 0 {
                             self.selector.moving = true;
                         }
                     }
@@ -616,25 +861,32 @@ impl Game {
                         spawns_to_delete.sort();
                         spawns_to_delete.reverse();
                         for spawn_i in spawns_to_delete {
-                            self.stage.spawn_points.remove(spawn_i);
+                            self.sim.stage.spawn_points.remove(spawn_i);
                         }
 
                         let mut respawns_to_delete: Vec<usize> = self.selector.respawn_points.iter().cloned().collect();
                         respawns_to_delete.sort();
                         respawns_to_delete.reverse();
                         for respawn_i in respawns_to_delete {
-                            self.stage.respawn_points.remove(respawn_i);
+                            self.sim.stage.respawn_points.remove(respawn_i);
+                        }
+
+                        let mut hazards_to_delete: Vec<usize> = self.selector.hazards.iter().cloned().collect();
+                        hazards_to_delete.sort();
+                        hazards_to_delete.reverse();
+                        for hazard_i in hazards_to_delete {
+                            self.sim.stage.hazards.remove(hazard_i);
                         }
 
                         let mut surfaces_to_delete = self.selector.surfaces_vec();
                         surfaces_to_delete.sort();
                         surfaces_to_delete.reverse();
-                        let players = self.players.clone();
+                        let players = self.sim.players.clone();
                         for surface_i in surfaces_to_delete {
-                            for player in self.players.iter_mut() {
-                                player.platform_deleted(&players, &self.package.fighters, &self.stage.surfaces, surface_i);
+                            for player in self.sim.players.iter_mut() {
+                                player.platform_deleted(&players, &self.sim.package.fighters, &self.sim.stage.surfaces, surface_i);
                             }
-                            self.stage.surfaces.remove(surface_i);
+                            self.sim.stage.surfaces.remove(surface_i);
                         }
 
                         self.update_frame();
@@ -655,35 +907,50 @@ impl Game {
                     }
                     // add stage surface
                     if os_input.key_pressed(VirtualKeyCode::R) {
-                        let surface = Surface { floor: Some(Floor { traction: 1.0, pass_through: false }), .. Surface::default() };
+                        let surface = Surface { floor: Some(Floor { traction: 1.0, conveyor: 0.0, pass_through: false }), .. Surface::default() };
                         self.add_surface(surface, os_input);
                     }
                     // add platform surface
                     if os_input.key_pressed(VirtualKeyCode::F) {
-                        let surface = Surface { floor: Some(Floor { traction: 1.0, pass_through: true }), .. Surface::default() };
+                        let surface = Surface { floor: Some(Floor { traction: 1.0, conveyor: 0.0, pass_through: true }), .. Surface::default() };
                         self.add_surface(surface, os_input);
                     }
                     // add spawn point
                     if os_input.key_pressed(VirtualKeyCode::Z) {
                         if let Some((m_x, m_y)) = os_input.game_mouse(self.camera.for_winit_helper()) {
-                            self.stage.spawn_points.push(SpawnPoint::new(m_x, m_y));
+                            self.sim.stage.spawn_points.push(SpawnPoint::new(m_x, m_y));
                             self.update_frame();
                         }
                     }
                     // add respawn point
                     if os_input.key_pressed(VirtualKeyCode::X) {
                         if let Some((m_x, m_y)) = os_input.game_mouse(self.camera.for_winit_helper()) {
-                            self.stage.respawn_points.push(SpawnPoint::new(m_x, m_y));
+                            self.sim.stage.respawn_points.push(SpawnPoint::new(m_x, m_y));
                             self.update_frame();
                         }
                     }
+                    // add hazard
+                    if os_input.key_pressed(VirtualKeyCode::C) {
+                        if let Some((m_x, m_y)) = os_input.game_mouse(self.camera.for_winit_helper()) {
+                            self.sim.stage.hazards.push(Hazard::new(m_x, m_y));
+                            self.update_frame();
+                        }
+                    }
+                    // toggle hazard on selected surfaces
+                    if os_input.key_pressed(VirtualKeyCode::V) {
+                        for surface_i in self.selector.surfaces_vec() {
+                            let surface = &mut self.sim.stage.surfaces[surface_i];
+                            surface.hazard = if surface.hazard.is_some() { None } else { Some(HitBox::default()) };
+                        }
+                        self.update_frame();
+                    }
                     if os_input.key_pressed(VirtualKeyCode::S) {
                         let mut join = false;
                         let mut points: Vec<(f32, f32)> = vec!();
                         for selection in self.selector.surfaces.iter() {
                             match selection {
                                 &SurfaceSelection::P1 (i) => {
-                                    let surface = &self.stage.surfaces[i];
+                                    let surface = &self.sim.stage.surfaces[i];
                                     if let Some((prev_x, prev_y)) = points.last().cloned() {
                                         if surface.x1 != prev_x || surface.y1 != prev_y {
                                             join = true;
@@ -692,7 +959,7 @@ impl Game {
                                     points.push((surface.x1, surface.y1));
                                 }
                                 &SurfaceSelection::P2 (i) => {
-                                    let surface = &self.stage.surfaces[i];
+                                    let surface = &self.sim.stage.surfaces[i];
                                     if let Some((prev_x, prev_y)) = points.last().cloned() {
                                         if surface.x2 != prev_x || surface.y2 != prev_y {
                                             join = true;
@@ -716,12 +983,12 @@ impl Game {
                             for selection in self.selector.surfaces.iter() {
                                 match selection {
                                     &SurfaceSelection::P1 (i) => {
-                                        let surface = &mut self.stage.surfaces[i];
+                                        let surface = &mut self.sim.stage.surfaces[i];
                                         surface.x1 = average_x;
                                         surface.y1 = average_y;
                                     }
                                     &SurfaceSelection::P2 (i) => {
-                                        let surface = &mut self.stage.surfaces[i];
+                                        let surface = &mut self.sim.stage.surfaces[i];
                                         surface.x2 = average_x;
                                         surface.y2 = average_y;
                                     }
@@ -731,12 +998,12 @@ impl Game {
                             for selection in self.selector.surfaces.iter() {
                                 match selection {
                                     &SurfaceSelection::P1 (i) => {
-                                        let surface = &mut self.stage.surfaces[i];
+                                        let surface = &mut self.sim.stage.surfaces[i];
                                         surface.x1 = average_x + (surface.x2 - average_x) / 5.0;
                                         surface.y1 = average_y + (surface.y2 - average_y) / 5.0;
                                     }
                                     &SurfaceSelection::P2 (i) => {
-                                        let surface = &mut self.stage.surfaces[i];
+                                        let surface = &mut self.sim.stage.surfaces[i];
                                         surface.x2 = average_x + (surface.x1 - average_x) / 5.0;
                                         surface.y2 = average_y + (surface.y1 - average_y) / 5.0;
                                     }
@@ -746,10 +1013,12 @@ impl Game {
                     }
                 }
 
+                self.sim.stage.rebuild_surface_grid();
+
                 // handle single selection
                 if let Some((m_x, m_y)) = self.selector.step_single_selection(os_input, &self.camera) {
                     if self.debug_stage.spawn_points {
-                        for (i, point) in self.stage.spawn_points.iter().enumerate() {
+                        for (i, point) in self.sim.stage.spawn_points.iter().enumerate() {
                             let distance = ((m_x - point.x).powi(2) + (m_y - point.y).powi(2)).sqrt();
                             if distance < 4.0 {
                                 if os_input.held_alt() {
@@ -761,7 +1030,7 @@ impl Game {
                         }
                     }
                     if self.debug_stage.respawn_points {
-                        for (i, point) in self.stage.respawn_points.iter().enumerate() {
+                        for (i, point) in self.sim.stage.respawn_points.iter().enumerate() {
                             let distance = ((m_x - point.x).powi(2) + (m_y - point.y).powi(2)).sqrt();
                             if distance < 4.0 {
                                 if os_input.held_alt() {
@@ -772,7 +1041,21 @@ impl Game {
                             }
                         }
                     }
-                    for (i, surface) in self.stage.surfaces.iter().enumerate() {
+                    if self.debug_stage.hazards {
+                        for (i, hazard) in self.sim.stage.hazards.iter().enumerate() {
+                            let distance = ((m_x - hazard.x).powi(2) + (m_y - hazard.y).powi(2)).sqrt();
+                            if distance < hazard.radius.max(4.0) {
+                                if os_input.held_alt() {
+                                    self.selector.hazards.remove(&i);
+                                } else {
+                                    self.selector.hazards.insert(i);
+                                }
+                            }
+                        }
+                    }
+                    let near_mouse = Rect { x1: m_x - 3.0, y1: m_y - 3.0, x2: m_x + 3.0, y2: m_y + 3.0 };
+                    for i in self.sim.stage.surfaces_near(&near_mouse) {
+                        let surface = &self.sim.stage.surfaces[i];
                         let distance1 = ((m_x - surface.x1).powi(2) + (m_y - surface.y1).powi(2)).sqrt();
                         if distance1 < 3.0 { // TODO: check entire half of surface, not just the edge
                             if os_input.held_alt() {
@@ -795,7 +1078,7 @@ impl Game {
                 // handle multiple selection
                 if let Some(rect) = self.selector.step_multiple_selection(os_input, &self.camera) {
                     if self.debug_stage.spawn_points {
-                        for (i, point) in self.stage.spawn_points.iter().enumerate() {
+                        for (i, point) in self.sim.stage.spawn_points.iter().enumerate() {
                             if rect.contains_point(point.x, point.y) { // TODO: check entire half of surface, not just the edge
                                 if os_input.held_alt() {
                                     self.selector.spawn_points.remove(&i);
@@ -806,7 +1089,7 @@ impl Game {
                         }
                     }
                     if self.debug_stage.respawn_points {
-                        for (i, point) in self.stage.respawn_points.iter().enumerate() {
+                        for (i, point) in self.sim.stage.respawn_points.iter().enumerate() {
                             if rect.contains_point(point.x, point.y) {
                                 if os_input.held_alt() {
                                     self.selector.respawn_points.remove(&i);
@@ -816,7 +1099,19 @@ impl Game {
                             }
                         }
                     }
-                    for (i, surface) in self.stage.surfaces.iter().enumerate() {
+                    if self.debug_stage.hazards {
+                        for (i, hazard) in self.sim.stage.hazards.iter().enumerate() {
+                            if rect.contains_point(hazard.x, hazard.y) {
+                                if os_input.held_alt() {
+                                    self.selector.hazards.remove(&i);
+                                } else {
+                                    self.selector.hazards.insert(i);
+                                }
+                            }
+                        }
+                    }
+                    for i in self.sim.stage.surfaces_near(&rect) {
+                        let surface = &self.sim.stage.surfaces[i];
                         if rect.contains_point(surface.x1, surface.y1) {
                             if os_input.held_alt() {
                                 self.selector.surfaces.remove(&SurfaceSelection::P1(i));
@@ -844,30 +1139,37 @@ impl Game {
             if self.selector.surfaces.len() == 1 {
                 // create new surface, p1 is selected surface, p2 is current mouse
                 let (x1, y1) = match self.selector.surfaces.iter().next().unwrap() {
-                    &SurfaceSelection::P1 (i) => (self.stage.surfaces[i].x1, self.stage.surfaces[i].y1),
-                    &SurfaceSelection::P2 (i) => (self.stage.surfaces[i].x2, self.stage.surfaces[i].y2)
+                    &SurfaceSelection::P1 (i) => (self.sim.stage.surfaces[i].x1, self.sim.stage.surfaces[i].y1),
+                    &SurfaceSelection::P2 (i) => (self.sim.stage.surfaces[i].x2, self.sim.stage.surfaces[i].y2)
                 };
 
                 self.selector.clear();
-                self.selector.surfaces.insert(SurfaceSelection::P2(self.stage.surfaces.len()));
-                self.stage.surfaces.push(Surface { x1, y1, x2: m_x, y2: m_y, .. surface });
+                self.selector.surfaces.insert(SurfaceSelection::P2(self.sim.stage.surfaces.len()));
+                self.sim.stage.surfaces.push(Surface { x1, y1, x2: m_x, y2: m_y, .. surface });
             }
             else if self.selector.surfaces.len() == 0 {
                 // create new surface, p1 is current mouse, p2 is moving
                 self.selector.clear();
-                self.selector.surfaces.insert(SurfaceSelection::P2(self.stage.surfaces.len()));
+                self.selector.surfaces.insert(SurfaceSelection::P2(self.sim.stage.surfaces.len()));
                 self.selector.moving = true;
-                self.stage.surfaces.push(Surface { x1: m_x, y1: m_y, x2: m_x, y2: m_y, .. surface } );
+                self.sim.stage.surfaces.push(Surface { x1: m_x, y1: m_y, x2: m_x, y2: m_y, .. surface } );
             }
         }
     }
 
-    /// next frame is advanced by using the input history on the current frame
-    // TODO: Activate by shift+K/L
-    fn step_replay_forwards(&mut self, input: &mut Input, netplay: &Netplay) { // TODO: rename: step_replay_forwards_from_input
-        if self.current_frame <= input.last_frame() {
-            self.current_frame += 1;
-            let player_inputs = &input.players(self.current_frame, netplay);
+    /// Next frame is simulated from the input history.
+    /// Any frames stored after the current frame are replaced, the replaced timeline is kept as a branch.
+    fn step_replay_forwards_from_input(&mut self, input: &mut Input, netplay: &Netplay) {
+        if self.sim.current_frame <= input.last_frame() {
+            self.branch_future(input);
+            self.player_history.truncate(self.sim.current_frame);
+            self.stage_history.truncate(self.sim.current_frame);
+            self.sim.rewind_sudden_deaths(self.sim.current_frame);
+
+            self.player_history.push(&self.sim.players);
+            self.stage_history.push(&self.sim.stage);
+            self.sim.current_frame += 1;
+            let player_inputs = &input.players(self.sim.current_frame, netplay);
             self.step_game(input, player_inputs);
 
             self.update_frame();
@@ -881,10 +1183,24 @@ impl Game {
         }
     }
 
-    // TODO: Activate by K/L
-    // fn step_replay_forwards_from_history() {
-    //     TODO
-    // }
+    /// Next frame is loaded from history, if it was never stored it is simulated from the input history instead.
+    fn step_replay_forwards(&mut self, input: &mut Input, netplay: &Netplay) {
+        let frame = self.sim.current_frame + 1;
+        if frame < self.player_history.first_frame() {
+            // the frame was dropped from history, so it is simulated without replacing the frames that are still stored
+            self.sim.current_frame = frame;
+            let player_inputs = &input.players(frame, netplay);
+            self.step_game(input, player_inputs);
+        }
+        else if !self.load_history_frame(frame) {
+            self.step_replay_forwards_from_input(input, netplay);
+            return;
+        }
+
+        if input.start_pressed() {
+            self.state = GameState::Paused;
+        }
+    }
 
     fn step_replay_forwards_os_input(&mut self, os_input: &WinitInputHelper<()>) {
         if os_input.key_pressed(VirtualKeyCode::H) {
@@ -895,15 +1211,11 @@ impl Game {
         }
     }
 
-    /// Immediately jumps to the previous frame in history
+    /// Immediately jumps to the previous frame in history, pausing when the start of the history is reached
     fn step_replay_backwards(&mut self, input: &mut Input) {
-        if self.current_frame > 0 {
-            self.current_frame -= 1;
-            self.players = self.player_history.get(self.current_frame).unwrap().clone();
-            self.stage   = self.stage_history .get(self.current_frame).unwrap().clone();
-            self.update_frame();
-        }
-        else {
+        self.store_current_frame();
+        let frame = self.sim.current_frame.wrapping_sub(1);
+        if !self.load_history_frame(frame) {
             self.state = GameState::Paused;
         }
 
@@ -924,193 +1236,48 @@ impl Game {
     }
 
     /// Jump to the saved frame in history
-    // TODO: FIX
-    //fn jump_frame(&mut self) {
-    //    let frame = self.saved_frame;
-    //    if (frame+1) < self.player_history.len() {
-    //        self.players = self.player_history.get(frame).unwrap().clone();
-
-    //        self.current_frame = frame;
-    //        self.update_frame();
-    //    }
-    //}
-
-    fn get_seed(&self) -> [u8; 32] {
-        let mut seed = [0; 32];
-        (&mut seed[0..8]).write_u64::<LittleEndian>(self.init_seed).unwrap();
-        (&mut seed[8..16]).write_u64::<LittleEndian>(self.current_frame as u64).unwrap();
-        seed
+    fn jump_frame(&mut self) {
+        let frame = self.saved_frame;
+        self.store_current_frame();
+        self.load_history_frame(frame);
     }
 
-    fn step_game(&mut self, input: &Input, player_input: &Vec<PlayerInput>) {
-        {
-            let mut rng = ChaChaRng::from_seed(self.get_seed());
-
-            // To synchronize player stepping, we step through player logic in stages (action logic, physics logic, collision logic)
-            // Modified players are copied from the previous stage so that every player perceives themselves as being stepped first, within that stage.
-
-            // step each player action
-            let mut action_players: Vec<Player> = vec!();
-            for (i, player) in self.players.iter().enumerate() {
-                let mut player = player.clone();
-                let input = &player_input[self.selected_controllers[i]];
-                let mut context = StepContext {
-                    players:  &self.players,
-                    fighters: &self.package.fighters,
-                    fighter:  &self.package.fighters[player.fighter.as_ref()],
-                    stage:    &self.stage,
-                    surfaces: &self.stage.surfaces,
-                    rng:      &mut rng,
-                    input,
-                };
-                player.action_hitlag_step(&mut context);
-                action_players.push(player);
-            }
+    fn step_game(&mut self, input: &Input, player_input: &[PlayerInput]) {
+        let state = self.sim.step_frame(player_input);
 
-            // step each player physics
-            let mut physics_players: Vec<Player> = vec!();
-            for (i, player) in action_players.iter().enumerate() {
-                let mut player = player.clone();
-                let input = &player_input[self.selected_controllers[i]];
-                let mut context = StepContext {
-                    players:  &self.players,
-                    fighters: &self.package.fighters,
-                    fighter:  &self.package.fighters[player.fighter.as_ref()],
-                    stage:    &self.stage,
-                    surfaces: &self.stage.surfaces,
-                    rng:      &mut rng,
-                    input,
-                };
-                player.physics_step(&mut context, i, self.current_frame, self.package.rules.goal.clone());
-                physics_players.push(player);
-            }
-
-            // check for hits and run hit logic
-            let mut collision_players: Vec<Player> = vec!();
-            let collision_results = collision_check(&physics_players, &self.package.fighters, &self.stage.surfaces);
-            for (i, player) in physics_players.iter().enumerate() {
-                let mut player = player.clone();
-                let input = &player_input[self.selected_controllers[i]];
-                let mut context = StepContext {
-                    players:  &self.players,
-                    fighters: &self.package.fighters,
-                    fighter:  &self.package.fighters[player.fighter.as_ref()],
-                    stage:    &self.stage,
-                    surfaces: &self.stage.surfaces,
-                    rng:      &mut rng,
-                    input,
-                };
-                player.step_collision(&mut context, &collision_results[i]);
-                collision_players.push(player);
-            }
+        // sudden deaths after this frame are only kept when the history after this frame is still stored
+        let frame = self.sim.current_frame;
+        let future_history = self.player_history.len() > frame;
+        let mut sudden_deaths = self.sim.sudden_deaths.clone();
+        if future_history {
+            sudden_deaths.extend(self.sudden_deaths.iter().filter(|x| x.start_frame > frame).cloned());
+        }
+        self.sudden_deaths = sudden_deaths;
 
-            self.players = collision_players;
+        self.checksum_history.truncate(self.sim.current_frame - 1);
+        if self.config.store_checksums {
+            self.checksum_history.resize(self.sim.current_frame - 1, None);
+            self.checksum_history.push(Some(self.sim.checksum()));
         }
 
-        if self.time_out() ||
-           (self.players.len() == 1 && self.players.iter().filter(|x| x.action != Action::Eliminated.to_u64().unwrap()).count() == 0) ||
-           (self.players.len() >  1 && self.players.iter().filter(|x| x.action != Action::Eliminated.to_u64().unwrap()).count() == 1)
-        {
-            self.state = self.generate_game_results(input);
+        if let SimulationState::Finished (order) = state {
+            self.state = self.generate_game_results(input, order);
         }
 
         self.update_frame();
     }
 
-    pub fn time_out(&self) -> bool {
-        if let Some(time_limit_frames) = self.package.rules.time_limit_frames() {
-            self.current_frame as u64 > time_limit_frames
-        } else {
-            false
-        }
-    }
+    pub fn generate_game_results(&self, input: &Input, order: Vec<usize>) -> GameState {
+        let (player_results, team_results) = self.sim.results(&order);
 
-    pub fn generate_game_results(&self, input: &Input) -> GameState {
-        let raw_player_results: Vec<RawPlayerResult> = self.players.iter().map(|x| x.result()).collect();
-        // TODO: Players on the same team score to the same pool, and share their place.
-        let places: Vec<usize> = match self.package.rules.goal {
-            Goal::LastManStanding => {
-                // most stocks remaining wins
-                // tie-breaker:
-                //  * if both eliminated: who lost their last stock last wins
-                //  * if both alive:      lowest percentage wins
-                let mut raw_player_results_i: Vec<(usize, &RawPlayerResult)> = raw_player_results.iter().enumerate().collect();
-                raw_player_results_i.sort_by(
-                    |a_set, b_set| {
-                        let a = a_set.1;
-                        let b = b_set.1;
-                        let a_deaths = a.deaths.len();
-                        let b_deaths = b.deaths.len();
-                        a_deaths.cmp(&b_deaths).then(
-                            if a_deaths == 0 {
-                                if let Some(death_a) = a.deaths.last() {
-                                    if let Some(death_b) = b.deaths.last() {
-                                        death_a.frame.cmp(&death_b.frame)
-                                    }
-                                    else {
-                                        Ordering::Equal
-                                    }
-                                }
-                                else {
-                                    Ordering::Equal
-                                }
-                            }
-                            else {
-                                a.final_damage.unwrap().partial_cmp(&b.final_damage.unwrap()).unwrap_or(Ordering::Equal)
-                            }
-                        )
-                    }
-                );
-                raw_player_results_i.iter().map(|x| x.0).collect()
-            }
-            Goal::KillDeathScore => {
-                // highest kills wins
-                // tie breaker: least deaths wins
-                let mut raw_player_results_i: Vec<(usize, &RawPlayerResult)> = raw_player_results.iter().enumerate().collect();
-                raw_player_results_i.sort_by(
-                    |a_set, b_set| {
-                        // Repopulating kill lists every frame shouldnt be too bad
-                        let a_kills: Vec<usize> = vec!(); // TODO: populate
-                        let b_kills: Vec<usize> = vec!(); // TODO: populate
-                        let a = a_set.1;
-                        let b = b_set.1;
-                        let a_kills = a_kills.len();
-                        let b_kills = b_kills.len();
-                        let a_deaths = a.deaths.len();
-                        let b_deaths = b.deaths.len();
-                        b_kills.cmp(&a_kills).then(a_deaths.cmp(&b_deaths))
-                    }
-                );
-                raw_player_results_i.iter().map(|x| x.0).collect()
-            }
-        };
-
-        let mut player_results: Vec<PlayerResult> = vec!();
-        for (i, raw_player_result) in raw_player_results.iter().enumerate() {
-            let lcancel_percent = if raw_player_result.lcancel_attempts == 0 {
-                100.0
-            }
-            else {
-                raw_player_result.lcancel_success as f32 / raw_player_result.lcancel_attempts as f32
-            };
-            player_results.push(PlayerResult {
-                fighter:         raw_player_result.ended_as_fighter.clone().unwrap(),
-                team:            raw_player_result.team,
-                controller:      self.selected_controllers[i],
-                place:           places[i],
-                kills:           vec!(), // TODO
-                deaths:          raw_player_result.deaths.clone(),
-                lcancel_percent: lcancel_percent,
-            });
-        }
-        player_results.sort_by_key(|x| x.place);
-
-        let replay = Replay::new(self, input);
+        let mut replay = Replay::new(self, input);
+        replay.metadata.winner = order.first().cloned();
 
         GameState::Quit (
             ResumeMenu::Results (
                 GameResults {
                     player_results,
+                    team_results,
                     replay,
                 }
             )
@@ -1118,13 +1285,13 @@ impl Game {
     }
 
     fn generate_debug(&mut self, input: &Input, netplay: &Netplay) {
-        let frame = self.current_frame;
+        let frame = self.sim.current_frame;
         let player_inputs = &input.players_no_log(frame, netplay);
 
         self.debug_lines = vec!(format!("Frame: {}    state: {}", frame, self.state));
-        for (i, player) in self.players.iter().enumerate() {
-            let fighter = &self.package.fighters[self.players[i].fighter.as_ref()];
-            let player_input = &player_inputs[self.selected_controllers[i]];
+        for (i, player) in self.sim.players.iter().enumerate() {
+            let fighter = &self.sim.package.fighters[self.sim.players[i].fighter.as_ref()];
+            let player_input = &player_inputs[self.sim.controllers[i]];
             let debug_player = &self.debug_players[i];
             self.debug_lines.extend(player.debug_print(fighter, player_input, debug_player, i));
         }
@@ -1149,7 +1316,9 @@ impl Game {
     pub fn render(&self) -> RenderGame {
         let mut entities = vec!();
 
-        for (i, player) in self.players.iter().enumerate() {
+        // only the most recent frames are used for onion skinning
+        let player_history = self.player_history.range(self.sim.current_frame.saturating_sub(10)..self.sim.current_frame);
+        for (i, player) in self.sim.players.iter().enumerate() {
             let mut selected_colboxes = HashSet::new();
             let mut fighter_selected = false;
             let mut player_selected = false;
@@ -1170,25 +1339,35 @@ impl Game {
 
             let debug = self.debug_players[i].clone();
             if debug.cam_area {
-                let cam_area = player.cam_area(&self.stage.camera, &self.players, &self.package.fighters, &self.stage.surfaces);
+                let cam_area = player.cam_area(&self.sim.stage.camera, &self.sim.players, &self.sim.package.fighters, &self.sim.stage.surfaces);
                 entities.push(RenderEntity::rect_outline(cam_area, 0.0, 0.0, 1.0));
             }
 
-            let fighters = &self.package.fighters;
-            let surfaces = &self.stage.surfaces;
-            let player_render = player.render(selected_colboxes, fighter_selected, player_selected, debug, i, &self.player_history[0..self.current_frame], &self.players, fighters, surfaces);
+            let fighters = &self.sim.package.fighters;
+            let surfaces = &self.sim.stage.surfaces;
+            let player_render = player.render(selected_colboxes, fighter_selected, player_selected, debug, i, &player_history, &self.sim.players, fighters, surfaces);
             entities.push(RenderEntity::Player(player_render));
         }
 
+        // players of the compared branch are drawn over the current timeline so the effect of an edit can be seen
+        if let Some(players) = self.branches.compared_players(self.sim.current_frame) {
+            let fighters = &self.sim.package.fighters;
+            let surfaces = &self.sim.stage.surfaces;
+            for (i, player) in players.iter().enumerate().filter(|(_, x)| fighters.contains_key(&x.fighter)) {
+                let player_render = player.render(HashSet::new(), false, false, DebugPlayer::default(), i, &[], &players, fighters, surfaces);
+                entities.push(RenderEntity::BranchPlayer(player_render));
+            }
+        }
+
         // render stage debug entities
         if self.debug_stage.blast {
-            entities.push(RenderEntity::rect_outline(self.stage.blast.clone(),  1.0, 0.0, 0.0));
+            entities.push(RenderEntity::rect_outline(self.sim.stage.blast.clone(),  1.0, 0.0, 0.0));
         }
         if self.debug_stage.camera {
-            entities.push(RenderEntity::rect_outline(self.stage.camera.clone(), 0.0, 0.0, 1.0));
+            entities.push(RenderEntity::rect_outline(self.sim.stage.camera.clone(), 0.0, 0.0, 1.0));
         }
         if self.debug_stage.spawn_points {
-            for (i, point) in self.stage.spawn_points.iter().enumerate() {
+            for (i, point) in self.sim.stage.spawn_points.iter().enumerate() {
                 if self.selector.spawn_points.contains(&i) {
                     entities.push(RenderEntity::spawn_point(point.clone(), 0.0, 1.0, 0.0));
                 } else {
@@ -1197,7 +1376,7 @@ impl Game {
             }
         }
         if self.debug_stage.respawn_points {
-            for (i, point) in self.stage.respawn_points.iter().enumerate() {
+            for (i, point) in self.sim.stage.respawn_points.iter().enumerate() {
                 if self.selector.respawn_points.contains(&i) {
                     entities.push(RenderEntity::spawn_point(point.clone(), 0.0, 1.0, 0.0));
                 } else {
@@ -1206,6 +1385,21 @@ impl Game {
             }
         }
 
+        // active hazards are always visible, inactive hazards are only shown when debugging
+        let stage_frame = self.sim.current_frame as u64;
+        if self.sim.package.rules.stage_hazards || self.debug_stage.hazards {
+            for (i, hazard) in self.sim.stage.hazards.iter().enumerate() {
+                let active = hazard.is_active(stage_frame);
+                if self.debug_stage.hazards && self.selector.hazards.contains(&i) {
+                    entities.push(RenderEntity::hazard(hazard, 0.0, 1.0, 0.0, 0.6));
+                } else if active && self.sim.package.rules.stage_hazards {
+                    entities.push(RenderEntity::hazard(hazard, 1.0, 0.2, 0.0, 0.6));
+                } else if self.debug_stage.hazards {
+                    entities.push(RenderEntity::hazard(hazard, 0.5, 0.2, 0.0, 0.3));
+                }
+            }
+        }
+
         // render selector box
         if let Some(point) = self.selector.point {
             if let Some(mouse) = self.selector.mouse {
@@ -1214,23 +1408,30 @@ impl Game {
             }
         }
 
-        let timer = if let Some(time_limit_frames) = self.package.rules.time_limit_frames() {
-            let frames_remaining = time_limit_frames.saturating_sub(self.current_frame as u64);
+        let timer = if let Some(end_frame) = self.sim.end_frame() {
+            let frames_remaining = end_frame.saturating_sub(self.sim.current_frame as u64);
             let frame_duration = Duration::new(1, 0) / 60;
             Some(frame_duration * frames_remaining as u32)
         } else {
             None
         };
 
+        let pause_hold = match self.sim.package.rules.pause {
+            Pause::Hold { frames } if self.pause_hold_frames > 0 => Some(self.pause_hold_frames as f32 / frames.max(1) as f32),
+            _ => None
+        };
+
         RenderGame {
-            seed:              self.get_seed(),
-            surfaces:          self.stage.surfaces.to_vec(),
+            seed:              self.sim.get_seed(),
+            surfaces:          self.sim.stage.surfaces.to_vec(),
             selected_surfaces: self.selector.surfaces.clone(),
             entities:          entities,
             state:             self.state.clone(),
             camera:            self.camera.clone(),
             debug_lines:       self.debug_lines.clone(),
             timer:             timer,
+            pause_hold:        pause_hold,
+            sudden_death:      self.sim.sudden_deaths.len() > 0,
         }
     }
 
@@ -1243,13 +1444,13 @@ impl Game {
         };
 
         GraphicsMessage {
-            package_updates: self.package.updates(),
+            package_updates: self.sim.package.updates(),
             render:          render,
         }
     }
 
     pub fn reclaim(self) -> (Package, Config) {
-        (self.package, self.config)
+        (self.sim.package, self.config)
     }
 }
 
@@ -1258,8 +1459,9 @@ pub enum GameState {
     Local,
     ReplayForwards,
     ReplayBackwards,
+    ResimulateForwards, // like ReplayForwards but always simulates from the input history
     Netplay,
-    Paused, // Only Local, ReplayForwards and ReplayBackwards can be paused
+    Paused, // Only Local, ReplayForwards, ReplayBackwards and ResimulateForwards can be paused
     Quit (ResumeMenu), // Both Local and Netplay end at Quit
 
     // Used for TAS, in game these are run during pause state
@@ -1274,6 +1476,7 @@ impl fmt::Display for GameState {
             &GameState::Local                 => write!(f, "Local"),
             &GameState::ReplayForwards        => write!(f, "ReplayForwards"),
             &GameState::ReplayBackwards       => write!(f, "ReplayBackwards"),
+            &GameState::ResimulateForwards    => write!(f, "ResimulateForwards"),
             &GameState::Netplay               => write!(f, "Netplay"),
             &GameState::Paused                => write!(f, "Paused"),
             &GameState::Quit (_)              => write!(f, "Quit"),
@@ -1309,6 +1512,7 @@ pub struct Selector {
     surfaces:      HashSet<SurfaceSelection>,
     spawn_points:   HashSet<usize>,
     respawn_points: HashSet<usize>,
+    hazards:        HashSet<usize>,
     moving:         bool,
     point:          Option<(f32, f32)>, // selector starting point
     mouse:          Option<(f32, f32)>, // used to know mouse point during render
@@ -1350,6 +1554,7 @@ impl Selector {
         self.surfaces.clear();
         self.spawn_points.clear();
         self.respawn_points.clear();
+        self.hazards.clear();
     }
 
     /// Returns a selection rect iff a multiple selection is finished.
@@ -1417,12 +1622,16 @@ pub struct RenderGame {
     pub camera:            Camera,
     pub debug_lines:       Vec<String>,
     pub timer:             Option<Duration>,
+    pub pause_hold:        Option<f32>, // progress towards pausing while start is held
+    pub sudden_death:      bool,
 }
 
 pub enum RenderEntity {
-    Player      (RenderPlayer),
-    RectOutline (RenderRect),
-    SpawnPoint  (RenderSpawnPoint),
+    Player       (RenderPlayer),
+    BranchPlayer (RenderPlayer),
+    RectOutline  (RenderRect),
+    SpawnPoint   (RenderSpawnPoint),
+    Hazard       (RenderHazard),
 }
 
 impl RenderEntity {
@@ -1445,6 +1654,17 @@ impl RenderEntity {
             }
         )
     }
+
+    pub fn hazard(hazard: &Hazard, r: f32, g: f32, b: f32, a: f32) -> RenderEntity {
+        RenderEntity::Hazard (
+            RenderHazard {
+                x:      hazard.x,
+                y:      hazard.y,
+                radius: hazard.radius,
+                color:  [r, g, b, a]
+            }
+        )
+    }
 }
 
 pub struct RenderRect {
@@ -1459,12 +1679,19 @@ pub struct RenderSpawnPoint {
     pub color: [f32; 4]
 }
 
+pub struct RenderHazard {
+    pub x:      f32,
+    pub y:      f32,
+    pub radius: f32,
+    pub color:  [f32; 4]
+}
+
 #[derive(Clone)]
 pub struct GameSetup {
     pub init_seed:      u64,
     pub input_history:  Vec<Vec<ControllerInput>>,
-    pub player_history: Vec<Vec<Player>>,
-    pub stage_history:  Vec<Stage>,
+    pub player_history: History<Vec<Player>>,
+    pub stage_history:  History<Stage>,
     pub controllers:    Vec<usize>,
     pub players:        Vec<PlayerSetup>,
     pub ais:            Vec<usize>,
@@ -1477,9 +1704,3 @@ impl GameSetup {
         Local::now().timestamp() as u64
     }
 }
-
-#[derive(Clone, Default, Serialize, Deserialize, Node)]
-pub struct PlayerSetup {
-    pub fighter: String,
-    pub team:    usize,
-}
@@ -1,3 +1,4 @@
+/// Reads controllers from libusb and gilrs, converting them into PlayerInput is handled by pf_sandbox_lib::input
 /// The contents of this module should really be in pf_sandbox_lib::input
 /// However that would mean adding libusb as a dep to pf_sandbox_lib
 /// So I wont move this code into pf_sandbox_lib until libusb has no external dependencies to setup.
@@ -8,9 +9,6 @@ use pf_sandbox_lib::input::{
     ControllerInput,
     Deadzone,
     PlayerInput,
-    Button,
-    Stick,
-    Trigger
 };
 
 use std::time::Duration;
@@ -30,7 +28,9 @@ pub struct Input<'a> {
     // structure: frames Vec<controllers Vec<ControllerInput>>
     game_inputs:     Vec<Vec<ControllerInput>>,
     current_inputs:  Vec<ControllerInput>, // inputs for this frame
+    netplay_offset:  i64, // netplay frame minus the frame of the game/menu currently using netplay
     prev_start:      bool,
+    prev_quit:       bool,
     input_sources:   Vec<InputSource<'a>>,
     gilrs:           Gilrs,
     controller_maps: ControllerMaps,
@@ -85,8 +85,10 @@ impl<'a> Input<'a> {
         Input {
             game_inputs:    vec!(),
             current_inputs: vec!(),
+            netplay_offset: 0,
             events:         vec!(),
             prev_start:     false,
+            prev_quit:      false,
             input_sources,
             gilrs,
             controller_maps,
@@ -197,6 +199,7 @@ impl<'a> Input<'a> {
         }
 
         self.prev_start = self.current_inputs.iter().any(|x| x.start);
+        self.prev_quit = self.current_inputs.iter().any(|x| Input::quit_chord(x));
         self.current_inputs = inputs;
 
         debug!("step");
@@ -206,6 +209,7 @@ impl<'a> Input<'a> {
     pub fn reset_history(&mut self) {
         self.game_inputs.clear();
         self.prev_start = false;
+        self.prev_quit = false;
     }
 
     /// Set the game input history
@@ -229,36 +233,28 @@ impl<'a> Input<'a> {
     }
 
     /// Call this once from netplay game/menu update logic only (instead of game_update)
-    pub fn netplay_update(&mut self) {
+    /// frame is the current frame of the game/menu, it is used to find the netplay frame of each frame passed to players()
+    pub fn netplay_update(&mut self, frame: usize, netplay: &Netplay) {
+        self.netplay_offset = netplay.frame() as i64 - frame as i64;
         self.game_inputs.push(self.current_inputs.clone());
     }
 
+    /// Returns the last frame of the game/menu where the inputs of every peer have been received, later frames use predicted inputs
+    pub fn netplay_confirmed_frame(&self, netplay: &Netplay) -> usize {
+        (netplay.confirmed_frame() as i64 - self.netplay_offset).max(0) as usize
+    }
+
     /// Return game inputs at specified index into history
+    /// During netplay the inputs come from netplay so that local inputs are delayed and remote inputs are predicted.
     pub fn players_no_log(&self, frame: usize, netplay: &Netplay) -> Vec<PlayerInput> {
-        let mut result_inputs: Vec<PlayerInput> = vec!();
-
-        let local_index = netplay.local_index();
-        let mut peer_offset = 0;
-        let peers_inputs = &netplay.confirmed_inputs;
-        for i in 0..netplay.number_of_peers() {
-            if i == local_index {
-                peer_offset = 1;
-
-                for i in 0..self.current_inputs.len() {
-                    let inputs = self.get_8frames_of_input(&self.game_inputs, i, frame as i64);
-                    result_inputs.push(Input::controller_inputs_to_player_input(inputs));
-                }
-            }
-            else {
-                let peer_inputs = &peers_inputs[i - peer_offset];
-                let num_controllers = peer_inputs.last().map_or(0, |x| x.len());
-                for i in 0..num_controllers {
-                    let inputs = self.get_8frames_of_input(&peer_inputs[..], i, netplay.frame() as i64);
-                    result_inputs.push(Input::controller_inputs_to_player_input(inputs));
-                }
-            }
+        if let NetplayState::Running = netplay.state() {
+            return netplay.player_inputs((frame as i64 + self.netplay_offset).max(0) as usize);
         }
 
+        let mut result_inputs: Vec<PlayerInput> = vec!();
+        for i in 0..self.current_inputs.len() {
+            result_inputs.push(PlayerInput::from_history(&self.game_inputs, i, frame as i64));
+        }
         result_inputs
     }
 
@@ -274,65 +270,9 @@ impl<'a> Input<'a> {
         result_inputs
     }
 
-    fn controller_inputs_to_player_input(inputs: Vec<ControllerInput>) -> PlayerInput {
-        if inputs[0].plugged_in {
-            PlayerInput {
-                plugged_in: true,
-
-                up:    Button { value: inputs[0].up,    press: inputs[0].up    && !inputs[1].up },
-                down:  Button { value: inputs[0].down,  press: inputs[0].down  && !inputs[1].down },
-                right: Button { value: inputs[0].right, press: inputs[0].right && !inputs[1].right },
-                left:  Button { value: inputs[0].left,  press: inputs[0].left  && !inputs[1].left },
-                y:     Button { value: inputs[0].y,     press: inputs[0].y     && !inputs[1].y },
-                x:     Button { value: inputs[0].x,     press: inputs[0].x     && !inputs[1].x },
-                b:     Button { value: inputs[0].b,     press: inputs[0].b     && !inputs[1].b },
-                a:     Button { value: inputs[0].a,     press: inputs[0].a     && !inputs[1].a },
-                l:     Button { value: inputs[0].l,     press: inputs[0].l     && !inputs[1].l },
-                r:     Button { value: inputs[0].r,     press: inputs[0].r     && !inputs[1].r },
-                z:     Button { value: inputs[0].z,     press: inputs[0].z     && !inputs[1].z },
-                start: Button { value: inputs[0].start, press: inputs[0].start && !inputs[1].start },
-
-                stick_x:   Stick { value: inputs[0].stick_x,   diff: inputs[0].stick_x   - inputs[1].stick_x },
-                stick_y:   Stick { value: inputs[0].stick_y,   diff: inputs[0].stick_y   - inputs[1].stick_y },
-                c_stick_x: Stick { value: inputs[0].c_stick_x, diff: inputs[0].c_stick_x - inputs[1].c_stick_x },
-                c_stick_y: Stick { value: inputs[0].c_stick_y, diff: inputs[0].c_stick_y - inputs[1].c_stick_y },
-
-                l_trigger:  Trigger { value: inputs[0].l_trigger, diff: inputs[0].l_trigger - inputs[1].l_trigger },
-                r_trigger:  Trigger { value: inputs[0].r_trigger, diff: inputs[0].r_trigger - inputs[1].r_trigger },
-                history: inputs,
-            }
-        }
-        else {
-            PlayerInput::empty()
-        }
-    }
-
-    /// converts frames Vec<controllers Vec<ControllerInput>> into frames Vec<ControllerInput> for the specified controller_i
-    /// Output must be 8 frames long, any missing frames due to either netplay lag or the game just starting are filled in
-    fn get_8frames_of_input(&self, game_inputs: &[Vec<ControllerInput>], controller_i: usize, frame: i64) -> Vec<ControllerInput> {
-        let mut result: Vec<ControllerInput> = vec!();
-        let empty_vec = vec!();
-
-        for frame_i in (frame-8..frame).rev() {
-            result.push(
-                if frame_i < 0 {
-                    ControllerInput::empty()
-                }
-                else {
-                    let controllers = match game_inputs.get(frame_i as usize) {
-                        Some(controllers) => controllers,
-                        None              => game_inputs.last().unwrap_or(&empty_vec)
-                    };
-                    match controllers.get(controller_i) {
-                        Some(value) => value.clone(),
-                        None        => ControllerInput::empty()
-                    }
-                }
-            );
-        }
-
-        assert!(result.len() == 8, "get_8frames_of_input needs to return a vector of size 8 but it was {}", result.len());
-        result
+    /// Returns the number of controllers that inputs are read from, including AI
+    pub fn controllers_len(&self) -> usize {
+        self.current_inputs.len()
     }
 
     /// Returns the index to the last frame in history
@@ -348,9 +288,19 @@ impl<'a> Input<'a> {
         !self.prev_start && self.current_inputs.iter().any(|x| x.start)
     }
 
+    /// Check for start button held
+    pub fn start_held(&self) -> bool {
+        self.current_inputs.iter().any(|x| x.start)
+    }
+
     /// button combination for quiting the game
+    /// Returns true on the frame a single controller completes the combination, regardless of the order the buttons were pressed in
     pub fn game_quit_held(&mut self) -> bool {
-        self.current_inputs.iter().any(|x| x.a && x.l && x.r && x.start) && self.start_pressed()
+        !self.prev_quit && self.current_inputs.iter().any(|x| Input::quit_chord(x))
+    }
+
+    fn quit_chord(input: &ControllerInput) -> bool {
+        input.a && input.l && input.r && input.start
     }
 }
 
@@ -6,17 +6,19 @@
 
 pub(crate) mod ai;
 pub(crate) mod app;
+pub(crate) mod branches;
 pub(crate) mod camera;
 pub(crate) mod cli;
-pub(crate) mod collision;
+pub(crate) mod command_line;
+pub(crate) mod debug;
 pub(crate) mod game;
 pub(crate) mod graphics;
 pub(crate) mod input;
 pub(crate) mod menu;
-pub(crate) mod particle;
-pub(crate) mod player;
 pub(crate) mod replays;
 pub(crate) mod results;
+pub(crate) mod savestates;
+pub(crate) mod set;
 
 #[cfg(feature = "wgpu_renderer")]
 pub(crate) mod wgpu;
@@ -1,15 +1,22 @@
-use pf_sandbox_lib::command_line::CommandLine;
 use pf_sandbox_lib::config::Config;
+use pf_sandbox_lib::history::History;
 use pf_sandbox_lib::input::PlayerInput;
 use pf_sandbox_lib::network::{Netplay, NetplayState};
 use pf_sandbox_lib::package::{Package, PackageMeta, Verify};
 use pf_sandbox_lib::package;
-use crate::game::{GameSetup, GameState, PlayerSetup};
+use pf_sandbox_lib::player::Handicap;
+use pf_sandbox_lib::results::{PlayerResult, TeamResult};
+use pf_sandbox_lib::simulation::PlayerSetup;
+use pf_sandbox_lib::stage_strike::{StageStrike, StrikeTurn};
+use crate::command_line::CommandLine;
+use crate::game::{GameSetup, GameState};
 use crate::graphics::{GraphicsMessage, Render, RenderType};
 use crate::graphics;
 use crate::input::Input;
+use crate::replays::{Replay, ReplayEntry, ReplayFilter};
 use crate::replays;
-use crate::results::{GameResults, PlayerResult};
+use crate::set::MatchSet;
+use crate::results::GameResults;
 
 use treeflection::{Node, NodeRunner, NodeToken};
 use winit::event::VirtualKeyCode;
@@ -43,6 +50,9 @@ pub struct Menu {
     netplay_history:    Vec<NetplayHistory>,
     prev_fighters_len:  usize,
     prev_stages_len:    usize,
+    set:                Option<MatchSet>,
+    set_replays:        Vec<Replay>, // kept out of the MatchSet as it is too large to copy for netplay
+    replay_filter:      ReplayFilter,
 }
 
 pub struct NetplayHistory {
@@ -50,6 +60,7 @@ pub struct NetplayHistory {
     prev_state:         Option<MenuState>,
     fighter_selections: Vec<PlayerSelect>,
     stage_ticker:       Option<MenuTicker>,
+    set:                Option<MatchSet>,
 }
 
 pub enum PackageHolder {
@@ -105,6 +116,9 @@ impl Menu {
             netplay_history:    vec!(),
             prev_fighters_len:  0,
             prev_stages_len:    0,
+            set:                None,
+            set_replays:        vec!(),
+            replay_filter:      ReplayFilter::default(),
         }
     }
 
@@ -117,10 +131,34 @@ impl Menu {
                 self.state = MenuState::NetplayWait { message };
             }
             ResumeMenu::Results (results) => {
+                // Only games started from stage striking are part of the set
+                let in_set = if let MenuState::StageStrike (..) = self.state { self.set.is_some() } else { false };
+                let set_summary = if in_set {
+                    let set = self.set.as_mut().unwrap();
+                    set.add_game(&results, results.replay.selected_stage.clone());
+                    self.set_replays.push(results.replay.clone());
+
+                    let summary = set.summary();
+                    if set.winner().is_some() {
+                        replays::save_set(set, &self.set_replays, self.package.get());
+                        self.set = None;
+                        self.set_replays.clear();
+                    }
+                    Some(summary)
+                } else {
+                    None
+                };
+
                 self.game_results = Some(results);
-                self.prev_state = Some(mem::replace(&mut self.state, MenuState::game_results()));
+                let prev_state = mem::replace(&mut self.state, MenuState::game_results(set_summary));
+                self.prev_state = Some(if in_set { MenuState::character_select() } else { prev_state });
+            }
+            ResumeMenu::Unchanged => {
+                // The stage striking has already completed, so go back to character select to start it again.
+                if let MenuState::StageStrike (..) = self.state {
+                    self.state = MenuState::character_select();
+                }
             }
-            ResumeMenu::Unchanged => { }
         }
     }
 
@@ -151,7 +189,7 @@ impl Menu {
                     self.state = MenuState::NetplayWait { message: String::from("") };
                 }
                 2 => {
-                    self.state = MenuState::replay_select(self.package.get());
+                    self.state = MenuState::replay_select(self.package.get(), &self.replay_filter);
                 }
                 _ => unreachable!()
             }
@@ -163,7 +201,7 @@ impl Menu {
     }
 
     pub fn step_replay_select(&mut self, player_inputs: &[PlayerInput]) {
-        let back = if let &mut MenuState::ReplaySelect (ref replays, ref mut ticker) = &mut self.state {
+        let back = if let &mut MenuState::ReplaySelect (ref mut replays, ref mut ticker, ref mut message) = &mut self.state {
             if player_inputs.iter().any(|x| x[0].stick_y > 0.4 || x[0].up) {
                 ticker.up();
             }
@@ -174,15 +212,38 @@ impl Menu {
                 ticker.reset();
             }
 
-            if (player_inputs.iter().any(|x| x.start.press || x.a.press)) && replays.len() > 0 {
-                let name = &replays[ticker.cursor];
-                match replays::load_replay(name, self.package.get()) {
+            if player_inputs.iter().any(|x| x.x.press || x.y.press || x.z.press) {
+                let entries = replays::get_replay_entries(self.package.get());
+                if player_inputs.iter().any(|x| x.x.press) {
+                    self.replay_filter.cycle_fighter(&entries);
+                }
+                if player_inputs.iter().any(|x| x.y.press) {
+                    self.replay_filter.cycle_stage(&entries);
+                }
+                if player_inputs.iter().any(|x| x.z.press) {
+                    self.replay_filter.cycle_player_tag(&entries);
+                }
+                let filter = &self.replay_filter;
+                *replays = entries.into_iter().filter(|x| filter.matches(&x.metadata)).collect();
+                *ticker = MenuTicker::new(replays.len());
+                false
+            }
+            else if (player_inputs.iter().any(|x| x.start.press || x.a.press)) && replays.len() > 0 {
+                let name = &replays[ticker.cursor].metadata.name;
+                match replays::load_replay(&replays[ticker.cursor].file, self.package.get()) {
                     Ok(replay) => {
+                        if replay.cache.is_none() && !replay.matches_package(self.package.get()) {
+                            warn!("Replay {} was recorded with a different package and has no cache, it may desync", name);
+                        }
+                        let (player_history, stage_history) = match replay.cache {
+                            Some(cache) => (cache.player_history, cache.stage_history),
+                            None        => (History::new(), History::new()),
+                        };
                         self.game_setup = Some(GameSetup {
                             init_seed:      replay.init_seed,
                             input_history:  replay.input_history,
-                            player_history: replay.player_history,
-                            stage_history:  replay.stage_history,
+                            player_history,
+                            stage_history,
                             controllers:    replay.selected_controllers,
                             players:        replay.selected_players,
                             ais:            replay.selected_ais,
@@ -191,7 +252,7 @@ impl Menu {
                         });
                     }
                     Err(error) => {
-                        println!("Failed to load replay: {}\n{}", name, error);
+                        *message = format!("Failed to load replay {}: {}", name, error);
                     }
                 }
                 false
@@ -231,6 +292,7 @@ impl Menu {
                     cpu_ai:          None,
                     ui:              ui,
                     animation_frame: 0,
+                    handicap:        Handicap::default(),
                     team
                 });
             }
@@ -360,10 +422,12 @@ impl Menu {
                             PlayerSelectUi::CpuFighter (_) => {
                                 selection.fighter = None;
                             }
-                            PlayerSelectUi::HumanTeam (_) => {
+                            PlayerSelectUi::HumanTeam (_) |
+                            PlayerSelectUi::HumanHandicap (_) => {
                                 selection.ui = PlayerSelectUi::human_fighter(self.package.get());
                             }
                             PlayerSelectUi::CpuTeam (_) |
+                            PlayerSelectUi::CpuHandicap (_) |
                             PlayerSelectUi::CpuAi (_) => {
                                 selection.ui = PlayerSelectUi::cpu_fighter(self.package.get());
                                 selection.ui = PlayerSelectUi::cpu_fighter(self.package.get());
@@ -381,7 +445,8 @@ impl Menu {
                                 else {
                                     match ticker.cursor - fighters.len() {
                                         0 => { selection.ui = PlayerSelectUi::human_team() }
-                                        1 => { add_cpu = true; }
+                                        1 => { selection.ui = PlayerSelectUi::human_handicap() }
+                                        2 => { add_cpu = true; }
                                         _ => { unreachable!() }
                                     }
                                 }
@@ -394,8 +459,9 @@ impl Menu {
                                 else {
                                     match ticker.cursor - fighters.len() {
                                         0 => { selection.ui = PlayerSelectUi::cpu_team() }
-                                        1 => { /* TODO: selection.ui = PlayerSelectUi::cpu_ai()*/ }
-                                        2 => { remove_cpu = Some(selection_i); }
+                                        1 => { selection.ui = PlayerSelectUi::cpu_handicap() }
+                                        2 => { /* TODO: selection.ui = PlayerSelectUi::cpu_ai()*/ }
+                                        3 => { remove_cpu = Some(selection_i); }
                                         _ => { unreachable!() }
                                     }
                                 }
@@ -422,6 +488,20 @@ impl Menu {
                                     }
                                 }
                             }
+                            PlayerSelectUi::HumanHandicap (ticker) => {
+                                if ticker.cursor < HANDICAP_FIELDS {
+                                    Menu::cycle_handicap(&mut selection.handicap, ticker.cursor);
+                                } else {
+                                    selection.ui = PlayerSelectUi::human_fighter(self.package.get());
+                                }
+                            }
+                            PlayerSelectUi::CpuHandicap (ticker) => {
+                                if ticker.cursor < HANDICAP_FIELDS {
+                                    Menu::cycle_handicap(&mut selection.handicap, ticker.cursor);
+                                } else {
+                                    selection.ui = PlayerSelectUi::cpu_fighter(self.package.get());
+                                }
+                            }
                             PlayerSelectUi::CpuAi (_) => { }
                             PlayerSelectUi::HumanUnplugged => unreachable!(),
                         }
@@ -432,6 +512,8 @@ impl Menu {
                         PlayerSelectUi::CpuFighter   (ref mut ticker) |
                         PlayerSelectUi::HumanTeam    (ref mut ticker) |
                         PlayerSelectUi::CpuTeam      (ref mut ticker) |
+                        PlayerSelectUi::HumanHandicap (ref mut ticker) |
+                        PlayerSelectUi::CpuHandicap  (ref mut ticker) |
                         PlayerSelectUi::CpuAi        (ref mut ticker) => {
                             if input[0].stick_y > 0.4 || input[0].up {
                                 ticker.up();
@@ -464,6 +546,7 @@ impl Menu {
                         cpu_ai:          None,
                         ui:              PlayerSelectUi::cpu_fighter(self.package.get()),
                         animation_frame: 0,
+                        handicap:        Handicap::default(),
                         team
                     });
                 }
@@ -491,6 +574,106 @@ impl Menu {
 
         if let Some(state) = new_state {
             self.state = state;
+            if let MenuState::StageSelect = self.state {
+                if self.package.get().rules.best_of > 1 {
+                    self.stage_strike(netplay);
+                }
+            }
+        }
+    }
+
+    /// Starts a new set if the players have changed since the last game and moves to stage striking
+    fn stage_strike(&mut self, netplay: &Netplay) {
+        let (players, controllers, _, inputs) = self.selected_players();
+        let rules = &self.package.get().rules;
+        let pools: Vec<usize> = if rules.teams_enabled() {
+            players.iter().map(|x| x.team).collect()
+        } else {
+            (0..players.len()).collect()
+        };
+
+        if !self.set.as_ref().map(|x| x.same_players(&controllers, &pools)).unwrap_or(false) {
+            self.set = Some(MatchSet::new(rules, controllers, inputs, pools));
+            self.set_replays.clear();
+        }
+
+        // The state must remain StageStrike while the game is played so the results are added to the set
+        let set = self.set.as_ref().unwrap();
+        let package = self.package.get();
+        let strike = StageStrike::new(&set.games, &package.rules, package.stages.keys());
+        let turn = strike.turn(&set.games, &set.pool_order());
+        let ticker = MenuTicker::new(strike.stages.len());
+        self.state = MenuState::StageStrike (strike, ticker);
+        if let StrikeTurn::Done { stage } = turn {
+            self.game_setup(netplay, stage);
+        }
+    }
+
+    fn step_stage_strike(&mut self, player_inputs: &[PlayerInput], netplay: &Netplay) {
+        let mut stage = None;
+        let mut back = false;
+        if let &mut MenuState::StageStrike (ref mut strike, ref mut ticker) = &mut self.state {
+            let set = self.set.as_ref().unwrap();
+            let pools = set.pool_order();
+            let turn = strike.turn(&set.games, &pools);
+            let pool = match turn {
+                StrikeTurn::Strike { pool } |
+                StrikeTurn::Pick   { pool } => pool,
+                StrikeTurn::Done   { .. }   => 0,
+            };
+
+            // Only the players whose turn it is can strike or pick, unless they are all CPUs
+            let pool_inputs = set.pool_inputs(pool);
+            let acting_inputs: Vec<&PlayerInput> = player_inputs.iter().enumerate()
+                .filter(|x| pool_inputs.len() == 0 || pool_inputs.contains(&x.0))
+                .map(|x| x.1)
+                .collect();
+
+            if acting_inputs.iter().any(|x| x[0].stick_y > 0.4 || x[0].up) {
+                ticker.up();
+            }
+            else if acting_inputs.iter().any(|x| x[0].stick_y < -0.4 || x[0].down) {
+                ticker.down();
+            }
+            else {
+                ticker.reset();
+            }
+
+            if let StrikeTurn::Done { stage: done } = turn {
+                stage = Some(done);
+            }
+            else if acting_inputs.iter().any(|x| x.a.press) {
+                stage = strike.select(&set.games, &pools, ticker.cursor);
+            }
+            else if player_inputs.iter().any(|x| x.b.press) {
+                back = true;
+            }
+        } else { unreachable!() }
+
+        if let Some(stage) = stage {
+            self.game_setup(netplay, stage);
+        }
+        else if back {
+            self.state = MenuState::character_select();
+        }
+    }
+
+    /// Advances the handicap field at the cursor to its next value, wrapping back to the smallest value
+    fn cycle_handicap(handicap: &mut Handicap, field: usize) {
+        fn next_ratio(ratio: f32) -> f32 {
+            let next = ratio + 0.25;
+            if next > 2.0 { 0.5 } else { next }
+        }
+        match field {
+            0 => {
+                let next = handicap.starting_damage + 10.0;
+                handicap.starting_damage = if next > 150.0 { 0.0 } else { next };
+            }
+            1 => { handicap.damage_taken_ratio    = next_ratio(handicap.damage_taken_ratio) }
+            2 => { handicap.damage_dealt_ratio    = next_ratio(handicap.damage_dealt_ratio) }
+            3 => { handicap.knockback_taken_ratio = next_ratio(handicap.knockback_taken_ratio) }
+            4 => { handicap.extra_stocks          = (handicap.extra_stocks + 1) % 6 }
+            _ => { unreachable!() }
         }
     }
 
@@ -521,27 +704,32 @@ impl Menu {
         }
 
         if (player_inputs.iter().any(|x| x.start.press || x.a.press)) && self.package.get().stages.len() > 0 {
-            self.game_setup(netplay);
+            let stage = self.package.get().stages.index_to_key(ticker.cursor).unwrap();
+            self.game_setup(netplay, stage);
         }
         else if player_inputs.iter().any(|x| x.b.press) {
             self.state = MenuState::character_select();
         }
     }
 
-    pub fn game_setup(&mut self, netplay: &Netplay) {
+    /// Returns the players, controllers, ais and the input index of each human player for the current fighter selections
+    fn selected_players(&self) -> (Vec<PlayerSetup>, Vec<usize>, Vec<usize>, Vec<Option<usize>>) {
         let mut players: Vec<PlayerSetup> = vec!();
         let mut controllers: Vec<usize> = vec!();
         let mut ais: Vec<usize> = vec!();
+        let mut inputs: Vec<Option<usize>> = vec!();
         let mut ais_skipped = 0;
         for (i, selection) in (&self.fighter_selections).iter().enumerate() {
             // add human players
             if selection.ui.is_human_plugged_in() {
                 if let Some(fighter) = selection.fighter {
                     players.push(PlayerSetup {
-                        fighter: self.package.get().fighters.index_to_key(fighter).unwrap(),
-                        team:    selection.team,
+                        fighter:  self.package.get().fighters.index_to_key(fighter).unwrap(),
+                        team:     selection.team,
+                        handicap: selection.handicap.clone(),
                     });
                     controllers.push(i);
+                    inputs.push(Some(i));
                 }
             }
 
@@ -550,10 +738,12 @@ impl Menu {
                 if selection.fighter.is_some() /* && selection.cpu.is_some() TODO */ {
                     let fighter = selection.fighter.unwrap();
                     players.push(PlayerSetup {
-                        fighter: self.package.get().fighters.index_to_key(fighter).unwrap(),
-                        team:    selection.team,
+                        fighter:  self.package.get().fighters.index_to_key(fighter).unwrap(),
+                        team:     selection.team,
+                        handicap: selection.handicap.clone(),
                     });
                     controllers.push(i - ais_skipped);
+                    inputs.push(None);
                     ais.push(0); // TODO: delete this
                     // ais.push(selection.cpu_ai.unwrap()); TODO: add this
                 }
@@ -562,15 +752,18 @@ impl Menu {
                 }
             }
         }
+        (players, controllers, ais, inputs)
+    }
 
-        let stage = self.package.get().stages.index_to_key(self.stage_ticker.as_ref().unwrap().cursor).unwrap();
+    pub fn game_setup(&mut self, netplay: &Netplay, stage: String) {
+        let (players, controllers, ais, _) = self.selected_players();
         let state = if netplay.number_of_peers() == 1 { GameState::Local } else { GameState::Netplay };
         let init_seed = netplay.get_seed().unwrap_or(GameSetup::gen_seed());
 
         self.game_setup = Some(GameSetup {
             input_history:  vec!(),
-            player_history: vec!(),
-            stage_history:  vec!(),
+            player_history: History::new(),
+            stage_history:  History::new(),
             init_seed,
             controllers,
             ais,
@@ -632,6 +825,8 @@ impl Menu {
         self.state = MenuState::GameSelect;
         self.fighter_selections = vec!();
         self.stage_ticker = None;
+        self.set = None;
+        self.set_replays.clear();
 
         // remember selection
         self.config.current_package = Some(self.package.get().meta.folder_name());
@@ -770,7 +965,7 @@ impl Menu {
         if !netplay.skip_frame() {
             self.current_frame += 1;
 
-            let start = self.current_frame - netplay.frames_to_step();
+            let start = self.current_frame.saturating_sub(netplay.frames_to_step());
             let end = self.current_frame;
 
             self.netplay_history.truncate(start);
@@ -780,9 +975,10 @@ impl Menu {
                 self.prev_state         = history.prev_state.clone();
                 self.fighter_selections = history.fighter_selections.clone();
                 self.stage_ticker       = history.stage_ticker.clone();
+                self.set                = history.set.clone();
             }
 
-            input.netplay_update();
+            input.netplay_update(self.current_frame, netplay);
 
             for frame in start..end {
                 if let NetplayState::Disconnected { reason } = netplay.state() {
@@ -795,10 +991,11 @@ impl Menu {
                 if frame > 1 {
                     match self.state {
                         MenuState::GameSelect           => self.step_game_select   (&player_inputs, netplay),
-                        MenuState::ReplaySelect (_, _)  => self.step_replay_select (&player_inputs),
+                        MenuState::ReplaySelect (..)    => self.step_replay_select (&player_inputs),
                         MenuState::PackageSelect (_, _) => self.step_package_select(&player_inputs),
                         MenuState::CharacterSelect {..} => self.step_fighter_select(&player_inputs, netplay),
                         MenuState::StageSelect          => self.step_stage_select  (&player_inputs, netplay),
+                        MenuState::StageStrike (_, _)   => self.step_stage_strike  (&player_inputs, netplay),
                         MenuState::GameResults {..}     => self.step_results       (&player_inputs),
                         MenuState::NetplayWait {..}     => self.step_netplay_wait  (&player_inputs, netplay),
                     };
@@ -809,6 +1006,7 @@ impl Menu {
                     prev_state:         self.prev_state.clone(),
                     fighter_selections: self.fighter_selections.clone(),
                     stage_ticker:       self.stage_ticker.clone(),
+                    set:                self.set.clone(),
                 });
             }
         }
@@ -828,12 +1026,33 @@ impl Menu {
                         self.package_loader.as_ref().map(|x| x.state.message()).unwrap_or_default()
                     )
                 }
-                MenuState::GameResults { replay_saved } => RenderMenuState::GameResults { results: self.game_results.as_ref().unwrap().player_results.clone(), replay_saved },
+                MenuState::GameResults { replay_saved, ref set_summary } => {
+                    let game_results = self.game_results.as_ref().unwrap();
+                    RenderMenuState::GameResults {
+                        results:      game_results.player_results.clone(),
+                        team_results: game_results.team_results.clone(),
+                        set_summary:  set_summary.clone(),
+                        replay_saved
+                    }
+                }
                 MenuState::CharacterSelect { back_counter, .. } => RenderMenuState::CharacterSelect (self.fighter_selections.clone(), back_counter, self.back_counter_max),
-                MenuState::ReplaySelect (ref replays, ref ticker) => RenderMenuState::ReplaySelect (replays.clone(), ticker.cursor),
+                MenuState::ReplaySelect (ref replays, ref ticker, ref message) => {
+                    let descriptions = replays.iter().map(|x| x.metadata.description()).collect();
+                    RenderMenuState::ReplaySelect (descriptions, ticker.cursor, self.replay_filter.description(), message.clone())
+                }
                 MenuState::NetplayWait { ref message } => RenderMenuState::GenericText (message.clone()),
                 MenuState::GameSelect  => RenderMenuState::GameSelect  (self.game_ticker.cursor),
                 MenuState::StageSelect => RenderMenuState::StageSelect (self.stage_ticker.as_ref().unwrap().cursor),
+                MenuState::StageStrike (ref strike, ref ticker) => {
+                    let set = self.set.as_ref().unwrap();
+                    let stages = strike.stages.iter().map(|x| RenderStrikeStage {
+                        key:      x.key.clone(),
+                        name:     self.package.get().stages.key_to_value(&x.key).map(|x| x.name.clone()).unwrap_or_default(),
+                        pickable: x.selectable(),
+                        struck:   x.struck,
+                    }).collect();
+                    RenderMenuState::StageStrike { stages, selection: ticker.cursor, message: set.strike_message(strike), set_summary: set.summary() }
+                }
             },
             package_verify: self.package.verify(),
         }
@@ -870,6 +1089,78 @@ impl Menu {
     }
 }
 
+impl Menu {
+    fn replay_command(&mut self, action: &str, args: &[String]) -> String {
+        let package = if let &PackageHolder::Package (ref package, _) = &self.package {
+            package
+        } else {
+            return String::from("No package is loaded.");
+        };
+
+        if action == "filter_replays" {
+            let value = if args.len() > 1 { Some(args[1..].join(" ")) } else { None };
+            match (args.get(0).map(|x| x.as_str()), value) {
+                (Some("clear"), _)                => self.replay_filter = ReplayFilter::default(),
+                (Some("fighter"), Some(value))    => self.replay_filter.fighter = Some(value),
+                (Some("stage"), Some(value))      => self.replay_filter.stage = Some(value),
+                (Some("player_tag"), Some(value)) => self.replay_filter.player_tag = Some(value),
+                (Some("tag"), Some(value))        => self.replay_filter.tag = Some(value),
+                (Some(field), None) if ["fighter", "stage", "player_tag", "tag"].contains(&field) => return String::from("Didn't specify a value"),
+                (Some(field), _) => return format!("Cannot filter by '{}', must be one of: fighter, stage, player_tag, tag", field),
+                (None, _)        => return String::from("Didn't specify a field"),
+            }
+            if let MenuState::ReplaySelect (..) = self.state {
+                self.state = MenuState::replay_select(package, &self.replay_filter);
+            }
+            return format!("Filtering replays by: {}", self.replay_filter.description());
+        }
+
+        let entries = replays::get_filtered_replay_entries(package, &self.replay_filter);
+        if action == "list_replays" {
+            if entries.is_empty() {
+                return String::from("There are no replays");
+            }
+            return entries.iter().enumerate().map(|(i, x)| format!("{}: {}", i, x.metadata.description())).collect::<Vec<_>>().join("\n");
+        }
+
+        let entry = match args.get(0).map(|x| x.parse::<usize>()) {
+            Some(Ok(index)) => match entries.get(index) {
+                Some(entry) => entry,
+                None        => return format!("There is no replay {}", index),
+            }
+            Some(Err(_)) => return format!("Index must be a number, was '{}'", args[0]),
+            None         => return String::from("Didn't specify a replay"),
+        };
+        let file = &entry.file;
+        let name = &entry.metadata.name;
+        let value = args[1..].join(" ");
+
+        let result = match action {
+            "delete_replay" => replays::delete_replay(file, package).map(|_| format!("Deleted replay {}", name)),
+            _ if value.is_empty() => Err(String::from("Didn't specify a value")),
+            "rename_replay" => replays::rename_replay(file, &value, package).map(|_| format!("Renamed replay {} to {}", name, value)),
+            "tag_replay"    => replays::tag_replay(file, &value, package).map(|_| format!("Tagged replay {} with '{}'", name, value)),
+            "untag_replay"  => replays::untag_replay(file, &value, package).map(|_| format!("Removed tag '{}' from replay {}", value, name)),
+            _ => {
+                let player = match args[1].parse::<usize>() {
+                    Ok(player) => player,
+                    Err(_)     => return format!("Player must be a number, was '{}'", args[1]),
+                };
+                let tag = args[2..].join(" ");
+                replays::set_player_tag(file, player, &tag, package).map(|_| format!("Set the tag of player {} in replay {} to '{}'", player, name, tag))
+            }
+        };
+
+        if let MenuState::ReplaySelect (..) = self.state {
+            self.state = MenuState::replay_select(package, &self.replay_filter);
+        }
+        match result {
+            Ok(message) => message,
+            Err(err)    => format!("Failed to {} {}: {}", action.replace("_", " "), name, err),
+        }
+    }
+}
+
 impl Node for Menu {
     fn node_step(&mut self, mut runner: NodeRunner) -> String {
         let result = match runner.step() {
@@ -890,8 +1181,20 @@ impl Node for Menu {
 Menu Help
 
 Commands:
-*   help               - display this help
-*   open_package $name - loads the package with the given folder name, if it doesnt exist it is created.
+*   help                                 - display this help
+*   open_package $name                   - loads the package with the given folder name, if it doesnt exist it is created.
+*   compact_replay $name                 - converts the replay to the compact format, which is resimulated from its inputs during playback
+*   cache_replay $name                   - converts the replay to the cached format, which plays back unchanged after the package is modified
+*   replay_sizes                         - compares the size of every replay in the compact and cached formats
+*   verify_replays                       - resimulates every replay and reports the first frame each replay diverges from its checksums and cache
+*   list_replays                         - list the replays that match the filter, the listed index is used by the following commands
+*   filter_replays $field $value         - only list replays where $field (fighter, stage, player_tag or tag) matches $value
+*   filter_replays clear                 - list every replay
+*   rename_replay $index $name           - change the name the replay is displayed with
+*   tag_replay $index $tag               - add a tag to the replay
+*   untag_replay $index $tag             - remove a tag from the replay
+*   set_player_tag $index $player $tag   - set the tag of a player in the replay
+*   delete_replay $index                 - delete the replay
 
 Accessors:
 *   .package - Package"#)
@@ -914,6 +1217,31 @@ Accessors:
                             format!("Didn't specify a package")
                         }
                     }
+                    "compact_replay" | "cache_replay" | "replay_sizes" | "verify_replays" => {
+                        let package = if let &PackageHolder::Package (ref package, _) = &self.package {
+                            package
+                        } else {
+                            return String::from("No package is loaded.");
+                        };
+
+                        let name = args.join(" ");
+                        match action.as_ref() {
+                            "replay_sizes"   => replays::replay_sizes(package),
+                            "verify_replays" => replays::verify_replays(package).report(),
+                            _ if name.is_empty() => String::from("Didn't specify a replay"),
+                            "compact_replay" => match replays::compact_replay(&name, package) {
+                                Ok(())   => format!("Converted replay {} to the compact format", name),
+                                Err(err) => format!("Failed to convert replay {}: {}", name, err),
+                            }
+                            _ => match replays::cache_replay(&name, package) {
+                                Ok(())   => format!("Converted replay {} to the cached format", name),
+                                Err(err) => format!("Failed to convert replay {}: {}", name, err),
+                            }
+                        }
+                    }
+                    "list_replays" | "filter_replays" | "rename_replay" | "tag_replay" | "untag_replay" | "set_player_tag" | "delete_replay" => {
+                        self.replay_command(&action, &args)
+                    }
                     _ => {
                         format!("Menu cannot '{}'", action)
                     }
@@ -928,10 +1256,11 @@ Accessors:
 #[derive(Clone)]
 pub enum MenuState {
     GameSelect,
-    ReplaySelect (Vec<String>, MenuTicker), // MenuTicker must be tied with the Vec<String>, otherwise they may become out of sync
+    ReplaySelect (Vec<ReplayEntry>, MenuTicker, String), // MenuTicker must be tied with the Vec<ReplayEntry>, otherwise they may become out of sync. The String is the error from the last replay that failed to load
     CharacterSelect { back_counter: usize },
     StageSelect,
-    GameResults { replay_saved: bool },
+    StageStrike (StageStrike, MenuTicker), // MenuTicker must be tied with the StageStrike, otherwise they may become out of sync
+    GameResults { replay_saved: bool, set_summary: Option<String> },
     PackageSelect (Vec<(String, PackageMeta)>, MenuTicker),
     NetplayWait { message: String },
 }
@@ -943,18 +1272,18 @@ impl MenuState {
         MenuState::PackageSelect(packages, ticker)
     }
 
-    pub fn replay_select(package: &Package) -> MenuState {
-        let replays = replays::get_replay_names(package);
+    pub fn replay_select(package: &Package, filter: &ReplayFilter) -> MenuState {
+        let replays = replays::get_filtered_replay_entries(package, filter);
         let ticker = MenuTicker::new(replays.len());
-        MenuState::ReplaySelect (replays, ticker)
+        MenuState::ReplaySelect (replays, ticker, String::new())
     }
 
     pub fn character_select() -> MenuState {
         MenuState::CharacterSelect { back_counter: 0 }
     }
 
-    pub fn game_results() -> MenuState {
-        MenuState::GameResults { replay_saved: false }
+    pub fn game_results(set_summary: Option<String>) -> MenuState {
+        MenuState::GameResults { replay_saved: false, set_summary }
     }
 }
 
@@ -993,20 +1322,32 @@ impl PackageLoadState {
 
 pub enum RenderMenuState {
     GameSelect      (usize),
-    ReplaySelect    (Vec<String>, usize),
+    ReplaySelect    (Vec<String>, usize, String, String), // replay descriptions, selection, filter description, error message
     CharacterSelect (Vec<PlayerSelect>, usize, usize),
     StageSelect     (usize),
-    GameResults     { results: Vec<PlayerResult>, replay_saved: bool },
+    StageStrike     { stages: Vec<RenderStrikeStage>, selection: usize, message: String, set_summary: String },
+    GameResults     { results: Vec<PlayerResult>, team_results: Vec<TeamResult>, set_summary: Option<String>, replay_saved: bool },
     PackageSelect   (Vec<String>, usize, String),
     GenericText     (String),
 }
 
+pub struct RenderStrikeStage {
+    pub key:      String,
+    pub name:     String,
+    pub pickable: bool,
+    pub struck:   bool,
+}
+
+/// Number of editable fields in the handicap menu, the menu also has a trailing return option
+pub const HANDICAP_FIELDS: usize = 5;
+
 #[derive(Clone)]
 pub struct PlayerSelect {
     pub controller:      Option<(usize, MenuTicker)>, // the cursor of the ticker is ignored
     pub fighter:         Option<usize>,
     pub cpu_ai:          Option<usize>,
     pub team:            usize,
+    pub handicap:        Handicap,
     pub ui:              PlayerSelectUi,
     pub animation_frame: usize,
 }
@@ -1021,11 +1362,13 @@ impl PlayerSelect {
 #[derive(Clone)]
 #[allow(dead_code)]
 pub enum PlayerSelectUi {
-    CpuAi        (MenuTicker),
-    CpuFighter   (MenuTicker),
-    CpuTeam      (MenuTicker),
-    HumanFighter (MenuTicker),
-    HumanTeam    (MenuTicker),
+    CpuAi         (MenuTicker),
+    CpuFighter    (MenuTicker),
+    CpuTeam       (MenuTicker),
+    CpuHandicap   (MenuTicker),
+    HumanFighter  (MenuTicker),
+    HumanTeam     (MenuTicker),
+    HumanHandicap (MenuTicker),
     HumanUnplugged,
 }
 
@@ -1036,11 +1379,11 @@ impl PlayerSelectUi {
     }
 
     pub fn cpu_fighter(package: &Package) -> Self {
-        PlayerSelectUi::CpuFighter (MenuTicker::new(package.fighters.len() + 3))
+        PlayerSelectUi::CpuFighter (MenuTicker::new(package.fighters.len() + 4))
     }
 
     pub fn human_fighter(package: &Package) -> Self {
-        PlayerSelectUi::HumanFighter (MenuTicker::new(package.fighters.len() + 2))
+        PlayerSelectUi::HumanFighter (MenuTicker::new(package.fighters.len() + 3))
     }
 
     pub fn cpu_team() -> Self {
@@ -1051,6 +1394,14 @@ impl PlayerSelectUi {
         PlayerSelectUi::HumanTeam (MenuTicker::new(graphics::get_colors().len() + 1))
     }
 
+    pub fn cpu_handicap() -> Self {
+        PlayerSelectUi::CpuHandicap (MenuTicker::new(HANDICAP_FIELDS + 1))
+    }
+
+    pub fn human_handicap() -> Self {
+        PlayerSelectUi::HumanHandicap (MenuTicker::new(HANDICAP_FIELDS + 1))
+    }
+
     pub fn is_visible(&self) -> bool {
         match self {
             &PlayerSelectUi::HumanUnplugged => false,
@@ -1062,7 +1413,8 @@ impl PlayerSelectUi {
         match self {
             &PlayerSelectUi::CpuAi (_) |
             &PlayerSelectUi::CpuFighter (_) |
-            &PlayerSelectUi::CpuTeam (_) => true,
+            &PlayerSelectUi::CpuTeam (_) |
+            &PlayerSelectUi::CpuHandicap (_) => true,
             _                            => false
         }
     }
@@ -1070,8 +1422,9 @@ impl PlayerSelectUi {
     pub fn is_human_plugged_in(&self) -> bool {
         match self {
             &PlayerSelectUi::HumanFighter (_) |
-            &PlayerSelectUi::HumanTeam (_) => true,
-            _                              => false
+            &PlayerSelectUi::HumanTeam (_) |
+            &PlayerSelectUi::HumanHandicap (_) => true,
+            _                                  => false
         }
     }
 
@@ -1082,6 +1435,8 @@ impl PlayerSelectUi {
             &PlayerSelectUi::CpuFighter   (ref ticker) |
             &PlayerSelectUi::HumanTeam    (ref ticker) |
             &PlayerSelectUi::CpuTeam      (ref ticker) |
+            &PlayerSelectUi::HumanHandicap (ref ticker) |
+            &PlayerSelectUi::CpuHandicap  (ref ticker) |
             &PlayerSelectUi::CpuAi        (ref ticker) => { ticker }
             &PlayerSelectUi::HumanUnplugged => {
                 panic!("Tried to unwrap the PlayerSelectUi ticker but was HumanUnplugged")
@@ -1095,6 +1450,8 @@ impl PlayerSelectUi {
             &mut PlayerSelectUi::CpuFighter   (ref mut ticker) |
             &mut PlayerSelectUi::HumanTeam    (ref mut ticker) |
             &mut PlayerSelectUi::CpuTeam      (ref mut ticker) |
+            &mut PlayerSelectUi::HumanHandicap (ref mut ticker) |
+            &mut PlayerSelectUi::CpuHandicap  (ref mut ticker) |
             &mut PlayerSelectUi::CpuAi        (ref mut ticker) => {
                 ticker.reset();
                 ticker.cursor = 0;
@@ -1,21 +1,83 @@
 use std::fs;
-use std::path::PathBuf;
-use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
 
 use chrono::{Local, DateTime};
+use serde_json::Value;
+use serde_json;
 
+use pf_sandbox_lib::divergence::Divergence;
 use pf_sandbox_lib::files;
+use pf_sandbox_lib::history::History;
 use pf_sandbox_lib::input::ControllerInput;
+use pf_sandbox_lib::json_upgrade;
 use pf_sandbox_lib::package::Package;
+use pf_sandbox_lib::player::Player;
+use pf_sandbox_lib::simulation::{self, Simulation, SimulationSetup, PlayerSetup};
 use pf_sandbox_lib::stage::Stage;
-use crate::game::{Game, PlayerSetup};
+use crate::game::Game;
 use crate::input::Input;
-use crate::player::Player;
+use crate::set::MatchSet;
 
-pub fn get_replay_names(package: &Package) -> Vec<String> {
+/// Returns the replays of the package, most recent first.
+/// The index is used to avoid loading every replay, replays missing from the index are loaded and added to it.
+pub fn get_replay_entries(package: &Package) -> Vec<ReplayEntry> {
+    let files = get_replay_files(package);
+    let mut index = load_index(package);
+    let mut changed = false;
+
+    let prev_len = index.entries.len();
+    index.entries.retain(|x| files.contains(&x.file));
+    changed |= index.entries.len() != prev_len;
+
+    for file in files {
+        if !index.entries.iter().any(|x| x.file == file) {
+            match load_replay(&file, package) {
+                Ok(replay) => {
+                    index.entries.push(ReplayEntry::new(&file, &replay));
+                    changed = true;
+                }
+                Err(err) => warn!("Replay {} could not be added to the index: {}", file, err)
+            }
+        }
+    }
+
+    if changed {
+        save_index(&index, package);
+    }
+
+    let mut entries = index.entries;
+    entries.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).reverse());
+    entries
+}
+
+/// Returns the replays of the package that match the filter, most recent first
+pub fn get_filtered_replay_entries(package: &Package, filter: &ReplayFilter) -> Vec<ReplayEntry> {
+    get_replay_entries(package).into_iter().filter(|x| filter.matches(&x.metadata)).collect()
+}
+
+/// Returns the file of every replay in the package, including replays that are part of a set
+fn get_replay_files(package: &Package) -> Vec<String> {
+    let replays_path = get_replays_dir_path(package);
+    let mut result: Vec<String> = get_zip_names(&replays_path);
+
+    let mut sets_path = replays_path;
+    sets_path.push("sets");
+    if let Ok(sets) = fs::read_dir(sets_path) {
+        for set in sets {
+            if let Ok(set) = set {
+                let set_name = set.file_name().into_string().unwrap();
+                for name in get_zip_names(&set.path()) {
+                    result.push(format!("sets/{}/{}", set_name, name));
+                }
+            }
+        }
+    }
+    result
+}
+
+fn get_zip_names(path: &Path) -> Vec<String> {
     let mut result: Vec<String> = vec!();
-    
-    if let Ok(files) = fs::read_dir(get_replays_dir_path(package)) {
+    if let Ok(files) = fs::read_dir(path) {
         for file in files {
             if let Ok(file) = file {
                 let file_name = file.file_name().into_string().unwrap();
@@ -28,29 +90,6 @@ pub fn get_replay_names(package: &Package) -> Vec<String> {
             }
         }
     }
-
-    // Most recent dates come first
-    // Dates come before non-dates
-    // Non-dates are sorted alphabetically
-    result.sort_by(
-        |a, b| {
-            let a_dt = DateTime::parse_from_rfc2822(a);
-            let b_dt = DateTime::parse_from_rfc2822(b);
-            if a_dt.is_err() && b_dt.is_err() {
-                a.cmp(b)
-            } else {
-                if let Ok(a_dt) = a_dt {
-                    if let Ok(b_dt) = b_dt {
-                        a_dt.cmp(&b_dt).reverse()
-                    } else {
-                        Ordering::Less
-                    }
-                } else {
-                    Ordering::Greater
-                }
-            }
-        }
-    );
     result
 }
 
@@ -61,52 +100,598 @@ fn get_replays_dir_path(package: &Package) -> PathBuf {
     replays_path
 }
 
-fn get_replay_path(package: &Package, name: &str) -> PathBuf {
+fn get_replay_path(package: &Package, file: &str) -> PathBuf {
     let mut replay_path = get_replays_dir_path(package);
-    replay_path.push(format!("{}.zip", name));
+    replay_path.push(format!("{}.zip", file));
     replay_path
 }
 
-pub fn load_replay(name: &str, package: &Package) -> Result<Replay, String> {
-    let replay_path = get_replay_path(package, name);
-    files::load_struct_compressed(replay_path)
+fn get_index_path(package: &Package) -> PathBuf {
+    let mut index_path = get_replays_dir_path(package);
+    index_path.push("index.json");
+    index_path
+}
+
+/// An index that is missing or from an older version is rebuilt from the replays
+fn load_index(package: &Package) -> ReplayIndex {
+    files::load_struct(get_index_path(package)).unwrap_or_default()
+}
+
+fn save_index(index: &ReplayIndex, package: &Package) {
+    files::save_struct(get_index_path(package), index);
+}
+
+/// Adds or replaces the entry of the replay in the index
+fn update_index(file: &str, replay: &Replay, package: &Package) {
+    let mut index = load_index(package);
+    let entry = ReplayEntry::new(file, replay);
+    match index.entries.iter_mut().find(|x| x.file == file) {
+        Some(existing) => *existing = entry,
+        None           => index.entries.push(entry),
+    }
+    save_index(&index, package);
+}
+
+/// Returns a file name derived from the timestamp that is not used by another replay
+fn unique_file(package: &Package, dir: &str, timestamp: &DateTime<Local>) -> String {
+    let base = format!("{}{}", dir, timestamp.format("%Y-%m-%d_%H-%M-%S"));
+    let mut file = base.clone();
+    let mut i = 2;
+    while get_replay_path(package, &file).exists() || get_replays_dir_path(package).join(&file).exists() {
+        file = format!("{}_{}", base, i);
+        i += 1;
+    }
+    file
+}
+
+/// Replays saved by older versions of PF Sandbox are upgraded to the current format.
+/// The cache is dropped if it was stored by a different engine version, as the players and stages it contains cannot be upgraded.
+pub fn load_replay(file: &str, package: &Package) -> Result<Replay, String> {
+    let replay_path = get_replay_path(package, file);
+    if !replay_path.exists() {
+        return Err(format!("The replay {} does not exist", file));
+    }
+    let mut json: Value = files::load_struct_compressed(replay_path)?;
+    json_upgrade::upgrade_to_latest_replay(&mut json)?;
+    let mut replay: Replay = serde_json::from_value(json).map_err(|x| format!("The replay could not be upgraded: {}", x))?;
+
+    if replay.cache.is_some() && replay.header.engine_version != json_upgrade::engine_version() {
+        warn!("Replay {} was cached by engine version {} instead of {}, it is resimulated from its inputs instead", file, replay.header.engine_version, json_upgrade::engine_version());
+        replay.cache = None;
+    }
+    Ok(replay)
 }
 
 pub fn save_replay(replay: &Replay, package: &Package) {
-    let replay_path = get_replay_path(package, replay.timestamp.to_rfc2822().as_ref()); // TODO: could still collide under strange circumstances: check and handle
-    files::save_struct_compressed(replay_path, &replay);
+    let file = unique_file(package, "", &replay.timestamp);
+    save_replay_file(replay, &file, package);
+}
+
+/// Saves the replay over the passed file and updates its entry in the index
+pub fn save_replay_file(replay: &Replay, file: &str, package: &Package) {
+    files::save_struct_compressed(get_replay_path(package, file), &replay);
+    update_index(file, replay, package);
+}
+
+/// Loads the replay, modifies it and saves it back to the same file
+fn modify_replay<F>(file: &str, package: &Package, modify: F) -> Result<(), String> where F: FnOnce(&mut Replay) -> Result<(), String> {
+    let mut replay = load_replay(file, package)?;
+    modify(&mut replay)?;
+    save_replay_file(&replay, file, package);
+    Ok(())
 }
 
+pub fn rename_replay(file: &str, name: &str, package: &Package) -> Result<(), String> {
+    modify_replay(file, package, |replay| {
+        replay.metadata.name = name.to_string();
+        Ok(())
+    })
+}
+
+pub fn tag_replay(file: &str, tag: &str, package: &Package) -> Result<(), String> {
+    modify_replay(file, package, |replay| {
+        if !replay.metadata.tags.iter().any(|x| x == tag) {
+            replay.metadata.tags.push(tag.to_string());
+        }
+        Ok(())
+    })
+}
+
+pub fn untag_replay(file: &str, tag: &str, package: &Package) -> Result<(), String> {
+    modify_replay(file, package, |replay| {
+        if !replay.metadata.tags.iter().any(|x| x == tag) {
+            return Err(format!("The replay is not tagged '{}'", tag));
+        }
+        replay.metadata.tags.retain(|x| x != tag);
+        Ok(())
+    })
+}
+
+pub fn set_player_tag(file: &str, player: usize, tag: &str, package: &Package) -> Result<(), String> {
+    modify_replay(file, package, |replay| {
+        match replay.metadata.player_tags.get_mut(player) {
+            Some(player_tag) => {
+                *player_tag = tag.to_string();
+                Ok(())
+            }
+            None => Err(format!("The replay has no player {}", player))
+        }
+    })
+}
+
+pub fn delete_replay(file: &str, package: &Package) -> Result<(), String> {
+    fs::remove_file(get_replay_path(package, file)).map_err(|x| format!("{}", x))?;
+    let mut index = load_index(package);
+    index.entries.retain(|x| x.file != file);
+    save_index(&index, package);
+    Ok(())
+}
+
+/// Converts the replay to the compact format, dropping the cache
+pub fn compact_replay(name: &str, package: &Package) -> Result<(), String> {
+    let replay = load_replay(name, package)?;
+    save_replay_file(&replay.compact(), name, package);
+    Ok(())
+}
+
+/// Converts the replay to the cached format by resimulating it with the package
+pub fn cache_replay(name: &str, package: &Package) -> Result<(), String> {
+    let replay = load_replay(name, package)?;
+    save_replay_file(&replay.cached(package)?, name, package);
+    Ok(())
+}
+
+/// Compares the file size of every replay of the package when stored in the compact and cached formats.
+/// Replays that fail to load are listed rather than skipped so the totals are not mistaken for every replay.
+pub fn replay_sizes(package: &Package) -> String {
+    let mut lines = vec!();
+    let mut total_compact = 0;
+    let mut total_cached = 0;
+    for name in get_replay_files(package) {
+        let replay = match load_replay(&name, package) {
+            Ok(replay) => replay,
+            Err(err) => {
+                lines.push(format!("{}: failed to load: {}", name, err));
+                continue;
+            }
+        };
+        let cached = match replay.cached(package) {
+            Ok(cached) => cached,
+            Err(err) => {
+                lines.push(format!("{}: failed to resimulate: {}", name, err));
+                continue;
+            }
+        };
+
+        let compact_size = files::compressed_size(&replay.compact());
+        let cached_size = files::compressed_size(&cached);
+        total_compact += compact_size;
+        total_cached += cached_size;
+        lines.push(format!("{}: {} frames, compact {} bytes, cached {} bytes", name, replay.input_history.len(), compact_size, cached_size));
+    }
+
+    if total_compact > 0 {
+        lines.push(format!("Total: compact {} bytes, cached {} bytes, compact is {:.1}x smaller", total_compact, total_cached, total_cached as f64 / total_compact as f64));
+    } else {
+        lines.push(String::from("There are no replays"));
+    }
+    lines.join("\n")
+}
+
+/// Saves every replay of the set along with a summary of the set into its own folder
+pub fn save_set(set: &MatchSet, replays: &[Replay], package: &Package) {
+    let set_file = unique_file(package, "sets/", &set.timestamp);
+
+    for (i, replay) in replays.iter().enumerate() {
+        let mut replay = replay.clone();
+        replay.metadata.set = Some(ReplaySetInfo {
+            set:     set_file.trim_start_matches("sets/").to_string(),
+            best_of: set.best_of,
+            game:    i + 1,
+        });
+        save_replay_file(&replay, &format!("{}/Game {}", set_file, i + 1), package);
+    }
+
+    let mut summary_path = get_replays_dir_path(package);
+    summary_path.push(set_file);
+    summary_path.push("summary.json");
+    files::save_struct(summary_path, set);
+}
+
+/// Only the setup and inputs are needed to play back a replay, as the match is resimulated from them.
+/// Resimulating with a modified package may desync, so the player and stage history can be kept as a cache that plays back unchanged.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Replay {
+    pub header:               ReplayHeader,
+    pub metadata:             ReplayMetadata,
     pub init_seed:            u64,
     pub timestamp:            DateTime<Local>,
     pub input_history:        Vec<Vec<ControllerInput>>,
-    pub player_history:       Vec<Vec<Player>>,
-    pub stage_history:        Vec<Stage>,
+    pub checksums:            Vec<Option<u64>>, // checksum of the state after each frame, index f - 1 holds frame f. None if the frame was dropped from history
     pub selected_controllers: Vec<usize>,
     pub selected_players:     Vec<PlayerSetup>,
     pub selected_ais:         Vec<usize>,
     pub selected_stage:       String,
+    pub cache:                Option<ReplayCache>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub format_version: u64, // used to upgrade replays saved by older versions
+    pub engine_version: u64, // 0 if the replay was saved before the header existed
+    pub build_version:  String, // empty if the replay was saved before the header existed
+    pub package_hash:   String, // hash of the package the replay was recorded with
+}
+
+impl ReplayHeader {
+    pub fn new(package: &Package) -> ReplayHeader {
+        ReplayHeader {
+            format_version: json_upgrade::replay_format_version(),
+            engine_version: json_upgrade::engine_version(),
+            build_version:  json_upgrade::build_version(),
+            package_hash:   package.compute_hash(),
+        }
+    }
+}
+
+/// Describes the match so replays can be listed and filtered without resimulating them
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ReplayMetadata {
+    pub name:        String, // displayed in the replay select menu, defaults to when the replay was recorded
+    pub fighters:    Vec<String>,
+    pub stage:       String,
+    pub player_tags: Vec<String>, // the tag of each player, empty if not set
+    pub winner:      Option<usize>, // index of the winning player, None if the game was not finished
+    pub duration:    usize, // frames
+    pub set:         Option<ReplaySetInfo>,
+    pub tags:        Vec<String>, // used to organize replays, unrelated to player_tags
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplaySetInfo {
+    pub set:     String, // folder of the set in replays/$package/sets
+    pub best_of: u64,
+    pub game:    usize, // starts from 1
+}
+
+impl ReplayMetadata {
+    /// Returns a single line description of the match
+    pub fn description(&self) -> String {
+        let players: Vec<String> = self.fighters.iter().enumerate().map(|(i, fighter)| {
+            let tag = self.player_tags.get(i).map(|x| x.as_str()).unwrap_or("");
+            let winner = if self.winner == Some(i) { "*" } else { "" };
+            if tag.is_empty() {
+                format!("{}{}", fighter, winner)
+            } else {
+                format!("{} ({}){}", tag, fighter, winner)
+            }
+        }).collect();
+
+        let seconds = self.duration / 60;
+        let mut description = format!("{} - {} on {} - {}:{:02}", self.name, players.join(" vs "), self.stage, seconds / 60, seconds % 60);
+        if let Some(ref set) = self.set {
+            description.push_str(&format!(" - game {} of best of {}", set.game, set.best_of));
+        }
+        if !self.tags.is_empty() {
+            description.push_str(&format!(" [{}]", self.tags.join(", ")));
+        }
+        description
+    }
+}
+
+/// Stored in replays/$package/index.json so the replays can be listed without loading each of them
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ReplayIndex {
+    pub entries: Vec<ReplayEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub file:      String, // path relative to replays/$package without the .zip extension
+    pub timestamp: DateTime<Local>,
+    pub metadata:  ReplayMetadata,
+}
+
+impl ReplayEntry {
+    fn new(file: &str, replay: &Replay) -> ReplayEntry {
+        ReplayEntry {
+            file:      file.to_string(),
+            timestamp: replay.timestamp,
+            metadata:  replay.metadata.clone(),
+        }
+    }
+}
+
+/// Only replays that match every field that is set are displayed
+#[derive(Clone, Default)]
+pub struct ReplayFilter {
+    pub fighter:    Option<String>,
+    pub stage:      Option<String>,
+    pub player_tag: Option<String>,
+    pub tag:        Option<String>,
+}
+
+impl ReplayFilter {
+    pub fn matches(&self, metadata: &ReplayMetadata) -> bool {
+        self.fighter.as_ref().map(|x| metadata.fighters.contains(x)).unwrap_or(true) &&
+        self.stage.as_ref().map(|x| &metadata.stage == x).unwrap_or(true) &&
+        self.player_tag.as_ref().map(|x| metadata.player_tags.contains(x)).unwrap_or(true) &&
+        self.tag.as_ref().map(|x| metadata.tags.contains(x)).unwrap_or(true)
+    }
+
+    /// Sets the fighter filter to the next fighter used in the replays, wrapping around to no filter
+    pub fn cycle_fighter(&mut self, entries: &[ReplayEntry]) {
+        let fighters = entries.iter().flat_map(|x| x.metadata.fighters.iter().cloned()).collect();
+        self.fighter = cycle(&self.fighter, fighters);
+    }
+
+    /// Sets the stage filter to the next stage used in the replays, wrapping around to no filter
+    pub fn cycle_stage(&mut self, entries: &[ReplayEntry]) {
+        let stages = entries.iter().map(|x| x.metadata.stage.clone()).collect();
+        self.stage = cycle(&self.stage, stages);
+    }
+
+    /// Sets the player tag filter to the next player tag used in the replays, wrapping around to no filter
+    pub fn cycle_player_tag(&mut self, entries: &[ReplayEntry]) {
+        let tags = entries.iter().flat_map(|x| x.metadata.player_tags.iter().filter(|x| !x.is_empty()).cloned()).collect();
+        self.player_tag = cycle(&self.player_tag, tags);
+    }
+
+    /// Returns a single line description of the filter
+    pub fn description(&self) -> String {
+        let fields = [
+            ("fighter",    &self.fighter),
+            ("stage",      &self.stage),
+            ("player tag", &self.player_tag),
+            ("tag",        &self.tag),
+        ];
+        let set: Vec<String> = fields.iter().filter_map(|&(name, value)| value.as_ref().map(|x| format!("{}: {}", name, x))).collect();
+        if set.is_empty() {
+            String::from("All replays")
+        } else {
+            set.join(", ")
+        }
+    }
+}
+
+fn cycle(current: &Option<String>, mut options: Vec<String>) -> Option<String> {
+    options.sort();
+    options.dedup();
+    match current {
+        Some(current) => options.into_iter().find(|x| x > current),
+        None          => options.into_iter().next(),
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayCache {
+    pub player_history: History<Vec<Player>>,
+    pub stage_history:  History<Stage>,
 }
 
 impl Replay {
     pub fn new(game: &Game, input: &Input) -> Replay {
-        let selected_players = game.players.iter().map(|x| PlayerSetup {
-            fighter: x.fighter.clone(),
-            team:    x.team,
+        let selected_players = game.sim.players.iter().map(|x| PlayerSetup {
+            fighter:  x.fighter.clone(),
+            team:     x.team,
+            handicap: x.handicap.clone(),
         }).collect();
 
+        let cache = if game.config.replay_cache {
+            Some(ReplayCache {
+                player_history: game.player_history.clone(),
+                stage_history:  game.stage_history.clone(),
+            })
+        } else {
+            None
+        };
+
+        let timestamp = Local::now();
+        let input_history = input.get_history();
+        let checksums = frame_checksums(game, input_history.len());
+        let metadata = ReplayMetadata {
+            name:        timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            fighters:    game.sim.players.iter().map(|x| x.fighter.clone()).collect(),
+            stage:       game.selected_stage.clone(),
+            player_tags: vec!(String::new(); game.sim.players.len()),
+            winner:      None,
+            duration:    input_history.len(),
+            set:         None,
+            tags:        vec!(),
+        };
+
         Replay {
-            init_seed:            game.init_seed.clone(),
-            timestamp:            Local::now(),
-            input_history:        input.get_history(),
-            player_history:       game.player_history.clone(),
-            stage_history:        game.stage_history.clone(),
-            selected_controllers: game.selected_controllers.clone(),
+            header:               ReplayHeader::new(&game.sim.package),
+            init_seed:            game.sim.init_seed.clone(),
+            selected_controllers: game.sim.controllers.clone(),
             selected_ais:         game.selected_ais.clone(),
             selected_stage:       game.selected_stage.clone(),
-            selected_players
+            metadata,
+            timestamp,
+            input_history,
+            checksums,
+            selected_players,
+            cache,
+        }
+    }
+
+    /// Returns the replay without the cache
+    pub fn compact(&self) -> Replay {
+        Replay {
+            cache: None,
+            .. self.clone()
+        }
+    }
+
+    /// Returns the replay with a cache, resimulating it with the package if it does not have one
+    pub fn cached(&self, package: &Package) -> Result<Replay, String> {
+        let cache = match self.cache {
+            Some(ref cache) => cache.clone(),
+            None            => self.resimulate(package)?,
+        };
+        Ok(Replay {
+            cache: Some(cache),
+            .. self.clone()
+        })
+    }
+
+    /// Returns true if the replay was recorded with the package, so it will play back the same when resimulated
+    pub fn matches_package(&self, package: &Package) -> bool {
+        self.header.package_hash == package.compute_hash()
+    }
+
+    /// Returns the simulation at the start of the match
+    fn simulation(&self, package: &Package) -> Result<Simulation, String> {
+        if !package.stages.contains_key(&self.selected_stage) {
+            return Err(format!("The stage '{}' is not in the package", self.selected_stage));
+        }
+        if let Some(player) = self.selected_players.iter().find(|x| !package.fighters.contains_key(&x.fighter)) {
+            return Err(format!("The fighter '{}' is not in the package", player.fighter));
+        }
+
+        Ok(Simulation::new(package.clone(), SimulationSetup {
+            init_seed:   self.init_seed,
+            stage:       self.selected_stage.clone(),
+            players:     self.selected_players.clone(),
+            controllers: self.selected_controllers.clone(),
+        }))
+    }
+
+    /// Resimulates the match from its inputs, storing the state of every frame
+    pub fn resimulate(&self, package: &Package) -> Result<ReplayCache, String> {
+        let mut sim = self.simulation(package)?;
+        let mut player_history = History::new();
+        let mut stage_history = History::new();
+        for inputs in &self.input_history {
+            player_history.push(&sim.players);
+            stage_history.push(&sim.stage);
+            sim.step(inputs);
+        }
+
+        Ok(ReplayCache { player_history, stage_history })
+    }
+
+    /// Returns true if the replay stores checksums or a cache to verify it against
+    pub fn verifiable(&self) -> bool {
+        self.cache.is_some() || self.checksums.iter().any(|x| x.is_some())
+    }
+
+    /// Resimulates the match from its inputs and compares every frame against the checksums and the players of the cache.
+    /// Returns the first frame that differs, frames without a checksum or dropped from the cache are not compared.
+    /// When a cache exists the players are compared so the fields that differ are reported.
+    pub fn verify(&self, package: &Package) -> Result<Option<Divergence>, String> {
+        if !self.verifiable() {
+            return Err(String::from("The replay has no checksums or cache to compare against"));
+        }
+
+        let mut sim = self.simulation(package)?;
+        for (frame, inputs) in self.input_history.iter().enumerate() {
+            if let Some(divergence) = self.compare_cache(frame, &sim) {
+                return Ok(Some(divergence));
+            }
+
+            sim.step(inputs);
+            if let Some(&Some(expected)) = self.checksums.get(frame) {
+                if sim.checksum() != expected {
+                    let divergence = self.compare_cache(sim.current_frame, &sim);
+                    return Ok(Some(divergence.unwrap_or_else(|| Divergence::checksum(sim.current_frame))));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Compares the players of the simulation against the players stored in the cache for the frame
+    fn compare_cache(&self, frame: usize, sim: &Simulation) -> Option<Divergence> {
+        let expected = self.cache.as_ref().and_then(|x| x.player_history.get(frame))?;
+        Divergence::compare(frame, &expected, &sim.players)
+    }
+}
+
+/// Returns the checksum of the state after each frame, index f - 1 holds frame f.
+/// Frames are checksummed from the history, so frames dropped from the history are None.
+fn frame_checksums(game: &Game, frames: usize) -> Vec<Option<u64>> {
+    (1..=frames).map(|frame| {
+        if frame == game.sim.current_frame {
+            return Some(game.sim.checksum());
+        }
+        match (game.player_history.get(frame), game.stage_history.get(frame)) {
+            (Some(players), Some(stage)) => {
+                let sudden_deaths = game.sudden_deaths_until(frame);
+                Some(simulation::checksum(&players, &stage, &sudden_deaths, game.sim.init_seed, frame))
+            }
+            _ => None
+        }
+    }).collect()
+}
+
+/// The result of verifying every replay of a package
+#[derive(Default)]
+pub struct ReplayVerification {
+    pub lines:    Vec<String>,
+    pub matched:  usize,
+    pub diverged: usize,
+    pub skipped:  usize, // replays without checksums or a cache cannot be verified
+    pub failed:   usize,
+}
+
+impl ReplayVerification {
+    pub fn report(&self) -> String {
+        let mut lines = self.lines.clone();
+        lines.push(format!("{} matched, {} diverged, {} skipped, {} failed", self.matched, self.diverged, self.skipped, self.failed));
+        lines.join("\n")
+    }
+
+    /// 0 if every replay matched, 1 if a replay diverged, 2 if a replay could not be loaded or resimulated,
+    /// 3 if a replay had no checksums or cache or there were no replays, as an unverified replay could hide a divergence
+    pub fn exit_code(&self) -> i32 {
+        if self.diverged > 0 {
+            1
+        } else if self.failed > 0 {
+            2
+        } else if self.skipped > 0 || self.matched == 0 {
+            3
+        } else {
+            0
+        }
+    }
+}
+
+/// Resimulates every replay of the package and compares it against the checksums and cache stored when it was recorded.
+/// Used to find the replays that are changed by modifying the engine or package.
+/// Replays recorded before checksums were stored are counted as skipped unless they have a cache.
+pub fn verify_replays(package: &Package) -> ReplayVerification {
+    let mut verification = ReplayVerification::default();
+    for file in get_replay_files(package) {
+        let replay = match load_replay(&file, package) {
+            Ok(replay) => replay,
+            Err(err) => {
+                verification.lines.push(format!("{}: failed to load: {}", file, err));
+                verification.failed += 1;
+                continue;
+            }
+        };
+
+        if !replay.verifiable() {
+            verification.lines.push(format!("{}: skipped, has no checksums or cache to compare against", file));
+            verification.skipped += 1;
+            continue;
+        }
+
+        match replay.verify(package) {
+            Ok(None) => {
+                verification.lines.push(format!("{}: matches for {} frames", file, replay.input_history.len()));
+                verification.matched += 1;
+            }
+            Ok(Some(divergence)) => {
+                verification.lines.push(format!("{}: {}", file, divergence.description()));
+                verification.diverged += 1;
+            }
+            Err(err) => {
+                verification.lines.push(format!("{}: failed to resimulate: {}", file, err));
+                verification.failed += 1;
+            }
         }
     }
+    verification
 }
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use chrono::{Local, DateTime};
+
+use pf_sandbox_lib::files;
+use pf_sandbox_lib::history::History;
+use pf_sandbox_lib::input::ControllerInput;
+use pf_sandbox_lib::package::Package;
+use pf_sandbox_lib::player::Player;
+use pf_sandbox_lib::simulation::SuddenDeath;
+use pf_sandbox_lib::stage::Stage;
+use crate::game::Game;
+use crate::input::Input;
+
+use treeflection::{Node, NodeRunner, NodeToken};
+
+fn get_savestates_dir_path(package: &Package) -> PathBuf {
+    let mut savestates_path = files::get_path();
+    savestates_path.push("savestates");
+    savestates_path.push(package.file_name());
+    savestates_path
+}
+
+fn get_savestate_path(package: &Package, slot: usize) -> PathBuf {
+    let mut savestate_path = get_savestates_dir_path(package);
+    savestate_path.push(format!("{}.zip", slot));
+    savestate_path
+}
+
+pub fn load_savestate(slot: usize, package: &Package) -> Result<Savestate, String> {
+    files::load_struct_compressed(get_savestate_path(package, slot))
+}
+
+pub fn save_savestate(savestate: &Savestate, slot: usize, package: &Package) {
+    files::save_struct_compressed(get_savestate_path(package, slot), savestate);
+}
+
+/// The state of a game at a single moment, including the history needed to step backwards from it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Savestate {
+    pub timestamp:        DateTime<Local>,
+    pub init_seed:        u64,
+    pub current_frame:    usize,
+    pub stage:            Stage,
+    pub players:          Vec<Player>,
+    pub controllers:      Vec<usize>,
+    pub sudden_deaths:    Vec<SuddenDeath>,
+    pub input_history:    Vec<Vec<ControllerInput>>,
+    pub player_history:   History<Vec<Player>>,
+    pub stage_history:    History<Stage>,
+    pub checksum_history: Vec<Option<u64>>,
+    pub selected_ais:     Vec<usize>,
+    pub selected_stage:   String,
+}
+
+impl Savestate {
+    pub fn new(game: &Game, input: &Input) -> Savestate {
+        Savestate {
+            timestamp:        Local::now(),
+            init_seed:        game.sim.init_seed,
+            current_frame:    game.sim.current_frame,
+            stage:            game.sim.stage.clone(),
+            players:          game.sim.players.clone(),
+            controllers:      game.sim.controllers.clone(),
+            sudden_deaths:    game.sudden_deaths.clone(),
+            input_history:    input.get_history(),
+            player_history:   game.player_history.clone(),
+            stage_history:    game.stage_history.clone(),
+            checksum_history: game.checksum_history.clone(),
+            selected_ais:     game.selected_ais.clone(),
+            selected_stage:   game.selected_stage.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SavestateRequest {
+    Save (usize),
+    Load (usize),
+}
+
+/// Savestate commands are queued here and handled by the game on its next step, as saving and loading needs access to the input history.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Savestates {
+    pub request: Option<SavestateRequest>,
+}
+
+impl Node for Savestates {
+    fn node_step(&mut self, mut runner: NodeRunner) -> String {
+        match runner.step() {
+            NodeToken::Help => {
+                String::from(r#"
+Savestates Help
+
+Commands:
+*   help        - display this help
+*   save $slot  - save the current game state to the numbered slot
+*   load $slot  - restore the game state saved in the numbered slot"#)
+            }
+            NodeToken::Custom (action, args) => {
+                let slot = match args.get(0).map(|x| x.parse::<usize>()) {
+                    Some(Ok(slot)) => slot,
+                    Some(Err(_))   => return format!("Slot must be a number, was '{}'", args[0]),
+                    None           => return String::from("Didn't specify a slot"),
+                };
+                match action.as_ref() {
+                    "save" => {
+                        self.request = Some(SavestateRequest::Save (slot));
+                        format!("Saving savestate slot {}", slot)
+                    }
+                    "load" => {
+                        self.request = Some(SavestateRequest::Load (slot));
+                        format!("Loading savestate slot {}", slot)
+                    }
+                    _ => format!("Savestates cannot '{}'", action)
+                }
+            }
+            action => format!("Savestates cannot '{:?}'", action)
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use pf_sandbox_lib::rules::Rules;
+use pf_sandbox_lib::stage_strike::{SetGame, StageStrike, StrikeTurn};
+use crate::graphics;
+use crate::results::GameResults;
+
+use chrono::{Local, DateTime};
+
+/// A best of N set, tracked by the Menu across multiple games.
+/// Players are grouped into pools, when teams are enabled teammates share a pool.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MatchSet {
+    pub timestamp:     DateTime<Local>,
+    pub best_of:       u64,
+    pub teams_enabled: bool,
+    pub controllers:   Vec<usize>,         // the controller of each player, used to match game results to players
+    pub inputs:        Vec<Option<usize>>, // the input index of each human player, None for CPU players
+    pub pools:         Vec<usize>,         // the pool of each player
+    pub games:         Vec<SetGame>,
+}
+
+impl MatchSet {
+    pub fn new(rules: &Rules, controllers: Vec<usize>, inputs: Vec<Option<usize>>, pools: Vec<usize>) -> MatchSet {
+        MatchSet {
+            timestamp:     Local::now(),
+            best_of:       rules.best_of,
+            teams_enabled: rules.teams_enabled(),
+            games:         vec!(),
+            controllers,
+            inputs,
+            pools,
+        }
+    }
+
+    /// Returns true if the set was started with the same players
+    pub fn same_players(&self, controllers: &[usize], pools: &[usize]) -> bool {
+        self.controllers.as_slice() == controllers && self.pools.as_slice() == pools
+    }
+
+    /// Returns each pool in ascending order
+    pub fn pool_order(&self) -> Vec<usize> {
+        let mut pools = self.pools.clone();
+        pools.sort();
+        pools.dedup();
+        pools
+    }
+
+    pub fn pool_name(&self, pool: usize) -> String {
+        if self.teams_enabled {
+            let colors = graphics::get_colors();
+            format!("{} Team", colors[pool % colors.len()].name)
+        } else {
+            format!("Player {}", pool + 1)
+        }
+    }
+
+    /// Returns the input indexes that can act on behalf of the pool.
+    /// Returns an empty Vec if the pool only contains CPU players.
+    pub fn pool_inputs(&self, pool: usize) -> Vec<usize> {
+        self.pools.iter().zip(self.inputs.iter()).filter(|x| *x.0 == pool).filter_map(|x| *x.1).collect()
+    }
+
+    pub fn wins(&self, pool: usize) -> usize {
+        self.games.iter().filter(|x| x.winner == pool).count()
+    }
+
+    /// Returns the pool that has won the majority of the best_of games
+    pub fn winner(&self) -> Option<usize> {
+        self.pool_order().into_iter().find(|pool| self.wins(*pool) as u64 > self.best_of / 2)
+    }
+
+    /// Records the winner and loser of a game played on the stage
+    pub fn add_game(&mut self, results: &GameResults, stage: String) {
+        let mut winner: Option<usize> = None;
+        let mut loser: Option<(usize, usize)> = None;
+        for result in &results.player_results {
+            if let Some(player_i) = self.controllers.iter().position(|x| *x == result.controller) {
+                let pool = self.pools[player_i];
+                if result.place == 0 {
+                    winner = Some(pool);
+                }
+                if loser.map(|(_, place)| result.place > place).unwrap_or(true) {
+                    loser = Some((pool, result.place));
+                }
+            }
+        }
+
+        if let (Some(winner), Some((loser, _))) = (winner, loser) {
+            self.games.push(SetGame { stage, winner, loser });
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary: Vec<String> = self.pool_order().iter().map(|pool| format!("{}: {}", self.pool_name(*pool), self.wins(*pool))).collect();
+        if let Some(winner) = self.winner() {
+            summary.push(format!("{} wins the set!", self.pool_name(winner)));
+        }
+        summary.join("    ")
+    }
+
+    /// Tells the players whose turn it is to strike or pick a stage
+    pub fn strike_message(&self, strike: &StageStrike) -> String {
+        match strike.turn(&self.games, &self.pool_order()) {
+            StrikeTurn::Strike { pool } => format!("{}: strike a stage", self.pool_name(pool)),
+            StrikeTurn::Pick   { pool } => format!("{}: pick a stage", self.pool_name(pool)),
+            StrikeTurn::Done   { .. }   => String::new(),
+        }
+    }
+}
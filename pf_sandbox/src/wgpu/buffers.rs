@@ -1,10 +1,9 @@
 use pf_sandbox_lib::fighter::{LinkType, CollisionBox, CollisionBoxLink, ColboxOrLink};
 use pf_sandbox_lib::geometry::Rect;
 use pf_sandbox_lib::package::Package;
+use pf_sandbox_lib::player::{RenderPlayer, RenderShield};
 use pf_sandbox_lib::stage::Surface;
-use crate::player::RenderShield;
 use crate::graphics;
-use crate::player::RenderPlayer;
 use crate::game::SurfaceSelection;
 
 use wgpu::{Device, Buffer};
@@ -47,6 +46,26 @@ fn colorvertex(x: f32, y: f32, color: [f32; 4]) -> ColorVertex {
     }
 }
 
+/// Surface materials take priority over the surface type coloring.
+/// The surface type is colored by removing red for floors, green for ceilings and blue for walls.
+fn surface_color(surface: &Surface) -> [f32; 4] {
+    let (traction, conveyor) = surface.floor.as_ref().map(|x| (x.traction, x.conveyor)).unwrap_or((1.0, 0.0));
+    if surface.hazard.is_some() {
+        [1.0, 0.3, 0.0, 1.0]
+    } else if traction < 1.0 {
+        [0.6, 0.9, 1.0, 1.0]
+    } else if conveyor != 0.0 {
+        [1.0, 0.85, 0.0, 1.0]
+    } else if surface.bounce > 0.0 {
+        [1.0, 0.4, 0.8, 1.0]
+    } else {
+        let r = if surface.is_pass_through() { 0.4 } else if surface.floor.is_some() { 0.6 } else { 0.0 };
+        let g = if surface.ceiling { 0.5 } else { 0.0 };
+        let b = if surface.wall { 0.5 } else { 0.0 };
+        [1.0 - g - b, 1.0 - r - b, 1.0 - r - g, 1.0]
+    }
+}
+
 struct StageVertexConstructor;
 impl VertexConstructor<FillVertex, ColorVertex> for StageVertexConstructor {
     fn new_vertex(&mut self, vertex: FillVertex) -> ColorVertex {
@@ -537,10 +556,7 @@ impl ColorBuffers {
         let mut indice_count = 0;
 
         for surface in surfaces {
-            let r = if surface.is_pass_through() { 0.4 } else if surface.floor.is_some() { 0.6 } else { 0.0 };
-            let g = if surface.ceiling { 0.5 } else { 0.0 };
-            let b = if surface.wall { 0.5 } else { 0.0 };
-            let color = [1.0 - g - b, 1.0 - r - b, 1.0 - r - g, 1.0];
+            let color = surface_color(surface);
 
             let angle = surface.render_angle() - 90f32.to_radians();
             let d_x = angle.cos() / 4.0;
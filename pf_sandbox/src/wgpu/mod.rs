@@ -5,14 +5,14 @@ mod buffers;
 use buffers::{ColorVertex, ColorBuffers, Vertex, Buffers};
 use crate::game::{GameState, RenderEntity, RenderGame};
 use crate::graphics::{self, GraphicsMessage, Render, RenderType};
-use crate::menu::{RenderMenu, RenderMenuState, PlayerSelect, PlayerSelectUi};
-use crate::particle::ParticleType;
-use crate::player::{RenderFighter, RenderPlayer, RenderPlayerFrame, DebugPlayer};
-use crate::results::PlayerResult;
+use crate::menu::{RenderMenu, RenderMenuState, PlayerSelect, PlayerSelectUi, RenderStrikeStage};
 use pf_sandbox_lib::fighter::{Action, ECB, CollisionBoxRole, ActionFrame};
 use pf_sandbox_lib::geometry::Rect;
 use pf_sandbox_lib::json_upgrade;
 use pf_sandbox_lib::package::{Package, PackageUpdate, Verify};
+use pf_sandbox_lib::particle::ParticleType;
+use pf_sandbox_lib::player::{RenderFighter, RenderPlayer, RenderPlayerFrame, DebugPlayer};
+use pf_sandbox_lib::results::{PlayerResult, TeamResult};
 
 use std::collections::HashSet;
 use std::sync::mpsc::{Sender, Receiver, channel};
@@ -318,8 +318,9 @@ impl WgpuGraphics {
                         package.stages.remove(index);
                     }
                 }
-                PackageUpdate::InsertStage { index, key, stage } => {
+                PackageUpdate::InsertStage { index, key, mut stage } => {
                     if let &mut Some(ref mut package) = &mut self.package {
+                        stage.rebuild_surface_grid();
                         package.stages.insert(index, key, stage);
                     }
                 }
@@ -439,6 +440,33 @@ impl WgpuGraphics {
         }
     }
 
+    fn sudden_death_render(&mut self, sudden_death: bool) {
+        if sudden_death {
+            self.glyph_brush.queue(Section {
+                text: "SUDDEN DEATH",
+                color: [1.0, 0.2, 0.2, 1.0],
+                screen_position: ((self.width / 2) as f32 - 110.0, 90.0),
+                scale: GlyphScale::uniform(40.0),
+                .. Section::default()
+            });
+        }
+    }
+
+    fn pause_hold_render(&mut self, pause_hold: Option<f32>) {
+        if let Some(progress) = pause_hold {
+            let bar_len = 20;
+            let filled = ((progress * bar_len as f32) as usize).min(bar_len);
+            self.glyph_brush.queue(Section {
+                text: format!("Pausing [{}{}]", "#".repeat(filled), " ".repeat(bar_len - filled)).as_ref(),
+                color: [1.0, 1.0, 1.0, 1.0],
+                screen_position: ((self.width / 2) as f32 - 150.0, 50.0),
+                scale: GlyphScale::uniform(30.0),
+                font_id: self.hack_font_id,
+                .. Section::default()
+            });
+        }
+    }
+
     fn game_hud_render(&mut self, entities: &[RenderEntity]) {
         let mut players = 0;
         for entity in entities {
@@ -478,8 +506,13 @@ impl WgpuGraphics {
                             });
                         }
 
+                        let damage_string = if let Some(hp) = player.hp {
+                            format!("{:.0} HP", hp.ceil())
+                        } else {
+                            format!("{}%", player.damage)
+                        };
                         self.glyph_brush.queue(Section {
-                            text: format!("{}%", player.damage).as_ref(),
+                            text: damage_string.as_ref(),
                             color,
                             screen_position: (location, self.height as f32 - 117.0),
                             scale: GlyphScale::uniform(110.0),
@@ -622,6 +655,8 @@ impl WgpuGraphics {
         if command_output.len() == 0 {
             self.game_hud_render(&render.entities);
             self.game_timer_render(&render.timer);
+            self.sudden_death_render(render.sudden_death);
+            self.pause_hold_render(render.pause_hold);
             self.debug_lines_render(&render.debug_lines);
             self.fps_render();
         }
@@ -853,12 +888,15 @@ impl WgpuGraphics {
                     let transformation = position * flip;
                     self.render_buffers(&self.pipeline, rpass, &render, buffers, &transformation, render_point.color.clone(), render_point.color.clone())
                 }
+                &RenderEntity::Hazard (_) => { } // transparent, rendered below
+                &RenderEntity::BranchPlayer (_) => { } // transparent, rendered below
             }
         }
 
         // Some things need to be rendered after everything else as they are transparent
         for (i, entity) in render.entities.iter().enumerate() {
             let z_shield = 0.4 - i as f32 * 0.00001;
+            let z_player = 0.5 - i as f32 * 0.00001;
             match entity {
                 &RenderEntity::Player(ref player) => {
                     // draw shield
@@ -874,6 +912,26 @@ impl WgpuGraphics {
                         self.render_buffers(&self.pipeline, rpass, &render, buffers, &position, shield.color, color);
                     }
                 }
+                &RenderEntity::BranchPlayer (ref player) => {
+                    let frame = &player.frames[0];
+                    if let Some(buffers) = Buffers::new_fighter_frame(&self.device, &self.package.as_ref().unwrap(), &frame.fighter, frame.action, frame.frame) {
+                        let dir      = Matrix4::from_nonuniform_scale(if frame.face_right { 1.0 } else { -1.0 }, 1.0, 1.0);
+                        let rotate   = Matrix4::from_angle_z(Rad(frame.angle));
+                        let position = Matrix4::from_translation(Vector3::new(frame.bps.0 + pan.0, frame.bps.1 + pan.1, z_player));
+                        let transformation = position * rotate * dir;
+                        let c = player.fighter_color.clone();
+                        let edge_color = [c[0], c[1], c[2], 0.5];
+                        let color = [0.9, 0.9, 0.9, 0.3];
+                        self.render_buffers(&self.pipeline, rpass, &render, buffers, &transformation, edge_color, color);
+                    }
+                }
+                &RenderEntity::Hazard (ref hazard) => {
+                    let buffers = Buffers::new_circle(&self.device);
+                    let size = Matrix4::from_nonuniform_scale(hazard.radius, hazard.radius, 1.0);
+                    let position = Matrix4::from_translation(Vector3::new(hazard.x + pan.0, hazard.y + pan.1, z_shield));
+                    let transformation = position * size;
+                    self.render_buffers(&self.pipeline, rpass, &render, buffers, &transformation, hazard.color, hazard.color);
+                }
                 _ => { }
             }
         }
@@ -888,8 +946,8 @@ impl WgpuGraphics {
                 self.draw_game_selector(selection);
                 self.draw_package_banner(&render.package_verify, command_output);
             }
-            RenderMenuState::ReplaySelect (replay_names, selection) => {
-                self.draw_replay_selector(&replay_names, selection);
+            RenderMenuState::ReplaySelect (replay_names, selection, filter, message) => {
+                self.draw_replay_selector(&replay_names, selection, &filter, &message);
                 self.draw_package_banner(&render.package_verify, command_output);
             }
             RenderMenuState::CharacterSelect (selections, back_counter, back_counter_max) => {
@@ -954,13 +1012,28 @@ impl WgpuGraphics {
                 self.draw_stage_selector(&mut entities, selection);
                 self.draw_package_banner(&render.package_verify, command_output);
             }
-            RenderMenuState::GameResults { results, replay_saved } => {
+            RenderMenuState::StageStrike { stages, selection, message, set_summary } => {
+                self.draw_stage_strike(&mut entities, &stages, selection, &message, &set_summary);
+                self.draw_package_banner(&render.package_verify, command_output);
+            }
+            RenderMenuState::GameResults { results, team_results, set_summary, replay_saved } => {
                 let max = results.len() as f32;
                 for (i, result) in results.iter().enumerate() {
                     let i = i as f32;
                     let start_x = i / max;
                     self.draw_player_result(result, start_x);
                 }
+                self.draw_team_results(&team_results);
+
+                if let Some(set_summary) = set_summary {
+                    self.glyph_brush.queue(Section {
+                        text: set_summary.as_ref(),
+                        color: [1.0, 1.0, 1.0, 1.0],
+                        screen_position: (30.0, self.height as f32 - 70.0),
+                        scale: GlyphScale::uniform(30.0),
+                        .. Section::default()
+                    });
+                }
 
                 if replay_saved {
                     self.glyph_brush.queue(Section {
@@ -1141,7 +1214,7 @@ impl WgpuGraphics {
         }
     }
 
-    fn draw_replay_selector(&mut self, replay_names: &[String], selection: usize) {
+    fn draw_replay_selector(&mut self, replay_names: &[String], selection: usize, filter: &str, message: &str) {
         self.glyph_brush.queue(Section {
             text: "Select Replay",
             color: [1.0, 1.0, 1.0, 1.0],
@@ -1149,6 +1222,21 @@ impl WgpuGraphics {
             scale: GlyphScale::uniform(50.0),
             .. Section::default()
         });
+        let filter = format!("{} (X: fighter, Y: stage, Z: player tag)", filter);
+        self.glyph_brush.queue(Section {
+            text: &filter,
+            color: [0.7, 0.7, 0.7, 1.0],
+            screen_position: (self.width as f32 * 0.5, 20.0),
+            scale: GlyphScale::uniform(26.0),
+            .. Section::default()
+        });
+        self.glyph_brush.queue(Section {
+            text: message,
+            color: [1.0, 0.0, 0.0, 1.0],
+            screen_position: (100.0, self.height as f32 - 60.0),
+            scale: GlyphScale::uniform(30.0),
+            .. Section::default()
+        });
 
         for (replay_i, name) in replay_names.iter().enumerate() {
             let size = 26.0; // TODO: determine from width/height of screen and start/end pos
@@ -1213,6 +1301,8 @@ impl WgpuGraphics {
                 PlayerSelectUi::HumanFighter (_) => format!("Port #{}", controller_i+1),
                 PlayerSelectUi::HumanTeam    (_) => format!("Port #{} Team", controller_i+1),
                 PlayerSelectUi::CpuTeam      (_) => format!("CPU Team"),
+                PlayerSelectUi::HumanHandicap (_) => format!("Port #{} Handicap", controller_i+1),
+                PlayerSelectUi::CpuHandicap  (_) => format!("CPU Handicap"),
                 PlayerSelectUi::HumanUnplugged   => unreachable!()
             };
             self.glyph_brush.queue(Section {
@@ -1230,11 +1320,13 @@ impl WgpuGraphics {
             PlayerSelectUi::HumanFighter (_) => {
                 options.extend(fighters.iter().map(|x| x.name.clone()));
                 options.push(String::from("Change Team"));
+                options.push(String::from("Change Handicap"));
                 options.push(String::from("Add CPU"));
             }
             PlayerSelectUi::CpuFighter (_) => {
                 options.extend(fighters.iter().map(|x| x.name.clone()));
                 options.push(String::from("Change Team"));
+                options.push(String::from("Change Handicap"));
                 options.push(String::from("Change AI"));
                 options.push(String::from("Remove CPU"));
             }
@@ -1246,6 +1338,16 @@ impl WgpuGraphics {
                 options.extend(graphics::get_colors().iter().map(|x| x.name.clone()));
                 options.push(String::from("Return"));
             }
+            PlayerSelectUi::HumanHandicap (_) |
+            PlayerSelectUi::CpuHandicap (_) => {
+                let handicap = &selection.handicap;
+                options.push(format!("Starting Damage: {}%", handicap.starting_damage));
+                options.push(format!("Damage Taken: x{}",    handicap.damage_taken_ratio));
+                options.push(format!("Damage Dealt: x{}",    handicap.damage_dealt_ratio));
+                options.push(format!("Knockback Taken: x{}", handicap.knockback_taken_ratio));
+                options.push(format!("Extra Stocks: {}",     handicap.extra_stocks));
+                options.push(String::from("Return"));
+            }
             PlayerSelectUi::CpuAi (_) => {
                 options.push(String::from("Return"));
             }
@@ -1314,6 +1416,7 @@ impl WgpuGraphics {
                         team:              selection.team,
                         debug:             DebugPlayer::default(),
                         damage:            0.0,
+                        hp:                None,
                         stocks:            None,
                         frame_data:        ActionFrame::default(),
                         fighter_color:     graphics::get_team_color3(selection.team),
@@ -1398,6 +1501,62 @@ impl WgpuGraphics {
         }
     }
 
+    fn draw_stage_strike(&mut self, entities: &mut Vec<MenuEntityAndBindGroup>, stages: &[RenderStrikeStage], selection: usize, message: &str, set_summary: &str) {
+        self.glyph_brush.queue(Section {
+            text: message,
+            color: [1.0, 1.0, 1.0, 1.0],
+            screen_position: (100.0, 4.0),
+            scale: GlyphScale::uniform(50.0),
+            .. Section::default()
+        });
+        self.glyph_brush.queue(Section {
+            text: set_summary,
+            color: [1.0, 1.0, 1.0, 1.0],
+            screen_position: (30.0, self.height as f32 - 30.0),
+            scale: GlyphScale::uniform(30.0),
+            .. Section::default()
+        });
+        for (stage_i, stage) in stages.iter().enumerate() {
+            let size = 26.0; // TODO: determine from width/height of screen and start/end pos
+            let x_offset = if stage_i == selection { 0.05 } else { 0.0 };
+            let x = self.width as f32 * (0.1 + x_offset);
+            let y = self.height as f32 * 0.1 + stage_i as f32 * 50.0;
+            let color = if stage.struck {
+                [0.3, 0.3, 0.3, 1.0]
+            } else if stage.pickable {
+                [1.0, 1.0, 1.0, 1.0]
+            } else {
+                [0.6, 0.3, 0.3, 1.0]
+            };
+            self.glyph_brush.queue(Section {
+                text: stage.name.as_ref(),
+                color,
+                screen_position: (x, y),
+                scale: GlyphScale::uniform(size),
+                .. Section::default()
+            });
+
+            if stage_i == selection {
+                let zoom_divider = 100.0;
+                let zoom = 1.0 / zoom_divider;
+                let y = -0.2 * zoom_divider;
+
+                let camera   = Matrix4::from_nonuniform_scale(zoom, zoom * self.aspect_ratio(), 1.0);
+                let position = Matrix4::from_translation(Vector3::new(1.0, y, 0.0));
+                let transformation = camera * position;
+                let uniform = ColorUniform { transformation: transformation.into() };
+
+                let bind_group = self.new_bind_group(uniform);
+                let entity = MenuEntity::Stage(stage.key.clone());
+                entities.push(MenuEntityAndBindGroup { bind_group, entity });
+
+                let bind_group = self.new_bind_group(uniform);
+                let entity = MenuEntity::StageFill(stage.key.clone());
+                entities.push(MenuEntityAndBindGroup { bind_group, entity });
+            }
+        }
+    }
+
     fn draw_player_result(&mut self, result: &PlayerResult, start_x: f32) {
         let fighter_name = self.package.as_ref().unwrap().fighters[result.fighter.as_ref()].name.as_ref();
         let color = graphics::get_team_color4(result.team);
@@ -1419,6 +1578,16 @@ impl WgpuGraphics {
             .. Section::default()
         });
         y += 30.0;
+        if let Some(score) = result.score {
+            self.glyph_brush.queue(Section {
+                text: format!("Score: {}", score).as_str(),
+                color,
+                screen_position: (x, y),
+                scale: GlyphScale::uniform(30.0),
+                .. Section::default()
+            });
+            y += 30.0;
+        }
         self.glyph_brush.queue(Section {
             text: format!("Kills: {}", result.kills.len()).as_str(),
             color,
@@ -1444,6 +1613,22 @@ impl WgpuGraphics {
         });
     }
 
+    fn draw_team_results(&mut self, team_results: &[TeamResult]) {
+        let colors = graphics::get_colors();
+        let mut y = 330.0;
+        for result in team_results {
+            let team_name = &colors[result.team % colors.len()].name;
+            self.glyph_brush.queue(Section {
+                text: format!("{}: {} Team", result.place + 1, team_name).as_str(),
+                color: graphics::get_team_color4(result.team),
+                screen_position: (0.05 * self.width as f32, y),
+                scale: GlyphScale::uniform(40.0),
+                .. Section::default()
+            });
+            y += 40.0;
+        }
+    }
+
     fn aspect_ratio(&self) -> f32 {
         self.width as f32 / self.height as f32
     }
@@ -0,0 +1,86 @@
+use pf_sandbox_lib::geometry::{self, Rect};
+use pf_sandbox_lib::stage::{Stage, Surface};
+use treeflection::ContextVec;
+
+use std::time::Instant;
+
+const QUERIES: usize = 100_000;
+
+/// Generates a stage with a long connected floor and many small decorative surfaces scattered around it
+fn large_stage(decorations: usize) -> Stage {
+    let mut surfaces = vec!();
+    for i in 0..100 {
+        let x = i as f32 * 10.0 - 500.0;
+        surfaces.push(Surface::new(x, 0.0, x + 10.0, 0.0, true, false, false));
+    }
+    for i in 0..decorations {
+        let x = (i * 37 % 1000) as f32 - 500.0;
+        let y = (i * 53 % 400) as f32 - 100.0;
+        surfaces.push(Surface::new(x, y, x + 4.0, y + 2.0, false, false, false));
+    }
+
+    let mut stage = Stage::default();
+    stage.surfaces = ContextVec::from_vec(surfaces);
+    stage.rebuild_surface_grid();
+    stage
+}
+
+fn queries() -> Vec<((f32, f32), (f32, f32))> {
+    (0..QUERIES).map(|i| {
+        let x = (i * 7 % 1000) as f32 - 500.0;
+        let y = (i * 11 % 200) as f32 - 50.0;
+        ((x, y + 1.0), (x + 1.0, y - 1.0))
+    }).collect()
+}
+
+fn bench_linear(stage: &Stage, queries: &[((f32, f32), (f32, f32))]) -> usize {
+    let mut hits = 0;
+    for &(old_p, new_p) in queries {
+        for surface in stage.surfaces.iter() {
+            if surface.floor.is_some() && geometry::segments_intersect(old_p, new_p, surface.p1(), surface.p2()) {
+                hits += 1;
+                break;
+            }
+        }
+    }
+    hits
+}
+
+fn bench_grid(stage: &Stage, queries: &[((f32, f32), (f32, f32))]) -> usize {
+    let mut hits = 0;
+    for &(old_p, new_p) in queries {
+        for surface_i in stage.surfaces_near(&Rect::from_tuples(old_p, new_p)) {
+            let surface = &stage.surfaces[surface_i];
+            if surface.floor.is_some() && geometry::segments_intersect(old_p, new_p, surface.p1(), surface.p2()) {
+                hits += 1;
+                break;
+            }
+        }
+    }
+    hits
+}
+
+fn main() {
+    let queries = queries();
+    for &decorations in &[100, 1000, 10000] {
+        let stage = large_stage(decorations);
+
+        let start = Instant::now();
+        let linear_hits = bench_linear(&stage, &queries);
+        let linear = start.elapsed();
+
+        let start = Instant::now();
+        let grid_hits = bench_grid(&stage, &queries);
+        let grid = start.elapsed();
+
+        assert_eq!(linear_hits, grid_hits);
+        println!("{} surfaces, {} queries: linear {:?}, grid {:?}", stage.surfaces.len(), QUERIES, linear, grid);
+    }
+
+    let stage = large_stage(10000);
+    let start = Instant::now();
+    for platform_i in 0..100 {
+        stage.connected_floors(platform_i);
+    }
+    println!("{} surfaces: connected_floors for 100 floors {:?}", stage.surfaces.len(), start.elapsed());
+}
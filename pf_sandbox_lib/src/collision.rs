@@ -0,0 +1,315 @@
+use crate::fighter::{Fighter, ActionFrame, HurtBox, HitBox, CollisionBox, CollisionBoxRole, PowerShield};
+use crate::geometry;
+use crate::player::{Player, Location};
+use crate::rules::Rules;
+use crate::stage::Stage;
+
+use treeflection::KeyedContextVec;
+
+// def - player who was attacked
+// atk - player who attacked
+
+/// returns a list of hit results for each player
+pub fn collision_check(players: &[Player], fighters: &KeyedContextVec<Fighter>, stage: &Stage, rules: &Rules, frame: usize) -> Vec<Vec<CollisionResult>> {
+    let surfaces = &stage.surfaces;
+    let mut result: Vec<Vec<CollisionResult>> = vec!();
+    for _ in players {
+        result.push(vec!());
+    }
+
+    let players_xy: Vec<(f32, f32)> = players.iter().map(|x| x.public_bps_xy(players, fighters, surfaces)).collect();
+    let frames: Vec<ActionFrame> = players.iter().map(|x| x.relative_frame(&fighters[x.fighter.as_ref()], surfaces)).collect();
+
+    // Clangs are resolved once for each pair of players before any hits are checked.
+    // The loser of a clang cannot hit anyone this frame, the winner continues its attack as normal.
+    let mut clanged: Vec<bool> = players.iter().map(|_| false).collect();
+    for player_a_i in 0..players.len() {
+        for player_b_i in player_a_i + 1..players.len() {
+            if !rules.can_hit_team(players[player_a_i].team, players[player_b_i].team) {
+                continue;
+            }
+
+            if let Some((hitbox_a, hitbox_b)) = clang_check(players, &players_xy, &frames, player_a_i, player_b_i) {
+                let damage_diff = hitbox_a.damage as i64 - hitbox_b.damage as i64; // TODO: retrieve proper damage with move staling etc
+
+                if damage_diff < 9 {
+                    result[player_a_i].push(CollisionResult::Clang { rebound: hitbox_a.enable_rebound });
+                    clanged[player_a_i] = true;
+                }
+                if damage_diff > -9 {
+                    result[player_b_i].push(CollisionResult::Clang { rebound: hitbox_b.enable_rebound });
+                    clanged[player_b_i] = true;
+                }
+            }
+        }
+    }
+
+    for (player_atk_i, player_atk) in players.iter().enumerate() {
+        if clanged[player_atk_i] {
+            continue;
+        }
+
+        let player_atk_xy = players_xy[player_atk_i];
+        let hitboxes_atk = sorted_hitboxes(&frames[player_atk_i]);
+        for (player_def_i, player_def) in players.iter().enumerate() {
+            if player_atk_i != player_def_i && rules.can_hit_team(player_atk.team, player_def.team) && !player_def.is_stamina_ko() {
+                let player_def_xy = players_xy[player_def_i];
+                let fighter_def = &fighters[player_def.fighter.as_ref()];
+                let frame_def = &frames[player_def_i];
+
+                for group in hitbox_groups(&hitboxes_atk) {
+                    if player_atk.in_hitlist(group, player_def_i) {
+                        continue;
+                    }
+
+                    // hitboxes are sorted by priority so the first hitbox to connect wins
+                    'hitbox_atk: for colbox_atk in hitboxes_atk.iter().filter(|x| x.hitbox_ref().group == group) {
+                        let hitbox_atk = colbox_atk.hitbox_ref();
+
+                        if colbox_shield_collision_check(player_atk_xy, colbox_atk, player_def_xy, player_def, fighter_def) {
+                            result[player_atk_i].push(CollisionResult::HitShieldAtk {
+                                hitbox: hitbox_atk.clone(),
+                                power_shield: fighter_def.power_shield.clone(),
+                                player_def_i
+                            });
+                            result[player_def_i].push(CollisionResult::HitShieldDef {
+                                hitbox: hitbox_atk.clone(),
+                                power_shield: fighter_def.power_shield.clone(),
+                                player_atk_i
+                            });
+                            break 'hitbox_atk;
+                        }
+
+                        for colbox_def in &frame_def.get_colboxes() {
+                            match colbox_collision_check(player_atk_xy, colbox_atk, player_def_xy, colbox_def) {
+                                ColBoxCollisionResult::Hit (point) => {
+                                    match &colbox_def.role {
+                                        &CollisionBoxRole::Hurt (ref hurtbox) => {
+                                            result[player_atk_i].push(CollisionResult::HitAtk { hitbox: hitbox_atk.clone(), player_def_i: player_def_i, point });
+                                            result[player_def_i].push(CollisionResult::HitDef { hitbox: hitbox_atk.clone(), hurtbox: hurtbox.clone(), player_atk_i: player_atk_i });
+                                            break 'hitbox_atk;
+                                        }
+                                        &CollisionBoxRole::Invincible => {
+                                            result[player_atk_i].push(CollisionResult::HitAtk { hitbox: hitbox_atk.clone(), player_def_i: player_def_i, point });
+                                            break 'hitbox_atk;
+                                        }
+                                        _ => { }
+                                    }
+                                }
+                                ColBoxCollisionResult::Phantom (_) => {
+                                    match &colbox_def.role {
+                                        &CollisionBoxRole::Hurt (ref hurtbox) => {
+                                            result[player_atk_i].push(CollisionResult::PhantomAtk (hitbox_atk.clone(), player_def_i));
+                                            result[player_def_i].push(CollisionResult::PhantomDef (hitbox_atk.clone(), hurtbox.clone()));
+                                            break 'hitbox_atk;
+                                        }
+                                        _ => { }
+                                    }
+                                }
+                                ColBoxCollisionResult::None => { }
+                            }
+                        }
+                    }
+                }
+
+                'grab: for colbox_atk in &hitboxes_atk {
+                    match &colbox_atk.role {
+                        &CollisionBoxRole::Grab => {
+                            for colbox_def in &frame_def.colboxes[..] {
+                                if let ColBoxCollisionResult::Hit (_) = colbox_collision_check(player_atk_xy, colbox_atk, player_def_xy, colbox_def) {
+                                    result[player_atk_i].push(CollisionResult::GrabAtk (player_def_i));
+                                    result[player_def_i].push(CollisionResult::GrabDef (player_atk_i));
+                                    break 'grab;
+                                }
+                            }
+                        }
+                        _ => { }
+                    }
+                }
+
+                // check colbox links
+                // TODO
+            }
+        }
+    }
+
+    if rules.stage_hazards {
+        stage_hazard_check(&mut result, players, &players_xy, &frames, stage, frame);
+    }
+    result
+}
+
+/// Hazards and hazardous surfaces hit any hurtbox they touch, players standing on a hazardous surface are always touching it.
+/// A player can only be hit by one hazard per frame and is then immune to hazards for the stages hazard_rehit_frames.
+fn stage_hazard_check(result: &mut [Vec<CollisionResult>], players: &[Player], players_xy: &[(f32, f32)], frames: &[ActionFrame], stage: &Stage, frame: usize) {
+    for (player_i, player) in players.iter().enumerate() {
+        if player.hazard_timer > 0 || player.is_stamina_ko() {
+            continue;
+        }
+
+        let (player_x, player_y) = players_xy[player_i];
+        let hurtboxes: Vec<(&CollisionBox, &HurtBox)> = frames[player_i].get_hurtboxes().into_iter().map(|colbox| {
+            match &colbox.role {
+                &CollisionBoxRole::Hurt (ref hurtbox) => (colbox, hurtbox),
+                _ => unreachable!()
+            }
+        }).collect();
+
+        let mut hit: Option<CollisionResult> = None;
+        'hazards: for hazard in stage.hazards.iter().filter(|x| x.is_active(frame as u64)) {
+            for &(colbox, hurtbox) in &hurtboxes {
+                let x = player_x + colbox.point.0;
+                let y = player_y + colbox.point.1;
+                let distance = ((x - hazard.x).powi(2) + (y - hazard.y).powi(2)).sqrt();
+                if distance < hazard.radius + colbox.radius {
+                    hit = Some(CollisionResult::HitStage { hitbox: hazard.hitbox.clone(), hurtbox: hurtbox.clone(), point: (hazard.x, hazard.y) });
+                    break 'hazards;
+                }
+            }
+        }
+
+        if hit.is_none() {
+            'surfaces: for (surface_i, surface) in stage.surfaces.iter().enumerate() {
+                if let Some(ref hitbox) = surface.hazard {
+                    let standing = match player.location {
+                        Location::Surface { platform_i, .. } => platform_i == surface_i,
+                        _                                    => false
+                    };
+
+                    for &(colbox, hurtbox) in &hurtboxes {
+                        let x = player_x + colbox.point.0;
+                        let y = player_y + colbox.point.1;
+                        let point = geometry::closest_point_on_segment(surface.p1(), surface.p2(), (x, y));
+                        let distance = ((x - point.0).powi(2) + (y - point.1).powi(2)).sqrt();
+                        if standing || distance < colbox.radius {
+                            hit = Some(CollisionResult::HitStage { hitbox: hitbox.clone(), hurtbox: hurtbox.clone(), point });
+                            break 'surfaces;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(hit) = hit {
+            result[player_i].push(hit);
+        }
+    }
+}
+
+/// Returns the pair of hitboxes that clang between the two players, if any.
+/// Hitbox groups that have already hit the other player are ignored.
+fn clang_check<'a>(players: &[Player], players_xy: &[(f32, f32)], frames: &'a [ActionFrame], player_a_i: usize, player_b_i: usize) -> Option<(&'a HitBox, &'a HitBox)> {
+    let hitboxes_a = sorted_hitboxes(&frames[player_a_i]);
+    let hitboxes_b = sorted_hitboxes(&frames[player_b_i]);
+    for colbox_a in hitboxes_a {
+        let hitbox_a = colbox_a.hitbox_ref();
+        if !hitbox_a.enable_clang || players[player_a_i].in_hitlist(hitbox_a.group, player_b_i) {
+            continue;
+        }
+        for &colbox_b in &hitboxes_b {
+            let hitbox_b = colbox_b.hitbox_ref();
+            if !hitbox_b.enable_clang || players[player_b_i].in_hitlist(hitbox_b.group, player_a_i) {
+                continue;
+            }
+            if let ColBoxCollisionResult::Hit (_) = colbox_collision_check(players_xy[player_a_i], colbox_a, players_xy[player_b_i], colbox_b) {
+                return Some((hitbox_a, hitbox_b));
+            }
+        }
+    }
+    None
+}
+
+/// Returns the hitboxes of the frame, highest priority first.
+/// Ties are broken by the lowest id and then by the colbox order, so the result is deterministic.
+fn sorted_hitboxes(frame: &ActionFrame) -> Vec<&CollisionBox> {
+    let mut hitboxes = frame.get_hitboxes();
+    hitboxes.sort_by(|a, b| {
+        let a = a.hitbox_ref();
+        let b = b.hitbox_ref();
+        b.priority.cmp(&a.priority).then(a.id.cmp(&b.id))
+    });
+    hitboxes
+}
+
+/// Returns each group used by the hitboxes in ascending order
+fn hitbox_groups(hitboxes: &[&CollisionBox]) -> Vec<u64> {
+    let mut groups: Vec<u64> = hitboxes.iter().map(|x| x.hitbox_ref().group).collect();
+    groups.sort();
+    groups.dedup();
+    groups
+}
+
+fn colbox_collision_check(player1_xy: (f32, f32), colbox1: &CollisionBox,  player2_xy: (f32, f32), colbox2: &CollisionBox) -> ColBoxCollisionResult {
+    let x1 = player1_xy.0 + colbox1.point.0;
+    let y1 = player1_xy.1 + colbox1.point.1;
+    let r1 = colbox1.radius;
+
+    let x2 = player2_xy.0 + colbox2.point.0;
+    let y2 = player2_xy.1 + colbox2.point.1;
+    let r2 = colbox2.radius;
+
+    let check_distance = r1 + r2;
+    let real_distance = ((x1 - x2).powi(2) + (y1 - y2).powi(2)).sqrt();
+
+    if check_distance > real_distance {
+        ColBoxCollisionResult::Hit (((x1 + x2) / 2.0, (y1 + y2) / 2.0))
+    }
+    else if check_distance + 0.01 > real_distance { // TODO: customizable phantom value
+        ColBoxCollisionResult::Phantom (((x1 + x2) / 2.0, (y1 + y2) / 2.0))
+    }
+    else {
+        ColBoxCollisionResult::None
+    }
+}
+
+enum ColBoxCollisionResult {
+    Hit ((f32, f32)),
+    Phantom ((f32, f32)),
+    None
+}
+
+fn colbox_shield_collision_check(player1_xy: (f32, f32), colbox1: &CollisionBox,  player2_xy: (f32, f32), player2: &Player, fighter2: &Fighter) -> bool {
+    if let &Some(ref shield) = &fighter2.shield {
+        if player2.is_shielding() {
+            let x1 = player1_xy.0 + colbox1.point.0;
+            let y1 = player1_xy.1 + colbox1.point.1;
+            let r1 = colbox1.radius;
+
+            let x2 = player2_xy.0 + player2.shield_offset_x + shield.offset_x;
+            let y2 = player2_xy.1 + player2.shield_offset_y + shield.offset_y;
+            let r2 = player2.shield_size(shield);
+
+            let check_distance = r1 + r2;
+            let real_distance = ((x1 - x2).powi(2) + (y1 - y2).powi(2)).sqrt();
+            check_distance > real_distance
+        } else {
+            false
+        }
+    }
+    else {
+        false
+    }
+}
+
+#[allow(dead_code)]
+pub enum CollisionResult {
+    PhantomDef   (HitBox, HurtBox),
+    PhantomAtk   (HitBox, usize),
+    HitDef       { hitbox: HitBox, hurtbox: HurtBox, player_atk_i: usize },
+    HitAtk       { hitbox: HitBox, player_def_i: usize, point: (f32, f32) },
+    HitShieldAtk { hitbox: HitBox, power_shield: Option<PowerShield>, player_def_i: usize },
+    HitShieldDef { hitbox: HitBox, power_shield: Option<PowerShield>, player_atk_i: usize },
+    ReflectDef   (HitBox), // TODO: add further details required for recreating projectile
+    ReflectAtk   (HitBox),
+    AbsorbDef    (HitBox),
+    AbsorbAtk    (HitBox),
+    GrabDef      (usize),
+    GrabAtk      (usize),
+    Clang        { rebound: bool },
+    HitStage     { hitbox: HitBox, hurtbox: HurtBox, point: (f32, f32) }, // point is where the hazard hit from
+}
+
+// Thoughts on special cases
+// *    when one hitbox connects to multiple hurtboxes HitDef is sent to all defenders
+// *    when one hurtbox is hit by multiple hitboxes it receives HitDef from all attackers
+// *    when multiple hitbox groups of one attacker connect with the same defender on the same frame the defender receives HitDef from each group
@@ -7,10 +7,16 @@ use serde_json;
 use treeflection::{Node, NodeRunner, NodeToken};
 
 #[derive(Clone, Serialize, Deserialize, Node)]
+#[serde(default)]
 pub struct Config {
     pub current_package:       Option<String>,
     pub netplay_region:        Option<String>,
+    pub netplay_input_delay:   usize, // frames between pressing a button and it taking effect during netplay
+    pub netplay_max_rollback:  usize, // frames the game can run ahead of a peer's inputs before waiting for them
+    pub history_max_mb:        Option<usize>, // memory used by each of the player and stage histories before the oldest frames are dropped, None keeps every frame
     pub auto_save_replay:      bool,
+    pub replay_cache:          bool, // store the player and stage history in replays so they play back unchanged after the package is modified
+    pub store_checksums:       bool, // store a checksum of every frame, used to find the frame a desync started on
     pub verify_package_hashes: bool,
     pub fullscreen:            bool,
     pub physical_device_name:  Option<String>,
@@ -49,7 +55,12 @@ impl Default for Config {
         Config {
             current_package:       None,
             netplay_region:        None,
+            netplay_input_delay:   2,
+            netplay_max_rollback:  8,
+            history_max_mb:        Some(256),
             auto_save_replay:      false,
+            replay_cache:          false,
+            store_checksums:       false,
             verify_package_hashes: true,
             fullscreen:            false,
             physical_device_name:  None,
@@ -0,0 +1,76 @@
+use serde_json::Value;
+
+use crate::player::Player;
+
+/// The first difference between the players stored in a replay and the players after resimulating it
+pub struct Divergence {
+    pub frame:  usize,
+    pub player: Option<usize>, // None when the number of players differs
+    pub fields: Vec<String>, // path of every field of the player that differs e.g. location.x
+}
+
+impl Divergence {
+    /// Returns the fields of the first player that differs, None if every player is identical
+    pub fn compare(frame: usize, expected: &[Player], actual: &[Player]) -> Option<Divergence> {
+        if expected.len() != actual.len() {
+            let fields = vec!(format!("{} players instead of {}", actual.len(), expected.len()));
+            return Some(Divergence { frame, player: None, fields });
+        }
+
+        for (i, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+            let mut fields = vec!();
+            diff_values("", &serde_json::to_value(expected).unwrap(), &serde_json::to_value(actual).unwrap(), &mut fields);
+            if !fields.is_empty() {
+                return Some(Divergence { frame, player: Some(i), fields });
+            }
+        }
+        None
+    }
+
+    /// The checksum of the frame differs, used when the players of the frame are not available to compare
+    pub fn checksum(frame: usize) -> Divergence {
+        Divergence { frame, player: None, fields: vec!(String::from("the checksum differs")) }
+    }
+
+    pub fn description(&self) -> String {
+        const MAX_FIELDS: usize = 10;
+        let mut fields = self.fields.iter().take(MAX_FIELDS).cloned().collect::<Vec<_>>().join(", ");
+        if self.fields.len() > MAX_FIELDS {
+            fields.push_str(&format!(" and {} more", self.fields.len() - MAX_FIELDS));
+        }
+
+        match self.player {
+            Some(player) => format!("diverged on frame {}, player {}: {}", self.frame, player, fields),
+            None         => format!("diverged on frame {}: {}", self.frame, fields),
+        }
+    }
+}
+
+/// Appends the path of every value that differs between expected and actual.
+/// Arrays of different lengths are reported as a single difference at the path of the array.
+pub fn diff_values(path: &str, expected: &Value, actual: &Value, fields: &mut Vec<String>) {
+    let child_path = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    match (expected, actual) {
+        (&Value::Object (ref expected), &Value::Object (ref actual)) => {
+            for (key, expected) in expected {
+                match actual.get(key) {
+                    Some(actual) => diff_values(&child_path(key.as_str()), expected, actual, fields),
+                    None         => fields.push(child_path(key.as_str())),
+                }
+            }
+            for key in actual.keys().filter(|x| !expected.contains_key(*x)) {
+                fields.push(child_path(key.as_str()));
+            }
+        }
+        (&Value::Array (ref expected), &Value::Array (ref actual)) if expected.len() == actual.len() => {
+            for (i, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+                diff_values(&format!("{}[{}]", path, i), expected, actual, fields);
+            }
+        }
+        _ => {
+            if expected != actual {
+                fields.push(path.to_string());
+            }
+        }
+    }
+}
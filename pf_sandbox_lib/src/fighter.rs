@@ -82,6 +82,7 @@ impl Default for Fighter {
             shield:                   None,
             power_shield:             None,
             tech:                     None,
+            wall_jump:                None,
             missed_tech_forced_getup: Some(200),
             run_turn_flip_dir_frame:  30,
             tilt_turn_flip_dir_frame: 5,
@@ -135,6 +136,7 @@ pub struct Fighter {
     pub shield:                   Option<Shield>,
     pub power_shield:             Option<PowerShield>,
     pub tech:                     Option<Tech>,
+    pub wall_jump:                Option<WallJump>,
     pub missed_tech_forced_getup: Option<u64>,
     pub run_turn_flip_dir_frame:  u64,
     pub tilt_turn_flip_dir_frame: u64,
@@ -157,6 +159,23 @@ impl Default for Tech {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize, Node)]
+pub struct WallJump {
+    pub window: u64, // frames after touching a wall that the stick can be smashed away from it to wall jump
+    pub x_vel:  f32,
+    pub y_vel:  f32,
+}
+
+impl Default for WallJump {
+    fn default() -> Self {
+        WallJump {
+            window: 6,
+            x_vel:  1.3,
+            y_vel:  2.8,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub struct LCancel {
     pub active_window: u64,
@@ -542,6 +561,7 @@ pub enum Action {
     JumpB,
     JumpAerialF,
     JumpAerialB,
+    WallJump,
     TiltTurn,
     RunTurn,
     SmashTurn,
@@ -576,6 +596,8 @@ pub enum Action {
     MissedTechGetupF,
     MissedTechGetupN,
     MissedTechGetupB,
+    WallTech,
+    CeilingTech,
     Rebound, // State after clang
     LedgeRoll,
     LedgeRollSlow,
@@ -740,6 +762,9 @@ impl Default for HurtBox {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Node)]
 pub struct HitBox {
+    pub id:                 u64, // breaks priority ties, lowest id wins
+    pub group:              u64, // hitboxes in the same group share a hitlist, so only one of them can hit each player
+    pub priority:           u64, // when multiple hitboxes in a group intersect, the highest priority hitbox is used
     pub shield_damage:      f32,
     pub damage:             f32,
     pub bkb:                f32, // base knockback
@@ -750,12 +775,15 @@ pub struct HitBox {
     pub enable_rebound:     bool,
     pub effect:             HitboxEffect,
     pub enable_reverse_hit: bool, // if the defender is behind the attacker the direction is reversed.
-    //pub team_funnel_angle: Option<f32>, // degrees to +- towards nearest teammate
+    pub team_funnel_angle:  Option<f32>, // degrees to +- towards nearest teammate
 }
 
 impl Default for HitBox {
     fn default() -> HitBox {
         HitBox {
+            id:                 0,
+            group:              0,
+            priority:           0,
             shield_damage:      0.0,
             damage:             6.0,
             bkb:                40.0,
@@ -764,6 +792,7 @@ impl Default for HitBox {
             enable_clang:       true,
             enable_rebound:     true,
             enable_reverse_hit: true,
+            team_funnel_angle:  None,
             hitstun:            HitStun::default(),
             effect:             HitboxEffect::default(),
         }
@@ -40,6 +40,13 @@ pub fn save_struct_compressed<T: Serialize>(filename: PathBuf, object: &T) {
     zip.finish().unwrap();
 }
 
+/// Returns the size of the file save_struct_compressed would create
+pub fn compressed_size<T: Serialize>(object: &T) -> usize {
+    let mut zip = ZipWriter::new(Cursor::new(vec!()));
+    write_to_zip(&mut zip, "data.json", object);
+    zip.finish().unwrap().into_inner().len()
+}
+
 pub fn load_struct<T: DeserializeOwned>(filename: PathBuf) -> Result<T, String> {
     let json = load_file(filename)?;
     serde_json::from_str(&json).map_err(|x| format!("{:?}", x))
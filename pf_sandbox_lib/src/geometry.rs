@@ -17,6 +17,18 @@ pub fn segments_intersect(p1: (f32, f32), q1: (f32, f32), p2: (f32, f32), q2: (f
     (o4 == 0 && point_on_segment(p2, q1, q2))
 }
 
+/// Returns the point on segment pq that is closest to point r
+pub fn closest_point_on_segment(p: (f32, f32), q: (f32, f32), r: (f32, f32)) -> (f32, f32) {
+    let d_x = q.0 - p.0;
+    let d_y = q.1 - p.1;
+    let length_sq = d_x * d_x + d_y * d_y;
+    if length_sq == 0.0 {
+        return p;
+    }
+    let t = (((r.0 - p.0) * d_x + (r.1 - p.1) * d_y) / length_sq).max(0.0).min(1.0);
+    (p.0 + t * d_x, p.1 + t * d_y)
+}
+
 /// Returns the orientation of triplet (p, q, r)
 /// 0 - colinear
 /// 1 - clockwise
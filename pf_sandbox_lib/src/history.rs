@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use bincode;
+use serde::ser::Serialize;
+use serde::de::DeserializeOwned;
+use treeflection::{Node, NodeRunner, NodeToken};
+
+const KEYFRAME_INTERVAL: usize = 60;
+const MIN_CHUNKS: usize = 2; // chunks kept regardless of the memory cap, so netplay can always roll back
+
+/// Stores a value for every frame, indexed by frame.
+/// Every KEYFRAME_INTERVAL frames the value is stored in full as a keyframe, the frames in between are stored as a delta against the keyframe.
+/// Values are stored as bincode, so accessing a frame deserializes a new copy of it.
+/// When max_bytes is exceeded the oldest frames are dropped, the index of the remaining frames is unaffected.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct History<T> {
+    first_frame: usize, // frame of the first keyframe, frames before it have been dropped
+    len:         usize,
+    chunks:      VecDeque<Chunk>,
+    bytes:       usize,
+    max_bytes:   Option<usize>,
+    phantom:     PhantomData<T>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Chunk {
+    keyframe: Vec<u8>,
+    deltas:   Vec<Vec<u8>>, // deltas[i] holds the frame i + 1 frames after the keyframe
+}
+
+impl Chunk {
+    fn bytes(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(|x| x.len()).sum::<usize>()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> History<T> {
+    pub fn new() -> History<T> {
+        History {
+            first_frame: 0,
+            len:         0,
+            chunks:      VecDeque::new(),
+            bytes:       0,
+            max_bytes:   None,
+            phantom:     PhantomData,
+        }
+    }
+
+    /// Set the memory cap, None keeps every frame
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.enforce_max_bytes();
+    }
+
+    /// Returns the number of frames in the history, including frames that have been dropped
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the first frame that has not been dropped
+    pub fn first_frame(&self) -> usize {
+        self.first_frame
+    }
+
+    /// Returns the number of bytes used to store the frames
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns a copy of the value at the frame, None if the frame is not stored.
+    /// Also None if the frame cannot be deserialized, e.g. the history was stored by a version of PF Sandbox with a different T.
+    pub fn get(&self, frame: usize) -> Option<T> {
+        if frame < self.first_frame || frame >= self.len {
+            return None;
+        }
+
+        let offset = frame - self.first_frame;
+        let chunk = &self.chunks[offset / KEYFRAME_INTERVAL];
+        let result = match offset % KEYFRAME_INTERVAL {
+            0 => bincode::deserialize(&chunk.keyframe),
+            i => bincode::deserialize(&apply_delta(&chunk.keyframe, &chunk.deltas[i - 1])),
+        };
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                error!("Frame {} of the history could not be deserialized: {}", frame, err);
+                None
+            }
+        }
+    }
+
+    /// Returns a copy of the value of every stored frame in the range, frames that are not stored are skipped
+    pub fn range(&self, range: Range<usize>) -> Vec<T> {
+        range.filter_map(|frame| self.get(frame)).collect()
+    }
+
+    /// Returns a copy of the value at the last frame
+    pub fn last(&self) -> Option<T> {
+        self.get(self.len.wrapping_sub(1))
+    }
+
+    pub fn push(&mut self, value: &T) {
+        let data = bincode::serialize(value).unwrap();
+        let stored = self.len - self.first_frame;
+        if stored % KEYFRAME_INTERVAL == 0 {
+            self.bytes += data.len();
+            self.chunks.push_back(Chunk { keyframe: data, deltas: vec!() });
+        } else {
+            let chunk = self.chunks.back_mut().unwrap();
+            let delta = create_delta(&chunk.keyframe, &data);
+            self.bytes += delta.len();
+            chunk.deltas.push(delta);
+        }
+        self.len += 1;
+        self.enforce_max_bytes();
+    }
+
+    /// Drops all frames from len onwards
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        if len <= self.first_frame {
+            self.chunks.clear();
+            self.first_frame = len;
+        } else {
+            let stored = len - self.first_frame;
+            self.chunks.truncate((stored + KEYFRAME_INTERVAL - 1) / KEYFRAME_INTERVAL);
+            self.chunks.back_mut().unwrap().deltas.truncate((stored - 1) % KEYFRAME_INTERVAL);
+        }
+        self.len = len;
+        self.bytes = self.chunks.iter().map(|x| x.bytes()).sum();
+    }
+
+    fn enforce_max_bytes(&mut self) {
+        if let Some(max_bytes) = self.max_bytes {
+            while self.bytes > max_bytes && self.chunks.len() > MIN_CHUNKS {
+                let chunk = self.chunks.pop_front().unwrap();
+                self.bytes -= chunk.bytes();
+                self.first_frame += KEYFRAME_INTERVAL;
+            }
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Default for History<T> {
+    fn default() -> History<T> {
+        History::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Node for History<T> {
+    fn node_step(&mut self, mut runner: NodeRunner) -> String {
+        match runner.step() {
+            NodeToken::Help => {
+                String::from(r#"
+History Help
+
+Commands:
+*   help - display this help
+*   info - display the number of frames stored and the memory they use"#)
+            }
+            NodeToken::Custom (action, _) => {
+                match action.as_ref() {
+                    "info" => format!("Frames {}..{} stored in {} bytes", self.first_frame, self.len, self.bytes),
+                    _      => format!("History cannot '{}'", action)
+                }
+            }
+            action => format!("History cannot '{:?}'", action)
+        }
+    }
+}
+
+const MAX_GAP: usize = 8; // equal bytes shorter than this are included in a run, as starting a new run costs more
+
+/// The delta is a sequence of runs, each run skips over bytes equal to the base, then replaces bytes.
+/// Encoded as: new length, then for each run: skip length, run length, run bytes. All lengths are varints.
+fn create_delta(base: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = vec!();
+    write_varint(&mut delta, new.len());
+
+    let equal = |i: usize| i < base.len() && base[i] == new[i];
+    let mut written = 0;
+    let mut i = 0;
+    while i < new.len() {
+        if equal(i) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i;
+        while i < new.len() && i - end < MAX_GAP {
+            if !equal(i) {
+                end = i + 1;
+            }
+            i += 1;
+        }
+
+        write_varint(&mut delta, start - written);
+        write_varint(&mut delta, end - start);
+        delta.extend_from_slice(&new[start..end]);
+        written = end;
+        i = end;
+    }
+
+    delta.shrink_to_fit();
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut result = Vec::with_capacity(len);
+
+    while pos < delta.len() {
+        let skip = read_varint(delta, &mut pos);
+        let run = read_varint(delta, &mut pos);
+        let skip_start = result.len();
+        result.extend_from_slice(&base[skip_start..skip_start + skip]);
+        result.extend_from_slice(&delta[pos..pos + run]);
+        pos += run;
+    }
+
+    // the runs reach the end of the value when it is longer than the base, so there is no tail to copy
+    let tail_start = result.len();
+    if tail_start < len {
+        result.extend_from_slice(&base[tail_start..len]);
+    }
+    result
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
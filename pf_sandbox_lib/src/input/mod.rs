@@ -1,13 +1,16 @@
 pub mod maps;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+
+#[cfg(feature = "gamepad")]
+pub use gamepad::{code_to_usize, read_generic};
 
 use std::ops::Index;
 use std::f32;
 
-use gilrs_core::{EvCode, Gamepad, EventType};
-use uuid::Uuid;
 use treeflection::{Node, NodeRunner, NodeToken};
 
-use maps::{ControllerMap, AnalogFilter, AnalogDest, DigitalFilter, DigitalDest};
+use maps::{AnalogDest, DigitalDest};
 
 /// Stores the first value returned from an input source
 pub struct Deadzone {
@@ -116,158 +119,74 @@ impl PlayerInput {
             history: vec!(ControllerInput::empty(); 8),
         }
     }
-}
-
-// gilrs returns the code as a u32 in the following formats
-// Linux:
-// *   16 bytes - kind
-// *   16 bytes - code
-// Windows:
-// *   24 bytes - padding
-// *   8 bytes  - code
-
-// On linux we only need the code so we strip out the kind, so the numbers are nicer to work with (when creating maps)
-pub fn code_to_usize(code: &EvCode) -> usize {
-    (code.into_u32() & 0xFFFF) as usize
-}
 
-/// Add a single controller to inputs, reading from the passed gamepad
-pub fn read_generic(controller_maps: &[ControllerMap], state: &mut ControllerInput, events: Vec<EventType>, gamepad: &Gamepad, deadzone: &mut Deadzone) -> ControllerInput {
-    let mut controller_map_use = None;
-    for controller_map in controller_maps {
-        if controller_map.name == gamepad.name() && controller_map.uuid == Uuid::from_bytes(gamepad.uuid()) {
-            controller_map_use = Some(controller_map);
-        }
+    /// Creates the input of a controller on the passed frame
+    /// history structure: frames Vec<controllers Vec<ControllerInput>>
+    pub fn from_history(history: &[Vec<ControllerInput>], controller_i: usize, frame: i64) -> PlayerInput {
+        PlayerInput::from_8frames(get_8frames_of_input(history, controller_i, frame))
     }
 
-    if let Some(controller_map) = controller_map_use {
-        // update internal state
-        for event in events {
-            match event {
-                // TODO: better handle multiple sources pointing to the same destination
-                // maybe keep a unique ControllerInput state for each source input
-                EventType::ButtonPressed (code) => {
-                    for map in &controller_map.analog_maps {
-                        if let AnalogFilter::FromDigital { value } = map.filter {
-                            if map.source == code_to_usize(&code) {
-                                state.set_analog_dest(map.dest.clone(), value);
-                            }
-                        }
-                    }
-
-                    for map in &controller_map.digital_maps {
-                        if let DigitalFilter::FromDigital = map.filter {
-                            if map.source == code_to_usize(&code) {
-                                state.set_digital_dest(map.dest.clone(), true);
-                            }
-                        };
-                    }
-                }
-                EventType::ButtonReleased (code) => {
-                    for map in &controller_map.analog_maps {
-                        if let AnalogFilter::FromDigital { .. } = map.filter {
-                            if map.source == code_to_usize(&code) {
-                                state.set_analog_dest(map.dest.clone(), 0.0);
-                            }
-                        }
-                    }
-
-                    for map in &controller_map.digital_maps {
-                        if let DigitalFilter::FromDigital = map.filter {
-                            if map.source == code_to_usize(&code) {
-                                state.set_digital_dest(map.dest.clone(), false);
-                            }
-                        };
-                    }
-                }
-                EventType::AxisValueChanged (value, code) => {
-                    for map in &controller_map.analog_maps {
-                        if let AnalogFilter::FromAnalog { min, max, flip } = map.filter {
-                            // Implemented as per https://stackoverflow.com/questions/345187/math-mapping-numbers
-                            let mut new_value = ((value-min) as f32) / ((max-min) as f32) * 2.0 - 1.0;
-
-                            new_value *= if flip { -1.0 } else { 1.0 };
-
-                            match &map.dest {
-                                &AnalogDest::LTrigger | &AnalogDest::RTrigger => {
-                                    new_value = (new_value + 1.0) / 2.0;
-                                }
-                                _ => { }
-                            }
-
-                            if map.source == code_to_usize(&code) {
-                                state.set_analog_dest(map.dest.clone(), new_value);
-                            }
-                        };
-                    }
-
-                    for map in &controller_map.digital_maps {
-                        if let DigitalFilter::FromAnalog { min, max } = map.filter {
-                            let value = value >= min && value <= max;
-
-                            if map.source == code_to_usize(&code) {
-                                state.set_digital_dest(map.dest.clone(), value);
-                            }
-                        };
-                    }
-                }
-                EventType::Connected => {
-                    state.plugged_in = true;
-                }
-                EventType::Disconnected => {
-                    state.plugged_in = false;
-                }
-            }
-        }
-
-        // convert state floats to bytes
-        let raw_stick_x   = generic_to_byte(state.stick_x);
-        let raw_stick_y   = generic_to_byte(state.stick_y);
-        let raw_c_stick_x = generic_to_byte(state.c_stick_x);
-        let raw_c_stick_y = generic_to_byte(state.c_stick_y);
-
-        let raw_l_trigger = generic_to_byte(state.l_trigger);
-        let raw_r_trigger = generic_to_byte(state.r_trigger);
-
-        // update deadzones
-        if state.plugged_in && !deadzone.plugged_in { // Only reset deadzone if controller was just plugged in
-            *deadzone = Deadzone {
+    /// inputs[0] is the current frame, inputs[1] the previous frame and so on
+    fn from_8frames(inputs: Vec<ControllerInput>) -> PlayerInput {
+        if inputs[0].plugged_in {
+            PlayerInput {
                 plugged_in: true,
-                stick_x:    raw_stick_x,
-                stick_y:    raw_stick_y,
-                c_stick_x:  raw_c_stick_x,
-                c_stick_y:  raw_c_stick_y,
-                l_trigger:  raw_l_trigger,
-                r_trigger:  raw_r_trigger,
-            };
+
+                up:    Button { value: inputs[0].up,    press: inputs[0].up    && !inputs[1].up },
+                down:  Button { value: inputs[0].down,  press: inputs[0].down  && !inputs[1].down },
+                right: Button { value: inputs[0].right, press: inputs[0].right && !inputs[1].right },
+                left:  Button { value: inputs[0].left,  press: inputs[0].left  && !inputs[1].left },
+                y:     Button { value: inputs[0].y,     press: inputs[0].y     && !inputs[1].y },
+                x:     Button { value: inputs[0].x,     press: inputs[0].x     && !inputs[1].x },
+                b:     Button { value: inputs[0].b,     press: inputs[0].b     && !inputs[1].b },
+                a:     Button { value: inputs[0].a,     press: inputs[0].a     && !inputs[1].a },
+                l:     Button { value: inputs[0].l,     press: inputs[0].l     && !inputs[1].l },
+                r:     Button { value: inputs[0].r,     press: inputs[0].r     && !inputs[1].r },
+                z:     Button { value: inputs[0].z,     press: inputs[0].z     && !inputs[1].z },
+                start: Button { value: inputs[0].start, press: inputs[0].start && !inputs[1].start },
+
+                stick_x:   Stick { value: inputs[0].stick_x,   diff: inputs[0].stick_x   - inputs[1].stick_x },
+                stick_y:   Stick { value: inputs[0].stick_y,   diff: inputs[0].stick_y   - inputs[1].stick_y },
+                c_stick_x: Stick { value: inputs[0].c_stick_x, diff: inputs[0].c_stick_x - inputs[1].c_stick_x },
+                c_stick_y: Stick { value: inputs[0].c_stick_y, diff: inputs[0].c_stick_y - inputs[1].c_stick_y },
+
+                l_trigger:  Trigger { value: inputs[0].l_trigger, diff: inputs[0].l_trigger - inputs[1].l_trigger },
+                r_trigger:  Trigger { value: inputs[0].r_trigger, diff: inputs[0].r_trigger - inputs[1].r_trigger },
+                history: inputs,
+            }
         }
-        if !state.plugged_in {
-            *deadzone = Deadzone::empty();
+        else {
+            PlayerInput::empty()
         }
+    }
+}
 
-        // convert bytes to result floats
-        let (stick_x, stick_y)     = stick_filter(stick_deadzone(raw_stick_x,   deadzone.stick_x),   stick_deadzone(raw_stick_y,   deadzone.stick_y));
-        let (c_stick_x, c_stick_y) = stick_filter(stick_deadzone(raw_c_stick_x, deadzone.c_stick_x), stick_deadzone(raw_c_stick_y, deadzone.c_stick_y));
-
-        let l_trigger = trigger_filter(raw_l_trigger.saturating_sub(deadzone.l_trigger));
-        let r_trigger = trigger_filter(raw_r_trigger.saturating_sub(deadzone.r_trigger));
+/// converts frames Vec<controllers Vec<ControllerInput>> into frames Vec<ControllerInput> for the specified controller_i
+/// Output must be 8 frames long, any missing frames due to either netplay lag or the game just starting are filled in
+fn get_8frames_of_input(game_inputs: &[Vec<ControllerInput>], controller_i: usize, frame: i64) -> Vec<ControllerInput> {
+    let mut result: Vec<ControllerInput> = vec!();
+    let empty_vec = vec!();
 
-        ControllerInput {
-            stick_x,
-            stick_y,
-            c_stick_x,
-            c_stick_y,
-            l_trigger,
-            r_trigger,
-            ..state.clone()
-        }
-    } else {
-        ControllerInput::default()
+    for frame_i in (frame-8..frame).rev() {
+        result.push(
+            if frame_i < 0 {
+                ControllerInput::empty()
+            }
+            else {
+                let controllers = match game_inputs.get(frame_i as usize) {
+                    Some(controllers) => controllers,
+                    None              => game_inputs.last().unwrap_or(&empty_vec)
+                };
+                match controllers.get(controller_i) {
+                    Some(value) => value.clone(),
+                    None        => ControllerInput::empty()
+                }
+            }
+        );
     }
-}
 
-fn generic_to_byte(value: f32) -> u8 {
-    (value.min(1.0).max(-1.0) * 127.0 + 127.0) as u8
+    assert!(result.len() == 8, "get_8frames_of_input needs to return a vector of size 8 but it was {}", result.len());
+    result
 }
 
 /// use the first received stick value to reposition the current stick value around 128
@@ -318,7 +237,7 @@ pub fn trigger_filter(trigger: u8) -> f32 {
 }
 
 /// Internal input storage
-#[derive(Clone, Default, Serialize, Deserialize, Node)]
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Node)]
 pub struct ControllerInput {
     pub plugged_in: bool,
 
@@ -2,7 +2,11 @@ use serde_json::{Value, Number};
 
 pub fn build_version() -> String { String::from(env!("BUILD_VERSION")) }
 
-pub fn engine_version() -> u64 { 15 }
+pub fn engine_version() -> u64 { 26 }
+
+/// Increase whenever Replay or any type stored in a replay changes and add an upgrade_replayN function.
+/// The cache stores players and stages as bincode which cannot be upgraded, it is dropped on load when engine_version differs.
+pub fn replay_format_version() -> u64 { 5 }
 
 pub fn engine_version_json() -> Value {
     Value::Number(Number::from(engine_version()))
@@ -34,6 +38,9 @@ pub(crate) fn upgrade_to_latest_fighter(fighter: &mut Value, file_name: &str) {
     else if fighter_engine_version < engine_version() {
         for upgrade_from in fighter_engine_version..engine_version() {
             match upgrade_from {
+                24 => { upgrade_fighter24(fighter) }
+                16 => { upgrade_fighter16(fighter) }
+                15 => { upgrade_fighter15(fighter) }
                 14 => { upgrade_fighter14(fighter) }
                 13 => { upgrade_fighter13(fighter) }
                 12 => { upgrade_fighter12(fighter) }
@@ -63,7 +70,15 @@ pub(crate) fn upgrade_to_latest_stage(stage: &mut Value, file_name: &str) {
         // TODO: Display warning in window
     }
     else if stage_engine_version < engine_version() {
-        // TODO: Handle upgrades here
+        for upgrade_from in stage_engine_version..engine_version() {
+            match upgrade_from {
+                25 => { upgrade_stage25(stage) }
+                23 => { upgrade_stage23(stage) }
+                22 => { upgrade_stage22(stage) }
+                21 => { upgrade_stage21(stage) }
+                _ => { }
+            }
+        }
         upgrade_engine_version(stage);
     }
 }
@@ -75,7 +90,17 @@ pub(crate) fn upgrade_to_latest_rules(rules: &mut Value) {
         // TODO: Display warning in window
     }
     else if rules_engine_version < engine_version() {
-        // TODO: Handle upgrades here
+        for upgrade_from in rules_engine_version..engine_version() {
+            match upgrade_from {
+                22 => { upgrade_rules22(rules) }
+                20 => { upgrade_rules20(rules) }
+                19 => { upgrade_rules19(rules) }
+                18 => { upgrade_rules18(rules) }
+                17 => { upgrade_rules17(rules) }
+                16 => { upgrade_rules16(rules) }
+                _ => { }
+            }
+        }
         upgrade_engine_version(rules);
     }
 }
@@ -92,6 +117,45 @@ pub(crate) fn upgrade_to_latest_meta(meta: &mut Value) {
     }
 }
 
+/// Replays from before the header was added are identified by their fields
+fn get_replay_format_version(replay: &Value) -> u64 {
+    if let Some (version) = replay.pointer("/header/format_version").and_then(|x| x.as_u64()) {
+        version
+    } else if replay.get("package_hash").is_some() {
+        2
+    } else if replay.get("player_history").map(|x| x.is_object()).unwrap_or(false) {
+        1
+    } else {
+        0
+    }
+}
+
+/// Returns an error describing why the replay cannot be upgraded
+pub fn upgrade_to_latest_replay(replay: &mut Value) -> Result<(), String> {
+    if !replay.is_object() {
+        return Err(String::from("The replay is not a JSON object"));
+    }
+
+    let replay_version = get_replay_format_version(replay);
+    if replay_version > replay_format_version() {
+        return Err(format!("The replay format version {} is newer than this version of PF Sandbox. Please upgrade to the latest version.", replay_version));
+    }
+    else if replay_version < replay_format_version() {
+        for upgrade_from in replay_version..replay_format_version() {
+            match upgrade_from {
+                4 => { upgrade_replay4(replay) }
+                3 => { upgrade_replay3(replay) }
+                2 => { upgrade_replay2(replay) }
+                1 => { upgrade_replay1(replay) }
+                0 => { upgrade_replay0(replay) }
+                _ => { }
+            }
+        }
+        replay["header"]["format_version"] = Value::Number(Number::from(replay_format_version()));
+    }
+    Ok(())
+}
+
 fn get_vec<'a>(parent: &'a mut Value, member: &str) -> Option<&'a mut Vec<Value>> {
     if let &mut Value::Object (ref mut object) = parent {
         if let Some (array) = object.get_mut(member) {
@@ -106,6 +170,275 @@ fn get_vec<'a>(parent: &'a mut Value, member: &str) -> Option<&'a mut Vec<Value>
 // Important:
 // Upgrades cannot rely on current structs as future changes may break those past upgrades
 
+/// add checksums, they are unknown as the game is not resimulated
+fn upgrade_replay4(replay: &mut Value) {
+    if let &mut Value::Object (ref mut replay) = replay {
+        replay.insert(String::from("checksums"), json!([]));
+    }
+}
+
+/// add metadata, the winner is unknown as the game is not resimulated
+fn upgrade_replay3(replay: &mut Value) {
+    let fighters: Vec<Value> = replay.get("selected_players").and_then(|x| x.as_array())
+        .map(|players| players.iter().map(|x| x.get("fighter").cloned().unwrap_or(json!(""))).collect())
+        .unwrap_or_default();
+    let player_tags = vec!(json!(""); fighters.len());
+    let stage = replay.get("selected_stage").cloned().unwrap_or(json!(""));
+    let duration = replay.get("input_history").and_then(|x| x.as_array()).map(|x| x.len()).unwrap_or(0);
+
+    // timestamps are stored as RFC 3339 e.g. 2019-03-02T14:05:09.123+10:00, which is displayed as 2019-03-02 14:05:09
+    let timestamp = replay.get("timestamp").and_then(|x| x.as_str()).unwrap_or("");
+    let name: String = timestamp.chars().take(19).map(|x| if x == 'T' { ' ' } else { x }).collect();
+
+    if let &mut Value::Object (ref mut replay) = replay {
+        replay.insert(String::from("metadata"), json!({
+            "name":        name,
+            "fighters":    fighters,
+            "stage":       stage,
+            "player_tags": player_tags,
+            "winner":      null,
+            "duration":    duration,
+            "set":         null,
+            "tags":        [],
+        }));
+    }
+}
+
+/// move package_hash into a header, the engine and build versions of the replay are unknown
+fn upgrade_replay2(replay: &mut Value) {
+    if let &mut Value::Object (ref mut replay) = replay {
+        let package_hash = replay.remove("package_hash").unwrap_or(json!(""));
+        replay.insert(String::from("header"), json!({
+            "format_version": 3,
+            "engine_version": 0,
+            "build_version":  "",
+            "package_hash":   package_hash,
+        }));
+    }
+}
+
+/// move player_history and stage_history into an optional cache, add package_hash
+fn upgrade_replay1(replay: &mut Value) {
+    if let &mut Value::Object (ref mut replay) = replay {
+        let player_history = replay.remove("player_history");
+        let stage_history = replay.remove("stage_history");
+        let cache = match (player_history, stage_history) {
+            (Some (player_history), Some (stage_history)) => json!({
+                "player_history": player_history,
+                "stage_history":  stage_history,
+            }),
+            _ => Value::Null
+        };
+        replay.insert(String::from("cache"), cache);
+        replay.insert(String::from("package_hash"), json!(""));
+    }
+}
+
+/// remove the uncompressed player_history and stage_history, the replay is resimulated from its inputs instead
+/// add a handicap to each player that has no effect
+fn upgrade_replay0(replay: &mut Value) {
+    if let &mut Value::Object (ref mut replay) = replay {
+        replay.remove("player_history");
+        replay.remove("stage_history");
+    }
+
+    if let Some (players) = get_vec(replay, "selected_players") {
+        for player in players {
+            if let &mut Value::Object (ref mut player) = player {
+                player.insert(String::from("handicap"), json!({
+                    "starting_damage":       0.0,
+                    "damage_taken_ratio":    1.0,
+                    "damage_dealt_ratio":    1.0,
+                    "knockback_taken_ratio": 1.0,
+                    "extra_stocks":          0,
+                }));
+            }
+        }
+    }
+}
+
+/// add legal
+fn upgrade_stage25(stage: &mut Value) {
+    if let &mut Value::Object (ref mut stage) = stage {
+        stage.insert(String::from("legal"), Value::Bool(false));
+    }
+}
+
+/// add wall_jump, WallJump, WallTech and CeilingTech
+/// The new actions start as copies of the similar JumpAerialF and TechN actions.
+fn upgrade_fighter24(fighter: &mut Value) {
+    if let &mut Value::Object (ref mut fighter) = fighter {
+        fighter.insert(String::from("wall_jump"), Value::Null);
+    }
+
+    if let Some (actions) = get_vec(fighter, "actions") {
+        if actions.len() > 44 {
+            let jump_aerial = actions[15].clone();
+            let tech = actions[44].clone();
+            actions.insert(17, jump_aerial);
+            actions.insert(50, tech.clone());
+            actions.insert(51, tech);
+        }
+    }
+}
+
+/// add bounce and no_ledge_grab to surfaces and conveyor to floors
+fn upgrade_stage23(stage: &mut Value) {
+    if let Some (surfaces) = get_vec(stage, "surfaces") {
+        for surface in surfaces {
+            if let &mut Value::Object (ref mut surface) = surface {
+                surface.insert(String::from("bounce"), json!(0.0));
+                surface.insert(String::from("no_ledge_grab"), Value::Bool(false));
+                if let Some(&mut Value::Object (ref mut floor)) = surface.get_mut("floor") {
+                    floor.insert(String::from("conveyor"), json!(0.0));
+                }
+            }
+        }
+    }
+}
+
+/// add stage_hazards
+fn upgrade_rules22(rules: &mut Value) {
+    if let &mut Value::Object (ref mut rules) = rules {
+        rules.insert(String::from("stage_hazards"), Value::Bool(true));
+    }
+}
+
+/// add hazards and hazard_rehit_frames to stage and hazard to surfaces
+fn upgrade_stage22(stage: &mut Value) {
+    if let Some (surfaces) = get_vec(stage, "surfaces") {
+        for surface in surfaces {
+            if let &mut Value::Object (ref mut surface) = surface {
+                surface.insert(String::from("hazard"), Value::Null);
+            }
+        }
+    }
+    if let &mut Value::Object (ref mut stage) = stage {
+        stage.insert(String::from("hazards"), json!([]));
+        stage.insert(String::from("hazard_rehit_frames"), json!(30));
+    }
+}
+
+/// add path to surfaces
+fn upgrade_stage21(stage: &mut Value) {
+    if let Some (surfaces) = get_vec(stage, "surfaces") {
+        for surface in surfaces {
+            if let &mut Value::Object (ref mut surface) = surface {
+                surface.insert(String::from("path"), Value::Null);
+            }
+        }
+    }
+}
+
+/// add tie_break
+fn upgrade_rules20(rules: &mut Value) {
+    if let &mut Value::Object (ref mut rules) = rules {
+        rules.insert(String::from("tie_break"), json!({ "SuddenDeath": { "damage": 300.0 } }));
+    }
+}
+
+/// add frames to Pause::Hold
+fn upgrade_rules19(rules: &mut Value) {
+    if let &mut Value::Object (ref mut rules) = rules {
+        if let Some (pause) = rules.get_mut("pause") {
+            if pause.as_str() == Some("Hold") {
+                *pause = json!({ "Hold": { "frames": 60 } });
+            }
+        }
+    }
+}
+
+/// add starter_stages, counterpick_stages, counterpick_bans and daves_stupid_rule
+fn upgrade_rules18(rules: &mut Value) {
+    if let &mut Value::Object (ref mut rules) = rules {
+        rules.insert(String::from("starter_stages"), json!([]));
+        rules.insert(String::from("counterpick_stages"), json!([]));
+        rules.insert(String::from("counterpick_bans"), json!(2));
+        rules.insert(String::from("daves_stupid_rule"), Value::Bool(true));
+    }
+}
+
+/// add last_hit_window_frames and self_destruct_penalty
+fn upgrade_rules17(rules: &mut Value) {
+    if let &mut Value::Object (ref mut rules) = rules {
+        rules.insert(String::from("last_hit_window_frames"), json!(600));
+        rules.insert(String::from("self_destruct_penalty"), json!(1));
+    }
+}
+
+/// add stock_steal to Teams::On
+fn upgrade_rules16(rules: &mut Value) {
+    if let &mut Value::Object (ref mut rules) = rules {
+        if let Some (teams) = rules.get_mut("teams") {
+            if let &mut Value::Object (ref mut teams) = teams {
+                if let Some (on) = teams.get_mut("On") {
+                    if let &mut Value::Object (ref mut on) = on {
+                        on.insert(String::from("stock_steal"), Value::Bool(false));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// add team_funnel_angle to Hit
+fn upgrade_fighter16(fighter: &mut Value) {
+    if let Some (actions) = get_vec(fighter, "actions") {
+        for action in actions {
+            if let Some (frames) = get_vec(action, "frames") {
+                for frame in frames {
+                    if let Some (colboxes) = get_vec(frame, "colboxes") {
+                        for colbox in colboxes {
+                            if let &mut Value::Object (ref mut colbox) = colbox {
+                                if let Some (role) = colbox.get_mut("role") {
+                                    if let &mut Value::Object (ref mut role) = role {
+                                        if let Some (hitbox) = role.get_mut("Hit") {
+                                            if let &mut Value::Object (ref mut hitbox) = hitbox {
+                                                hitbox.insert(String::from("team_funnel_angle"), Value::Null);
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// add id, group and priority to Hit
+fn upgrade_fighter15(fighter: &mut Value) {
+    if let Some (actions) = get_vec(fighter, "actions") {
+        for action in actions {
+            if let Some (frames) = get_vec(action, "frames") {
+                for frame in frames {
+                    if let Some (colboxes) = get_vec(frame, "colboxes") {
+                        for (i, colbox) in colboxes.iter_mut().enumerate() {
+                            if let &mut Value::Object (ref mut colbox) = colbox {
+                                if let Some (role) = colbox.get_mut("role") {
+                                    if let &mut Value::Object (ref mut role) = role {
+                                        if let Some (hitbox) = role.get_mut("Hit") {
+                                            if let &mut Value::Object (ref mut hitbox) = hitbox {
+                                                // Previously the first hitbox in the frame won, the colbox index is the closest match to that ordering.
+                                                // All hitboxes shared the one hitlist, so they all go in the same group.
+                                                hitbox.insert(String::from("id"), json!(i));
+                                                hitbox.insert(String::from("group"), json!(0));
+                                                hitbox.insert(String::from("priority"), json!(0));
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// move set_x_vel/set_y_vel to x_vel_modify/y_vel_modify and x_vel_temp/y_vel_temp
 fn upgrade_fighter14(fighter: &mut Value) {
     if let Some (actions) = get_vec(fighter, "actions") {
@@ -6,16 +6,27 @@
 #[macro_use] extern crate serde_json;
 #[macro_use] extern crate treeflection_derive;
 
-pub mod command_line;
+pub mod collision;
 pub mod config;
+pub mod divergence;
 pub mod fighter;
 pub mod files;
 pub mod geometry;
+pub mod graphics;
+pub mod history;
 pub mod input;
 pub mod json_upgrade;
 pub mod logger;
 pub mod network;
 pub mod package;
 pub mod panic_handler;
+pub mod particle;
+pub mod player;
+pub mod results;
 pub mod rules;
+pub mod simulation;
 pub mod stage;
+pub mod stage_analysis;
+pub mod stage_strike;
+pub mod stage_svg;
+pub mod surface_grid;
@@ -5,12 +5,13 @@ use rand;
 use crate::json_upgrade;
 
 use std::net::{TcpListener, UdpSocket, IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::io::Read;
 use std::io::Write;
 use std::str;
 use std::time::{Instant, Duration};
 
-use crate::input::ControllerInput;
+use crate::input::{ControllerInput, PlayerInput};
 
 pub struct NetCommandLine {
     listener: TcpListener
@@ -85,10 +86,139 @@ impl NetCommandLine {
         1 byte - 0xAA
 */
 
-pub struct Netplay {
+/// Buffers the inputs of every peer, predicting the inputs that have not arrived yet.
+/// Kept separate from Netplay so the prediction and rollback logic does not need a socket.
+pub struct NetplayInputs {
     // structure: peers Vec<frames Vec<controllers Vec<ControllerInput>>>
-    // frame 0 has index 2
-    pub confirmed_inputs:  Vec<Vec<Vec<ControllerInput>>>,
+    // the inputs of frame f are at index f - 1
+    confirmed:      Vec<Vec<Vec<ControllerInput>>>,
+    local:          Vec<Vec<ControllerInput>>, // inputs sent to peers, structured the same as a single peer of confirmed
+    pending:        Vec<(usize, InputConfirm)>, // received inputs that cannot be confirmed until the inputs of earlier frames arrive, with the index of the peer
+    input_delay:    usize, // frames between reading a local input and using it
+    max_rollback:   usize, // the furthest the local machine can predict ahead of the confirmed inputs before waiting for peers
+    rollback_frame: Option<usize>, // the earliest frame that was simulated with a mispredicted input, set by the last call to confirm
+}
+
+impl NetplayInputs {
+    pub fn new(input_delay: usize, max_rollback: usize) -> NetplayInputs {
+        NetplayInputs {
+            confirmed:      vec!(),
+            local:          vec!(),
+            pending:        vec!(),
+            input_delay,
+            max_rollback:   max_rollback.max(1),
+            rollback_frame: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.confirmed.clear();
+        self.local.clear();
+        self.pending.clear();
+        self.rollback_frame = None;
+    }
+
+    pub fn add_peer(&mut self) {
+        self.confirmed.push(vec!());
+    }
+
+    /// Stores the inputs a peer used on the frame, they are confirmed by the next call to confirm
+    pub fn receive(&mut self, peer: usize, frame: usize, inputs: Vec<ControllerInput>) {
+        self.pending.push((peer, InputConfirm { frame, inputs }));
+    }
+
+    /// Confirms the received inputs that follow on from the confirmed inputs of their peer.
+    /// state_frame is the last frame simulated, when an input for a simulated frame differs from the prediction a rollback to that frame is needed.
+    pub fn confirm(&mut self, state_frame: usize) {
+        self.rollback_frame = None;
+        for peer in 0..self.confirmed.len() {
+            // every frame without a confirmed input was simulated with the last confirmed input
+            let predicted = self.confirmed[peer].last().cloned();
+            loop {
+                let next_frame = self.confirmed[peer].len() + 1;
+                match self.pending.iter().position(|(x, msg)| *x == peer && msg.frame == next_frame) {
+                    Some(i) => {
+                        let (_, msg) = self.pending.remove(i);
+                        if msg.frame <= state_frame && predicted.as_ref() != Some(&msg.inputs) {
+                            self.rollback_frame = Some(self.rollback_frame.map_or(msg.frame, |x| x.min(msg.frame)));
+                        }
+                        self.confirmed[peer].push(msg.inputs);
+                    }
+                    None => break
+                }
+            }
+        }
+
+        // inputs are resent, so drop any that were already confirmed
+        let confirmed = &self.confirmed;
+        self.pending.retain(|(peer, msg)| confirmed.get(*peer).map_or(false, |x| msg.frame > x.len()));
+    }
+
+    /// Returns true if simulating the frame after state_frame would predict more than max_rollback frames past the confirmed inputs
+    pub fn stall(&self, state_frame: usize) -> bool {
+        let input_frames = self.confirmed.iter().map(|x| x.len()).min().unwrap_or(0);
+        state_frame + 1 > input_frames + self.max_rollback
+    }
+
+    /// Returns the number of frames that need to be stepped/restepped including state_frame
+    pub fn frames_to_step(&self, state_frame: usize) -> usize {
+        match self.rollback_frame {
+            Some(rollback_frame) => state_frame + 1 - rollback_frame,
+            None                 => 1
+        }
+    }
+
+    /// Returns the last frame where the inputs of every peer have been confirmed, state_frame if there are no peers
+    pub fn confirmed_frame(&self, state_frame: usize) -> usize {
+        self.confirmed.iter().map(|x| x.len()).min().unwrap_or(state_frame)
+    }
+
+    /// Stores the local inputs read on state_frame, which are used input_delay frames later.
+    /// The frames before the first delayed input have no input, so they are filled with unplugged controllers.
+    /// Returns the frames that were added, which need to be sent to the peers.
+    pub fn add_local(&mut self, state_frame: usize, inputs: Vec<ControllerInput>) -> RangeInclusive<usize> {
+        let first_frame = self.local.len() + 1;
+        while self.local.len() + 1 < state_frame + self.input_delay {
+            self.local.push(vec!(ControllerInput::empty(); inputs.len()));
+        }
+        self.local.push(inputs);
+        first_frame..=self.local.len()
+    }
+
+    /// Returns the local inputs used on the frame
+    pub fn local(&self, frame: usize) -> Option<&Vec<ControllerInput>> {
+        self.local.get(frame.wrapping_sub(1))
+    }
+
+    /// Returns the inputs of every controller on the specified frame, ordered by peer index with the local machine at local_index.
+    /// Inputs that have not been received yet are predicted.
+    pub fn player_inputs(&self, local_index: usize, frame: usize) -> Vec<PlayerInput> {
+        let mut result_inputs: Vec<PlayerInput> = vec!();
+
+        let mut peer_offset = 0;
+        for i in 0..self.confirmed.len() + 1 {
+            let history = if i == local_index {
+                peer_offset = 1;
+                &self.local
+            } else {
+                &self.confirmed[i - peer_offset]
+            };
+
+            let num_controllers = history.last().map_or(0, |x| x.len());
+            for controller_i in 0..num_controllers {
+                result_inputs.push(PlayerInput::from_history(history, controller_i, frame as i64));
+            }
+        }
+
+        result_inputs
+    }
+}
+
+/// Remote inputs that have not arrived yet are predicted by repeating the last input received from that peer.
+/// When a received input differs from the prediction, frames_to_step includes every frame since the misprediction so the caller can roll back and resimulate.
+pub struct Netplay {
+    inputs:                NetplayInputs,
+    skip_frame:            bool,
     match_making_response: Option<MatchMakingResponse>,
     peers:                 Vec<SocketAddr>,
     seed:                  u64,
@@ -101,18 +231,18 @@ pub struct Netplay {
     ping_msgs:             Vec<u8>,
     start_request_msgs:    Vec<usize>,
     start_confirm_msgs:    Vec<usize>,
-    running_msgs:          Vec<InputConfirm>,
 }
 
 impl Netplay {
-    pub fn new() -> Netplay {
+    pub fn new(input_delay: usize, max_rollback: usize) -> Netplay {
         let socket = UdpSocket::bind("0.0.0.0:8413").unwrap();
         socket.set_nonblocking(true).unwrap();
         Netplay {
             state:                 NetplayState::Offline,
             state_frame:           0,
             last_received_frame:   0,
-            confirmed_inputs:      vec!(),
+            inputs:                NetplayInputs::new(input_delay, max_rollback),
+            skip_frame:            false,
             match_making_response: None,
             peers:                 vec!(),
             seed:                  0,
@@ -121,17 +251,12 @@ impl Netplay {
             ping_msgs:             vec!(),
             start_request_msgs:    vec!(),
             start_confirm_msgs:    vec!(),
-            running_msgs:          vec!(),
             socket,
         }
     }
 
     /// Call this once every frame
     pub fn step(&mut self) {
-        if !self.skip_frame() {
-            self.state_frame += 1;
-        }
-
         // receive messages
         loop {
             let mut buf = [0; 1024];
@@ -160,9 +285,9 @@ impl Netplay {
                         }
                     }
                     0x04 => {
-                        if self.peers.contains(&addr) {
-                            if let Ok(data) = bincode::deserialize(&buf[1..]) {
-                                self.running_msgs.push(data);
+                        if let Some(peer) = self.peers.iter().position(|x| *x == addr) {
+                            if let Ok(data) = bincode::deserialize::<InputConfirm>(&buf[1..]) {
+                                self.inputs.receive(peer, data.frame, data.inputs);
                             }
                         }
                     }
@@ -200,7 +325,7 @@ impl Netplay {
                     for peer in response.addresses.iter() {
                         if !self.peers.contains(peer) {
                             self.peers.push(peer.clone());
-                            self.confirmed_inputs.push(vec!());
+                            self.inputs.add_peer();
                         }
                     }
                 }
@@ -284,29 +409,18 @@ impl Netplay {
                 }
             }
             NetplayState::Running => {
-                let peer = 0; // TODO: handle multiple peers
-                let mut found_msg = true;
-                let mut to_delete = vec!();
-                while found_msg {
-                    found_msg = false;
-                    for (i, msg) in self.running_msgs.iter().enumerate() {
-                        let inputs_len = self.confirmed_inputs[peer].len();
-                        // msg.frame starts at 1 because its taken from the peers state_frame which is incremented before any logic is run
-                        if msg.frame == inputs_len + 1 {
-                            self.confirmed_inputs[peer].push(msg.inputs.clone());
-                            found_msg = true;
-                            to_delete.push(i)
-                        }
-                    }
-
-                    to_delete.reverse();
-                    for i in to_delete.iter() {
-                        self.running_msgs.remove(*i);
-                    }
-                    to_delete.clear();
-                }
+                self.inputs.confirm(self.state_frame);
             }
         }
+
+        self.skip_frame = match &self.state {
+            &NetplayState::Running => self.inputs.stall(self.state_frame),
+            _ => false
+        };
+        if !self.skip_frame {
+            self.state_frame += 1;
+        }
+
         debug!("state: {}", self.state.to_string());
         debug!("number_of_peers: {}", self.number_of_peers());
         debug!("local_index: {}",  self.local_index());
@@ -332,16 +446,20 @@ impl Netplay {
         self.peers.len() + 1
     }
 
-    // TODO: Optimize by only starting from a frame where the inputs differ
     /// Returns the number of frames that need to be stepped/restepped including the current frame
+    /// Frames are only restepped when a received input differs from the input predicted for it.
     pub fn frames_to_step(&self) -> usize {
-        let input_frames = self.confirmed_inputs.iter().map(|x| x.len()).min().unwrap_or(1);
         match &self.state {
-            &NetplayState::Running => self.state_frame.saturating_sub(input_frames).max(1),
+            &NetplayState::Running => self.inputs.frames_to_step(self.state_frame),
             _ => 1
         }
     }
 
+    /// Returns the last frame where the inputs of every peer have been received, later frames use predicted inputs
+    pub fn confirmed_frame(&self) -> usize {
+        self.inputs.confirmed_frame(self.state_frame)
+    }
+
     pub fn frame(&self) -> usize {
         match &self.state {
             &NetplayState::Running => self.state_frame,
@@ -351,12 +469,15 @@ impl Netplay {
 
     // TODO: take ping into account
     /// Returns true if the local machine should do nothing for a frame so that peers can catch up.
+    /// This only happens once the local machine has predicted max_rollback frames past the last confirmed input.
     pub fn skip_frame(&self) -> bool {
-        let input_frames = self.confirmed_inputs.iter().map(|x| x.len()).min().unwrap_or(1);
-        match &self.state {
-            &NetplayState::Running => self.state_frame > input_frames + 1,
-            _ => false
-        }
+        self.skip_frame
+    }
+
+    /// Returns the inputs of every controller on the specified frame, ordered by peer index.
+    /// Inputs that have not been received yet are predicted.
+    pub fn player_inputs(&self, frame: usize) -> Vec<PlayerInput> {
+        self.inputs.player_inputs(self.local_index(), frame)
     }
 
     /// Return the seed used for this netplay session
@@ -383,14 +504,14 @@ impl Netplay {
     }
 
     fn clear(&mut self) {
-        self.confirmed_inputs.clear();
+        self.inputs.clear();
+        self.skip_frame = false;
         self.index = 0;
         self.init_msgs.clear();
         self.last_received_frame = 0;
         self.match_making_response = None;
         self.peers.clear();
         self.ping_msgs.clear();
-        self.running_msgs.clear();
         self.seed = 0;
         self.start_confirm_msgs.clear();
         self.start_request_msgs.clear();
@@ -400,7 +521,7 @@ impl Netplay {
     pub fn direct_connect(&mut self, address: IpAddr, hash: String) {
         self.clear();
         self.peers.push(SocketAddr::new(address, 8413));
-        self.confirmed_inputs.push(vec!());
+        self.inputs.add_peer();
         self.set_state(NetplayState::InitConnection (InitConnection {
             random:        rand::thread_rng().gen::<u64>(),
             build_version: json_upgrade::build_version(),
@@ -452,15 +573,18 @@ impl Netplay {
         }
     }
 
+    /// The inputs are used input_delay frames after the current frame.
     pub fn send_controller_inputs(&mut self, inputs: Vec<ControllerInput>) {
         if let &NetplayState::Running = &self.state {
-            let input_confirm = InputConfirm {
-                frame: self.state_frame,
-                inputs
-            };
-            let mut data = bincode::serialize(&input_confirm).unwrap();
-            data.insert(0, 0x04);
-            self.broadcast(&data, "controller input");
+            for frame in self.inputs.add_local(self.state_frame, inputs) {
+                let input_confirm = InputConfirm {
+                    frame,
+                    inputs: self.inputs.local(frame).unwrap().clone(),
+                };
+                let mut data = bincode::serialize(&input_confirm).unwrap();
+                data.insert(0, 0x04);
+                self.broadcast(&data, "controller input");
+            }
         }
         // TODO: Store InputConfirm so we can resend it later (maybe repeat it every step() for n steps, no idea how to best handle this sort of thing)
     }
@@ -17,6 +17,7 @@ use crate::json_upgrade::engine_version;
 use crate::json_upgrade;
 use crate::rules::Rules;
 use crate::stage::Stage;
+use crate::stage_svg;
 
 fn get_packages_path() -> PathBuf {
     let mut path = files::get_path();
@@ -65,6 +66,21 @@ pub fn print_list() {
     }
 }
 
+/// Prints a legality report for every stage in the package.
+/// The legal tag of each stage is only stored when save is set, as saving rewrites every file of the package.
+pub fn print_stage_analysis(name: &str, save: bool) {
+    if let Some(mut package) = Package::open(name) {
+        for key in package.stages.keys() {
+            println!("{}\n{}\n", key, package.stages[key.as_ref()].analyze());
+        }
+        if save {
+            println!("{}", package.save());
+        }
+    } else {
+        println!("Could not load package: {}", name);
+    }
+}
+
 pub fn get_package_metas() -> Vec<(String, PackageMeta)> {
     let mut result: Vec<(String, PackageMeta)> = vec!();
 
@@ -305,6 +321,33 @@ impl Package {
         String::from("Save completed successfully.")
     }
 
+    /// Imports the SVG file as the stage with the passed key, replacing any existing stage with that key
+    pub fn import_stage_svg(&mut self, path: &str, key: &str) -> String {
+        let stage = match files::load_file(PathBuf::from(path)).and_then(|svg| stage_svg::import(&svg)) {
+            Ok(stage) => stage,
+            Err(err)  => return format!("Import FAILED! {}", err)
+        };
+
+        if self.stages.contains_key(key) {
+            self.stages[key] = stage;
+        } else {
+            self.stages.push(key.to_string(), stage);
+        }
+        format!("Imported {} as stage {}", path, key)
+    }
+
+    /// Exports the stage with the passed key to an SVG file
+    pub fn export_stage_svg(&self, key: &str, path: &str) -> String {
+        if !self.stages.contains_key(key) {
+            return format!("Export FAILED! Package has no stage {}", key);
+        }
+
+        match fs::write(path, stage_svg::export(&self.stages[key])) {
+            Ok(_)    => format!("Exported stage {} to {}", key, path),
+            Err(err) => format!("Export FAILED! Failed to write {} because: {}", path, err)
+        }
+    }
+
     /// Clears the current package data, then loads the package from disk
     /// The upgraded json is loaded into this package
     /// the user can then save the package to make the upgrade permanent
@@ -386,7 +429,8 @@ impl Package {
             if let Some(file_path) = stage_paths.remove(file_name) {
                 let mut json = files::load_json(file_path)?;
                 json_upgrade::upgrade_to_latest_stage(&mut json, file_name);
-                let stage = serde_json::from_value(json).map_err(|x| format!("{:?}", x))?;
+                let mut stage: Stage = serde_json::from_value(json).map_err(|x| format!("{:?}", x))?;
+                stage.rebuild_surface_grid();
                 self.stages.push(file_name.clone(), stage);
             }
         }
@@ -395,7 +439,8 @@ impl Package {
         for (file_name, file_path) in stage_paths {
             let mut json = files::load_json(file_path)?;
             json_upgrade::upgrade_to_latest_stage(&mut json, &file_name);
-            let stage = serde_json::from_value(json).map_err(|x| format!("{:?}", x))?;
+            let mut stage: Stage = serde_json::from_value(json).map_err(|x| format!("{:?}", x))?;
+            stage.rebuild_surface_grid();
             self.stages.push(file_name.clone(), stage);
         }
 
@@ -789,6 +834,8 @@ Commands:
 *   save    - save changes to disc
 *   reload  - reload from disc, all changes are lost
 *   publish - export the package to a zip file in the PF_Sandbox/publish directory
+*   import_stage_svg $path $key - import the SVG file as a stage
+*   export_stage_svg $key $path - export the stage to an SVG file
 
 Accessors:
 *   .fighters - KeyedContextVec
@@ -796,11 +843,25 @@ Accessors:
 *   .meta     - PackageMeta
 *   .rules    - Rules"#)
             }
-            NodeToken::Custom (action, _) => {
+            NodeToken::Custom (action, args) => {
                 match action.as_ref() {
                     "save" => {
                         self.save()
                     }
+                    "import_stage_svg" => {
+                        if args.len() == 2 {
+                            self.import_stage_svg(&args[0], &args[1])
+                        } else {
+                            String::from("import_stage_svg needs a path and a stage key")
+                        }
+                    }
+                    "export_stage_svg" => {
+                        if args.len() == 2 {
+                            self.export_stage_svg(&args[0], &args[1])
+                        } else {
+                            String::from("export_stage_svg needs a stage key and a path")
+                        }
+                    }
                     "publish" => {
                         self.publish()
                     }
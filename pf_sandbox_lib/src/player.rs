@@ -1,22 +1,20 @@
 use crate::collision::CollisionResult;
+use crate::fighter::*;
+use crate::geometry::Rect;
+use crate::geometry;
 use crate::graphics;
+use crate::input::{PlayerInput};
+use crate::package::Package;
 use crate::particle::{Particle, ParticleType};
 use crate::results::{RawPlayerResult, DeathRecord};
-
-use pf_sandbox_lib::fighter::*;
-use pf_sandbox_lib::geometry::Rect;
-use pf_sandbox_lib::geometry;
-use pf_sandbox_lib::input::{PlayerInput};
-use pf_sandbox_lib::package::Package;
-use pf_sandbox_lib::rules::Goal;
-use pf_sandbox_lib::stage::{Stage, Surface};
+use crate::rules::{Goal, Rules};
+use crate::stage::{Stage, Surface};
 
 use treeflection::{Node, NodeRunner, NodeToken, KeyedContextVec};
 use rand::Rng;
+use serde::ser::{Serialize, Serializer, SerializeTuple};
 use rand_chacha::ChaChaRng;
 use num_traits::{FromPrimitive, ToPrimitive};
-use winit::event::VirtualKeyCode;
-use winit_input_helper::WinitInputHelper;
 
 use std::f32;
 use std::f32::consts::PI;
@@ -110,6 +108,35 @@ impl Hitlag {
     }
 }
 
+/// A player that has already been hit by a hitbox group during the current attack
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Node)]
+pub struct HitlistEntry {
+    pub group:    u64,
+    pub player_i: usize,
+}
+
+/// Per-player adjustments used to give weaker players an advantage
+#[derive(Clone, Debug, Serialize, Deserialize, Node)]
+pub struct Handicap {
+    pub starting_damage:       f32, // damage the player has at the start of the game and after each respawn
+    pub damage_taken_ratio:    f32,
+    pub damage_dealt_ratio:    f32,
+    pub knockback_taken_ratio: f32,
+    pub extra_stocks:          u64, // added to the stock count of the rules, ignored when there is no stock count
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Handicap {
+            starting_damage:       0.0,
+            damage_taken_ratio:    1.0,
+            damage_dealt_ratio:    1.0,
+            knockback_taken_ratio: 1.0,
+            extra_stocks:          0,
+        }
+    }
+}
+
 pub struct StepContext<'a> {
     pub input:    &'a PlayerInput,
     pub players:  &'a [Player],
@@ -117,6 +144,7 @@ pub struct StepContext<'a> {
     pub fighter:  &'a Fighter,
     pub stage:    &'a Stage,
     pub surfaces: &'a [Surface],
+    pub rules:    &'a Rules,
     pub rng:      &'a mut ChaChaRng,
 }
 
@@ -124,6 +152,7 @@ pub struct StepContext<'a> {
 pub struct Player {
     pub fighter:            String,
     pub team:               usize,
+    pub handicap:           Handicap,
     pub action:             u64, // always change through next_action
     pub set_action_called:  bool,
     pub new_action:         bool,
@@ -136,6 +165,8 @@ pub struct Player {
 
     pub stocks:             Option<u64>,
     pub damage:             f32,
+    pub hp:                 Option<f32>, // Only used by Goal::Stamina, damage stays at 0 so knockback scaling is fixed
+    pub stamina_ko_timer:   Option<u64>, // frames until a player with no hp remaining dies
     pub location:           Location,
     pub x_vel:              f32,
     pub y_vel:              f32,
@@ -160,10 +191,15 @@ pub struct Player {
     pub lcancel_timer:      u64,
     pub land_frame_skip:    u8,
     pub ecb:                ECB,
-    pub hitlist:            Vec<usize>,
+    pub hitlist:            Vec<HitlistEntry>,
     pub hitlag:             Hitlag,
     pub hitstun:            f32,
     pub hit_by:             Option<usize>,
+    pub hit_by_timer:       u64, // frames since hit_by was last set
+    pub hit_by_stage:       bool, // the last hit was from a stage hazard, a death is credited to the stage instead of hit_by
+    pub hazard_timer:       u64, // frames until stage hazards can hit again
+    pub wall_jump_timer:    u64, // frames remaining in which the player can wall jump off the last wall touched
+    pub wall_right:         bool, // the last wall touched is to the right of the player
     pub particles:          Vec<Particle>,
     pub aerial_dodge_frame: Option<u64>,
     pub result:             RawPlayerResult,
@@ -176,8 +212,83 @@ pub struct Player {
     pub c_stick:           Option<(f32, f32)>,
 }
 
+/// Serializes every field of the player except particles, which are visual only and are not resimulated by netplay rollback.
+/// Used by Simulation::checksum to hash the player without cloning it.
+pub(crate) struct PlayerChecksum<'a> (pub &'a Player);
+
+impl<'a> Serialize for PlayerChecksum<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // destructured without .. so that adding a field to Player fails to compile until it is added here
+        let Player {
+            fighter, team, handicap, action, set_action_called, new_action, frame, frame_norestart, stocks,
+            damage, hp, stamina_ko_timer, location, x_vel, y_vel, kb_x_vel, kb_y_vel, kb_x_dec, kb_y_dec,
+            face_right, frames_since_ledge, ledge_idle_timer, fastfalled, air_jumps_left, jumpsquat_button,
+            shield_hp, shield_analog, shield_offset_x, shield_offset_y, stun_timer, shield_stun_timer,
+            parry_timer, tech_timer, lcancel_timer, land_frame_skip, ecb, hitlist, hitlag, hitstun, hit_by,
+            hit_by_timer, hit_by_stage, hazard_timer, wall_jump_timer, wall_right, particles: _,
+            aerial_dodge_frame, result, frames_since_hit, hit_angle_pre_di, hit_angle_post_di, stick,
+            c_stick,
+        } = self.0;
+
+        let mut tuple = serializer.serialize_tuple(52)?;
+        tuple.serialize_element(fighter)?;
+        tuple.serialize_element(team)?;
+        tuple.serialize_element(handicap)?;
+        tuple.serialize_element(action)?;
+        tuple.serialize_element(set_action_called)?;
+        tuple.serialize_element(new_action)?;
+        tuple.serialize_element(frame)?;
+        tuple.serialize_element(frame_norestart)?;
+        tuple.serialize_element(stocks)?;
+        tuple.serialize_element(damage)?;
+        tuple.serialize_element(hp)?;
+        tuple.serialize_element(stamina_ko_timer)?;
+        tuple.serialize_element(location)?;
+        tuple.serialize_element(x_vel)?;
+        tuple.serialize_element(y_vel)?;
+        tuple.serialize_element(kb_x_vel)?;
+        tuple.serialize_element(kb_y_vel)?;
+        tuple.serialize_element(kb_x_dec)?;
+        tuple.serialize_element(kb_y_dec)?;
+        tuple.serialize_element(face_right)?;
+        tuple.serialize_element(frames_since_ledge)?;
+        tuple.serialize_element(ledge_idle_timer)?;
+        tuple.serialize_element(fastfalled)?;
+        tuple.serialize_element(air_jumps_left)?;
+        tuple.serialize_element(jumpsquat_button)?;
+        tuple.serialize_element(shield_hp)?;
+        tuple.serialize_element(shield_analog)?;
+        tuple.serialize_element(shield_offset_x)?;
+        tuple.serialize_element(shield_offset_y)?;
+        tuple.serialize_element(stun_timer)?;
+        tuple.serialize_element(shield_stun_timer)?;
+        tuple.serialize_element(parry_timer)?;
+        tuple.serialize_element(tech_timer)?;
+        tuple.serialize_element(lcancel_timer)?;
+        tuple.serialize_element(land_frame_skip)?;
+        tuple.serialize_element(ecb)?;
+        tuple.serialize_element(hitlist)?;
+        tuple.serialize_element(hitlag)?;
+        tuple.serialize_element(hitstun)?;
+        tuple.serialize_element(hit_by)?;
+        tuple.serialize_element(hit_by_timer)?;
+        tuple.serialize_element(hit_by_stage)?;
+        tuple.serialize_element(hazard_timer)?;
+        tuple.serialize_element(wall_jump_timer)?;
+        tuple.serialize_element(wall_right)?;
+        tuple.serialize_element(aerial_dodge_frame)?;
+        tuple.serialize_element(result)?;
+        tuple.serialize_element(frames_since_hit)?;
+        tuple.serialize_element(hit_angle_pre_di)?;
+        tuple.serialize_element(hit_angle_post_di)?;
+        tuple.serialize_element(stick)?;
+        tuple.serialize_element(c_stick)?;
+        tuple.end()
+    }
+}
+
 impl Player {
-    pub fn new(fighter: String, team: usize, player_i: usize, stage: &Stage, package: &Package) -> Player {
+    pub fn new(fighter: String, team: usize, handicap: Handicap, player_i: usize, stage: &Stage, package: &Package) -> Player {
         // get the spawn point
         let spawn = if stage.spawn_points.len() == 0 {
             None
@@ -225,8 +336,10 @@ impl Player {
             new_action:         false,
             frame:              0,
             frame_norestart:    0,
-            stocks:             package.rules.stock_count,
-            damage:             0.0,
+            stocks:             package.rules.stock_count.map(|x| x + handicap.extra_stocks),
+            damage:             handicap.starting_damage,
+            hp:                 Player::max_hp(&package.rules.goal),
+            stamina_ko_timer:   None,
             x_vel:              0.0,
             y_vel:              0.0,
             kb_x_vel:           0.0,
@@ -254,10 +367,16 @@ impl Player {
             hitlag:             Hitlag::None,
             hitstun:            0.0,
             hit_by:             None,
+            hit_by_timer:       0,
+            hit_by_stage:       false,
+            hazard_timer:       0,
+            wall_jump_timer:    0,
+            wall_right:         false,
             particles:          vec!(),
             aerial_dodge_frame: None,
             result:             RawPlayerResult::default(),
             team,
+            handicap,
             fighter,
             location,
 
@@ -379,6 +498,11 @@ impl Player {
         }
     }
 
+    /// Returns true if the hitbox group has already hit the player during the current attack
+    pub fn in_hitlist(&self, group: u64, player_i: usize) -> bool {
+        self.hitlist.iter().any(|x| x.group == group && x.player_i == player_i)
+    }
+
     pub fn set_airbourne(&mut self, context: &StepContext) {
         let (x, y) = self.bps_xy(context);
         self.fastfalled = false;
@@ -455,85 +579,19 @@ impl Player {
             match col_result {
                 &CollisionResult::HitAtk { player_def_i, ref hitbox, ref point } => {
                     self.hit_particles(point.clone(), hitbox);
-                    self.hitlist.push(player_def_i);
+                    self.hitlist.push(HitlistEntry { group: hitbox.group, player_i: player_def_i });
                     self.hitlag = Hitlag::Some ((hitbox.damage / 3.0 + 3.0) as u64);
                 }
                 &CollisionResult::HitDef { ref hitbox, ref hurtbox, player_atk_i } => {
-                    let player_atk = &context.players[player_atk_i];
-
-                    let damage_done = hitbox.damage * hurtbox.damage_mult; // TODO: apply staling
-                    self.damage += damage_done;
-
-                    let damage_launch = 0.05 * (hitbox.damage * (damage_done + self.damage.floor())) + (damage_done + self.damage) * 0.1;
-                    let weight = 2.0 - (2.0 * context.fighter.weight) / (1.0 + context.fighter.weight);
-                    let kbg = hitbox.kbg + hurtbox.kbg_add;
-                    let bkb = hitbox.bkb + hurtbox.bkb_add;
-
-                    let mut kb_vel = (bkb + kbg * (damage_launch * weight * 1.4 + 18.0)).min(2500.0);
-
-                    if let Some(action) = Action::from_u64(self.action) {
-                        match action {
-                            Action::Crouch => {
-                                kb_vel *= 0.67;
-                            }
-                            _ => { }
-                        }
-                    }
-
-                    if !self.is_grabbed() || kb_vel > 50.0 {
-                        self.hitstun = match hitbox.hitstun {
-                            HitStun::FramesTimesKnockback (frames) => { frames * kb_vel }
-                            HitStun::Frames               (frames) => { frames as f32 }
-                        };
-
-                        self.set_airbourne(context);
-
-                        if kb_vel > 80.0 {
-                            self.set_action(context, Action::DamageFly);
-                        }
-                        else {
-                            self.set_action(context, Action::Damage);
-                        }
-                    }
-
-                    // handle sakurai angle
-                    let angle_deg = if hitbox.angle == 361.0 {
-                        if kb_vel < 32.1 {
-                            0.0
-                        }
-                        else {
-                            44.0
-                        }
-                    } else if hitbox.angle == 180.0 - 361.0 {
-                        if kb_vel < 32.1 {
-                            180.0
-                        }
-                        else {
-                            180.0 - 44.0
-                        }
-                    } else {
-                        hitbox.angle
-                    };
-
-                    // convert from degrees to radians
-                    let angle_rad = angle_deg.to_radians() + if angle_deg < 0.0 { PI * 2.0 } else { 0.0 };
-
-                    // handle reverse hits
-                    let behind_player_atk = self.bps_xy(context).0 < player_atk.bps_xy(context).0 && player_atk.face_right ||
-                                            self.bps_xy(context).0 > player_atk.bps_xy(context).0 && !player_atk.face_right;
-                    let angle = if hitbox.enable_reverse_hit && behind_player_atk { PI - angle_rad } else { angle_rad };
-
-                    // debug data
-                    self.hit_angle_pre_di = Some(angle);
-                    self.hit_angle_post_di = None;
-                    self.frames_since_hit = 0;
-
-                    self.hitlag = Hitlag::Launch { counter: (hitbox.damage / 3.0 + 3.0) as u64, kb_vel, angle, wobble_x: 0.0 };
-                    self.hit_by = Some(player_atk_i);
-                    self.face_right = self.bps_xy(context).0 < player_atk.bps_xy(context).0;
+                    let source_x = context.players[player_atk_i].bps_xy(context).0;
+                    self.take_hit(context, hitbox, hurtbox, Some(player_atk_i), source_x);
+                }
+                &CollisionResult::HitStage { ref hitbox, ref hurtbox, point } => {
+                    self.take_hit(context, hitbox, hurtbox, None, point.0);
+                    self.hazard_timer = context.stage.hazard_rehit_frames;
                 }
                 &CollisionResult::HitShieldAtk { ref hitbox, ref power_shield, player_def_i} => {
-                    self.hitlist.push(player_def_i);
+                    self.hitlist.push(HitlistEntry { group: hitbox.group, player_i: player_def_i });
                     if let &Some(ref power_shield) = power_shield {
                         if let (Some(Action::PowerShield), &Some(ref stun)) = (Action::from_u64(self.action), &power_shield.enemy_stun) {
                             if stun.window > context.players[player_def_i].frame as u64 {
@@ -578,6 +636,133 @@ impl Player {
         }
     }
 
+    /// Applies damage and knockback from a hitbox.
+    /// player_atk_i is None when the hitbox belongs to the stage, source_x is the x position the hit came from.
+    fn take_hit(&mut self, context: &mut StepContext, hitbox: &HitBox, hurtbox: &HurtBox, player_atk_i: Option<usize>, source_x: f32) {
+        let damage_dealt_ratio = player_atk_i.map(|i| context.players[i].handicap.damage_dealt_ratio).unwrap_or(1.0);
+        let handicap_mult = damage_dealt_ratio * self.handicap.damage_taken_ratio;
+        let damage_done = hitbox.damage * hurtbox.damage_mult * handicap_mult; // TODO: apply staling
+        if let Some(ref mut hp) = self.hp {
+            *hp = (*hp - damage_done).max(0.0);
+        } else {
+            self.damage += damage_done;
+        }
+
+        let damage_launch = 0.05 * (hitbox.damage * (damage_done + self.damage.floor())) + (damage_done + self.damage) * 0.1;
+        let weight = 2.0 - (2.0 * context.fighter.weight) / (1.0 + context.fighter.weight);
+        let kbg = hitbox.kbg + hurtbox.kbg_add;
+        let bkb = hitbox.bkb + hurtbox.bkb_add;
+
+        let mut kb_vel = ((bkb + kbg * (damage_launch * weight * 1.4 + 18.0)) * self.handicap.knockback_taken_ratio).min(2500.0);
+
+        if let Some(action) = Action::from_u64(self.action) {
+            match action {
+                Action::Crouch => {
+                    kb_vel *= 0.67;
+                }
+                _ => { }
+            }
+        }
+
+        let stamina_ko = self.hp == Some(0.0);
+        if !self.is_grabbed() || kb_vel > 50.0 || stamina_ko {
+            self.hitstun = match hitbox.hitstun {
+                HitStun::FramesTimesKnockback (frames) => { frames * kb_vel }
+                HitStun::Frames               (frames) => { frames as f32 }
+            };
+
+            self.set_airbourne(context);
+
+            if kb_vel > 80.0 || stamina_ko {
+                self.set_action(context, Action::DamageFly);
+            }
+            else {
+                self.set_action(context, Action::Damage);
+            }
+        }
+
+        // handle sakurai angle
+        let angle_deg = if hitbox.angle == 361.0 {
+            if kb_vel < 32.1 {
+                0.0
+            }
+            else {
+                44.0
+            }
+        } else if hitbox.angle == 180.0 - 361.0 {
+            if kb_vel < 32.1 {
+                180.0
+            }
+            else {
+                180.0 - 44.0
+            }
+        } else {
+            hitbox.angle
+        };
+
+        // convert from degrees to radians
+        let angle_rad = angle_deg.to_radians() + if angle_deg < 0.0 { PI * 2.0 } else { 0.0 };
+
+        // handle reverse hits, stage hazards have no facing direction so they always launch away from themselves
+        let x = self.bps_xy(context).0;
+        let angle = if let Some(player_atk_i) = player_atk_i {
+            let face_right = context.players[player_atk_i].face_right;
+            let behind_player_atk = x < source_x && face_right || x > source_x && !face_right;
+            let angle = if hitbox.enable_reverse_hit && behind_player_atk { PI - angle_rad } else { angle_rad };
+            self.team_funnel(context, hitbox, player_atk_i, angle)
+        } else if x < source_x {
+            PI - angle_rad
+        } else {
+            angle_rad
+        };
+
+        // debug data
+        self.hit_angle_pre_di = Some(angle);
+        self.hit_angle_post_di = None;
+        self.frames_since_hit = 0;
+
+        self.hitlag = Hitlag::Launch { counter: (hitbox.damage / 3.0 + 3.0) as u64, kb_vel, angle, wobble_x: 0.0 };
+        self.hit_by = player_atk_i;
+        self.hit_by_stage = player_atk_i.is_none();
+        self.hit_by_timer = 0;
+        self.face_right = x < source_x;
+
+        if stamina_ko {
+            self.stamina_ko_timer = Some(40);
+            self.stamina_ko_particles(context);
+        }
+    }
+
+    /// Rotates the launch angle by up to the hitboxes team_funnel_angle towards the nearest teammate of the attacker
+    fn team_funnel(&self, context: &StepContext, hitbox: &HitBox, player_atk_i: usize, angle: f32) -> f32 {
+        let player_atk = &context.players[player_atk_i];
+        if let (true, Some(funnel_angle)) = (context.rules.teams_enabled(), hitbox.team_funnel_angle) {
+            if self.team == player_atk.team {
+                return angle;
+            }
+
+            let (x, y) = self.bps_xy(context);
+            let mut nearest: Option<(f32, f32)> = None;
+            for (player_i, player) in context.players.iter().enumerate() {
+                if player_i != player_atk_i && player.team == player_atk.team && player.action != Action::Eliminated.to_u64().unwrap() {
+                    let (teammate_x, teammate_y) = player.bps_xy(context);
+                    let distance = ((teammate_x - x).powi(2) + (teammate_y - y).powi(2)).sqrt();
+                    let teammate_angle = (teammate_y - y).atan2(teammate_x - x);
+                    if nearest.map(|(nearest_distance, _)| distance < nearest_distance).unwrap_or(true) {
+                        nearest = Some((distance, teammate_angle));
+                    }
+                }
+            }
+
+            if let Some((_, teammate_angle)) = nearest {
+                let max_rad = funnel_angle.abs().to_radians();
+                let diff = (teammate_angle - angle + PI).rem_euclid(PI * 2.0) - PI;
+                return angle + diff.max(-max_rad).min(max_rad);
+            }
+        }
+        angle
+    }
+
     /*
      *  Begin action section
      */
@@ -596,6 +781,9 @@ impl Player {
         }
         // The code from this point onwards can assume we are on a valid action and frame
 
+        self.hit_by_timer += 1;
+        self.hazard_timer = self.hazard_timer.saturating_sub(1);
+
         match self.hitlag.clone() {
             Hitlag::Some (_) => {
                 self.hitlag.decrement();
@@ -794,6 +982,10 @@ impl Player {
                 Action::JumpF      | Action::JumpB
                 => self.jump_action(context),
 
+                Action::WallJump         => self.wall_jump_action(context),
+                Action::WallTech |
+                Action::CeilingTech      => self.wall_tech_action(context),
+
                 Action::Jab       | Action::Jab2 |
                 Action::Jab3      | Action::Utilt |
                 Action::Ftilt     | Action::DashAttack |
@@ -815,7 +1007,7 @@ impl Player {
                 Action::DamageFall       => self.damage_fall_action(context),
                 Action::Damage           => self.damage_action(context),
                 Action::MissedTechIdle   => self.missed_tech_action(context),
-                Action::MissedTechStart  => self.missed_tech_start_action(context.fighter),
+                Action::MissedTechStart  => self.missed_tech_start_action(context),
                 Action::AerialDodge      => self.aerialdodge_action(context),
                 Action::SpecialFall      => self.specialfall_action(context),
                 Action::Dtilt            => self.dtilt_action(context),
@@ -899,9 +1091,9 @@ impl Player {
         self.ledge_idle_timer += 1;
     }
 
-    fn missed_tech_start_action(&mut self, fighter: &Fighter) {
+    fn missed_tech_start_action(&mut self, context: &mut StepContext) {
         if self.frame == -1 {
-            self.apply_friction(fighter);
+            self.apply_friction(context);
         } else {
             self.x_vel = 0.0;
         }
@@ -931,7 +1123,7 @@ impl Player {
             }
 
             self.hitstun -= 1.0;
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -950,7 +1142,7 @@ impl Player {
                 self.fall_action(context.fighter);
             }
             else {
-                self.apply_friction(context.fighter);
+                self.apply_friction(context);
             }
         }
     }
@@ -1012,6 +1204,7 @@ impl Player {
             else if context.input.b.press {
                 // special attack
             }
+            else if self.check_wall_jump(context) { }
             else if self.check_jump_aerial(context) { }
             else if context.input.l.press || context.input.r.press {
                 self.aerialdodge(context);
@@ -1032,6 +1225,7 @@ impl Player {
         else if context.input.b.press {
             // special attack
         }
+        else if self.check_wall_jump(context) { }
         else if self.check_jump_aerial(context) { }
         else if context.input.l.press || context.input.r.press {
             self.aerialdodge(context);
@@ -1042,6 +1236,42 @@ impl Player {
         }
     }
 
+    fn wall_jump_action(&mut self, context: &mut StepContext) {
+        if self.interruptible(context.fighter) {
+            self.aerial_action(context);
+        }
+        else {
+            self.fastfall_action(context);
+        }
+    }
+
+    /// The player sticks to the wall or ceiling until the tech ends, a wall tech can be cancelled into a wall jump.
+    fn wall_tech_action(&mut self, context: &mut StepContext) {
+        self.x_vel = 0.0;
+        self.y_vel = 0.0;
+        if let Some(Action::WallTech) = Action::from_u64(self.action) {
+            self.check_wall_jump(context);
+        }
+    }
+
+    /// Jumps off a recently touched wall when the stick is smashed away from it
+    fn check_wall_jump(&mut self, context: &mut StepContext) -> bool {
+        let fighter = context.fighter;
+        if let Some(ref wall_jump) = fighter.wall_jump {
+            let away = if self.wall_right { -1.0 } else { 1.0 };
+            if self.wall_jump_timer > 0 && context.input[0].stick_x * away > 0.7 && context.input[1].stick_x * away <= 0.7 {
+                self.x_vel = wall_jump.x_vel * away;
+                self.y_vel = wall_jump.y_vel;
+                self.fastfalled = false;
+                self.face_right = !self.wall_right;
+                self.wall_jump_timer = 0;
+                self.set_action(context, Action::WallJump);
+                return true;
+            }
+        }
+        false
+    }
+
     fn air_drift(&mut self, context: &mut StepContext) {
         let term_vel = context.fighter.air_x_term_vel * context.input[0].stick_x;
         let drift = context.input[0].stick_x.abs() >= 0.3;
@@ -1092,7 +1322,7 @@ impl Player {
         else if self.check_grab(context) { }
         else if self.check_taunt(context) { }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1108,7 +1338,7 @@ impl Player {
         else if self.check_grab(context) { }
         else if self.check_taunt(context) { }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1122,7 +1352,7 @@ impl Player {
 
         if self.check_jump(context) { }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1137,11 +1367,11 @@ impl Player {
             else if self.check_taunt(context) { }
             else if self.check_jump(context) { }
             else {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
         }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1159,11 +1389,11 @@ impl Player {
             else if self.check_smash_turn(context) { }
             else if self.check_tilt_turn(context) { }
             else {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
         }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1181,11 +1411,11 @@ impl Player {
             else if self.check_walk(context) { }
             else if self.check_taunt(context) { }
             else {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
         }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1204,11 +1434,11 @@ impl Player {
             else if self.check_tilt_turn(context) { }
             else if self.check_walk(context) { }
             else {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
         }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1233,11 +1463,11 @@ impl Player {
                 self.set_action(context, Action::Crouch);
             }
             else {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
         }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1260,11 +1490,11 @@ impl Player {
                 self.set_action(context, Action::Crouch);
             }
             else {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
         }
         else {
-            self.apply_friction(&context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1304,7 +1534,7 @@ impl Player {
             let vel_max = context.fighter.walk_max_vel * context.input[0].stick_x;
 
             if self.x_vel.abs() > vel_max.abs() {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
             else {
                 let acc = (vel_max - self.x_vel) * (2.0/context.fighter.walk_max_vel) * (context.fighter.walk_init_vel + context.fighter.walk_acc);
@@ -1327,15 +1557,15 @@ impl Player {
 
         if self.frame > 0 {
             if context.input[0].stick_x.abs() < 0.3 {
-                self.apply_friction(&context.fighter);
+                self.apply_friction(context);
             }
             else {
                 let vel_max = context.input[0].stick_x * context.fighter.dash_run_term_vel;
-                let acc     = context.input[0].stick_x * context.fighter.dash_run_acc_a;
+                let acc     = context.input[0].stick_x * context.fighter.dash_run_acc_a * self.traction(context);
 
                 self.x_vel += acc;
                 if (vel_max > 0.0 && self.x_vel > vel_max) || (vel_max < 0.0 && self.x_vel < vel_max) {
-                    self.apply_friction(&context.fighter);
+                    self.apply_friction(context);
                     if (vel_max > 0.0 && self.x_vel < vel_max) || (vel_max < 0.0 && self.x_vel > vel_max) {
                         self.x_vel = vel_max;
                     }
@@ -1391,7 +1621,8 @@ impl Player {
             let vel_max = context.input[0].stick_x * context.fighter.dash_run_term_vel;
             let acc = (vel_max - self.x_vel)
                     * (context.fighter.dash_run_acc_a + (context.fighter.dash_run_acc_b / context.input[0].stick_x.abs()))
-                    / (context.fighter.dash_run_term_vel * 2.5);
+                    / (context.fighter.dash_run_term_vel * 2.5)
+                    * self.traction(context);
 
             self.x_vel += acc;
             if self.relative_f(self.x_vel) > self.relative_f(vel_max) {
@@ -1407,7 +1638,7 @@ impl Player {
             self.set_action(context, Action::RunTurn);
         }
         else {
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
         }
     }
 
@@ -1450,11 +1681,11 @@ impl Player {
             self.action = Action::PowerShield as u64;
             self.frame = if power_shield_len >= 2 { 1 } else { 0 }; // change self.frame so that a powershield isnt laggier than a normal shield
 
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
             self.shield_shared_action(context);
         }
         else {
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
             self.shield_shared_action(context);
         }
     }
@@ -1473,11 +1704,11 @@ impl Player {
                 self.set_action(context, Action::ShieldOff);
             }
 
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
             self.shield_shared_action(context);
         }
         else {
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
             self.shield_shared_action(context);
         }
     }
@@ -1490,7 +1721,7 @@ impl Player {
         if !lock && self.check_jump(context) { }
         else if !lock && self.check_pass_platform(context) { }
         else {
-            self.apply_friction(context.fighter);
+            self.apply_friction(context);
             self.shield_shared_action(context);
         }
     }
@@ -1507,13 +1738,13 @@ impl Player {
                 self.shield_shared_action(context);
             }
             _ => {
-                self.apply_friction(context.fighter);
+                self.apply_friction(context);
             }
         }
     }
 
     fn shield_shared_action(&mut self, context: &mut StepContext) {
-        self.apply_friction(context.fighter);
+        self.apply_friction(context);
         if let Some(ref shield) = context.fighter.shield {
             // shield analog
             self.shield_analog = if context.input[0].l || context.input[0].r {
@@ -1554,7 +1785,7 @@ impl Player {
     }
 
     fn stun_action(&mut self, context: &mut StepContext) {
-        self.apply_friction(context.fighter);
+        self.apply_friction(context);
         if self.shield_hp > 30.0 {
             self.shield_hp = 30.0;
         }
@@ -1913,6 +2144,7 @@ impl Player {
             Some(Action::JumpB)          => self.set_action(context, Action::Fall),
             Some(Action::JumpAerialF)    => self.set_action(context, Action::AerialFall),
             Some(Action::JumpAerialB)    => self.set_action(context, Action::AerialFall),
+            Some(Action::WallJump)       => self.set_action(context, Action::AerialFall),
             Some(Action::SmashTurn)      => self.set_action(context, Action::Idle),
             Some(Action::RunTurn)        => {
                 if self.relative_f(context.input[0].stick_x) > 0.6 {
@@ -1989,6 +2221,8 @@ impl Player {
             Some(Action::MissedTechGetupF) => self.set_action(context, Action::Idle),
             Some(Action::MissedTechGetupN) => self.set_action(context, Action::Idle),
             Some(Action::MissedTechGetupB) => self.set_action(context, Action::Idle),
+            Some(Action::WallTech)         => self.set_action(context, Action::Fall),
+            Some(Action::CeilingTech)      => self.set_action(context, Action::Fall),
             Some(Action::Rebound)          => self.set_action(context, Action::Idle),
             Some(Action::LedgeRoll)        => self.set_action_idle_from_ledge(context),
             Some(Action::LedgeRollSlow)    => self.set_action_idle_from_ledge(context),
@@ -2164,7 +2398,7 @@ impl Player {
                 if self.is_airbourne() {
                     self.kb_x_vel -= self.kb_x_dec;
                 } else {
-                    self.kb_x_vel -= vel_dir * context.fighter.friction;
+                    self.kb_x_vel -= vel_dir * context.fighter.friction * self.traction(context);
                 }
                 if vel_dir != self.kb_x_vel.signum() {
                     self.kb_x_vel = 0.0;
@@ -2207,13 +2441,16 @@ impl Player {
                     if let Some(platform_i) = self.land_stage_collision(context, (x, y), (new_x, new_y)) {
                         let x = context.stage.surfaces[platform_i].world_x_to_plat_x(new_x);
                         self.land(context, platform_i, x);
+                    } else if let Some(surface_i) = self.wall_ceiling_collision(context, (x, y), (new_x, new_y)) {
+                        self.location = Location::Airbourne { x, y };
+                        self.hit_wall_ceiling(context, surface_i, (x, y));
                     } else {
                         self.location = Location::Airbourne { x: new_x, y: new_y };
                     }
                 }
                 Location::Surface { platform_i, mut x } => {
                     if let Some(platform) = context.stage.surfaces.get(platform_i) {
-                        x += x_vel * platform.floor_angle().unwrap_or_default().cos();
+                        x += x_vel * platform.floor_angle().unwrap_or_default().cos() + platform.conveyor();
                         self.floor_move(context, platform, platform_i, x);
                     }
                     else {
@@ -2230,10 +2467,18 @@ impl Player {
             if x < blast.left() || x > blast.right() || y < blast.bot() || y > blast.top() {
                 self.die(context, player_i, game_frame, goal);
             }
+            else if let Some(timer) = self.stamina_ko_timer {
+                if timer == 0 {
+                    self.die(context, player_i, game_frame, goal);
+                } else {
+                    self.stamina_ko_timer = Some(timer - 1);
+                }
+            }
 
             // ledge grabs
             let fighter_frame = &context.fighter.actions[self.action as usize].frames[self.frame as usize];
             self.frames_since_ledge += 1;
+            self.wall_jump_timer = self.wall_jump_timer.saturating_sub(1);
             if self.frames_since_ledge >= 30 && self.y_vel < 0.0 && context.input.stick_y.value > -0.5 {
                 if let Some(ref ledge_grab_box) = fighter_frame.ledge_grab_box {
                     self.check_ledge_grab(context, &ledge_grab_box);
@@ -2288,7 +2533,19 @@ impl Player {
         }
     }
 
-    fn apply_friction(&mut self, fighter: &Fighter) {
+    /// Returns the traction of the floor the player is standing on, 1.0 when not on a floor
+    fn traction(&self, context: &StepContext) -> f32 {
+        if let Location::Surface { platform_i, .. } = self.location {
+            if let Some(&Some(ref floor)) = context.surfaces.get(platform_i).map(|x| &x.floor) {
+                return floor.traction;
+            }
+        }
+        1.0
+    }
+
+    fn apply_friction(&mut self, context: &StepContext) {
+        let fighter = context.fighter;
+        let traction = self.traction(context);
         match Action::from_u64(self.action) {
             Some(Action::Idle) |
             Some(Action::Dash) |
@@ -2296,36 +2553,36 @@ impl Player {
             Some(Action::ShieldOn) |
             Some(Action::ShieldOff) |
             Some(Action::Damage)
-              => { self.apply_friction_weak(fighter) }
-            _ => { self.apply_friction_strong(fighter) }
+              => { self.apply_friction_weak(fighter, traction) }
+            _ => { self.apply_friction_strong(fighter, traction) }
         }
     }
 
     // TODO: These functions are split up as weak/strong so that one day they may be called individually by player scripts
-    fn apply_friction_weak(&mut self, fighter: &Fighter) {
+    fn apply_friction_weak(&mut self, fighter: &Fighter, traction: f32) {
         if self.x_vel > 0.0 {
-            self.x_vel -= fighter.friction;
+            self.x_vel -= fighter.friction * traction;
             if self.x_vel < 0.0 {
                 self.x_vel = 0.0;
             }
         }
         else {
-            self.x_vel += fighter.friction;
+            self.x_vel += fighter.friction * traction;
             if self.x_vel > 0.0 {
                 self.x_vel = 0.0;
             }
         }
     }
 
-    fn apply_friction_strong(&mut self, fighter: &Fighter) {
+    fn apply_friction_strong(&mut self, fighter: &Fighter, traction: f32) {
         if self.x_vel > 0.0 {
-            self.x_vel -= fighter.friction * if self.x_vel > fighter.walk_max_vel { 2.0 } else { 1.0 };
+            self.x_vel -= fighter.friction * traction * if self.x_vel > fighter.walk_max_vel { 2.0 } else { 1.0 };
             if self.x_vel < 0.0 {
                 self.x_vel = 0.0;
             }
         }
         else {
-            self.x_vel += fighter.friction * if self.x_vel < -fighter.walk_max_vel { 2.0 } else { 1.0 };
+            self.x_vel += fighter.friction * traction * if self.x_vel < -fighter.walk_max_vel { 2.0 } else { 1.0 };
             if self.x_vel > 0.0 {
                 self.x_vel = 0.0;
             }
@@ -2333,13 +2590,14 @@ impl Player {
     }
 
     /// returns the index platform that the player will land on
+    /// The collision is checked relative to each surface, so a rising platform can catch a player from below its previous position.
     fn land_stage_collision(&mut self, context: &mut StepContext, old_p: (f32, f32), new_p: (f32, f32)) -> Option<usize> {
-        if new_p.1 > old_p.1 {
-            return None
-        }
-
-        for (surface_i, surface) in context.stage.surfaces.iter().enumerate() {
-            if !self.pass_through_platform(context, surface) &&
+        for surface_i in context.stage.surfaces_near(&Rect::from_tuples(old_p, new_p)) {
+            let surface = &context.stage.surfaces[surface_i];
+            let (vel_x, vel_y) = surface.velocity();
+            let old_p = (old_p.0 + vel_x, old_p.1 + vel_y);
+            if new_p.1 <= old_p.1 &&
+                !self.pass_through_platform(context, surface) &&
                 surface.floor.is_some() &&
                 geometry::segments_intersect(old_p, new_p, surface.p1(), surface.p2())
             {
@@ -2349,6 +2607,104 @@ impl Player {
         None
     }
 
+    /// returns the index of the wall or ceiling that the player collides with
+    fn wall_ceiling_collision(&self, context: &StepContext, old_p: (f32, f32), new_p: (f32, f32)) -> Option<usize> {
+        for surface_i in context.stage.surfaces_near(&Rect::from_tuples(old_p, new_p)) {
+            let surface = &context.stage.surfaces[surface_i];
+            let (vel_x, vel_y) = surface.velocity();
+            let old_p = (old_p.0 + vel_x, old_p.1 + vel_y);
+            if (surface.wall || surface.ceiling) && geometry::segments_intersect(old_p, new_p, surface.p1(), surface.p2()) {
+                return Some(surface_i)
+            }
+        }
+        None
+    }
+
+    /// Tumbling players tech off the wall or ceiling if their tech window is active, otherwise they bounce off it.
+    /// Any other player is stopped by the surface and can wall jump off walls.
+    fn hit_wall_ceiling(&mut self, context: &mut StepContext, surface_i: usize, p: (f32, f32)) {
+        let stage = context.stage;
+        let surface = &stage.surfaces[surface_i];
+        let (n_x, n_y) = match Player::surface_normal(surface, p) {
+            Some(normal) => normal,
+            None         => return
+        };
+        let is_ceiling = surface.ceiling && (!surface.wall || n_y.abs() > n_x.abs());
+
+        match Action::from_u64(self.action) {
+            Some(Action::DamageFly) | Some(Action::DamageFall) => {
+                if self.tech_timer.is_active() {
+                    self.x_vel = 0.0;
+                    self.y_vel = 0.0;
+                    self.kb_x_vel = 0.0;
+                    self.kb_y_vel = 0.0;
+                    self.hitstun = 0.0;
+                    self.fastfalled = false;
+                    if is_ceiling {
+                        self.set_action(context, Action::CeilingTech);
+                    } else {
+                        self.face_right = n_x > 0.0;
+                        self.wall_right = n_x < 0.0;
+                        self.wall_jump_timer = context.fighter.wall_jump.as_ref().map(|x| x.window).unwrap_or(0);
+                        self.set_action(context, Action::WallTech);
+                    }
+                }
+                else {
+                    // a missed tech always bounces, even off surfaces without bounce
+                    self.bounce((n_x, n_y), surface.bounce.max(0.5));
+                }
+            }
+            _ => {
+                self.bounce((n_x, n_y), surface.bounce);
+                if !is_ceiling {
+                    self.wall_right = n_x < 0.0;
+                    self.wall_jump_timer = context.fighter.wall_jump.as_ref().map(|x| x.window).unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    /// Returns the unit normal of the surface pointing towards the side that p is on
+    fn surface_normal(surface: &Surface, p: (f32, f32)) -> Option<(f32, f32)> {
+        let d_x = surface.x2 - surface.x1;
+        let d_y = surface.y2 - surface.y1;
+        let length = (d_x * d_x + d_y * d_y).sqrt();
+        if length == 0.0 {
+            return None;
+        }
+
+        let (n_x, n_y) = (-d_y / length, d_x / length);
+        if (p.0 - surface.x1) * n_x + (p.1 - surface.y1) * n_y < 0.0 {
+            Some((-n_x, -n_y))
+        } else {
+            Some((n_x, n_y))
+        }
+    }
+
+    /// Reflects the players velocity off a surface with the passed normal, scaled by bounce.
+    /// With no bounce only the velocity into the surface is removed, so the player slides along it.
+    fn bounce(&mut self, normal: (f32, f32), bounce: f32) {
+        let (n_x, n_y) = normal;
+        let mult = 1.0 + bounce;
+        let dot = self.x_vel * n_x + self.y_vel * n_y;
+        if dot < 0.0 {
+            self.x_vel -= mult * dot * n_x;
+            self.y_vel -= mult * dot * n_y;
+        }
+        let kb_dot = self.kb_x_vel * n_x + self.kb_y_vel * n_y;
+        if kb_dot < 0.0 {
+            self.kb_x_vel -= mult * kb_dot * n_x;
+            self.kb_y_vel -= mult * kb_dot * n_y;
+
+            // knockback decays in the direction it is travelling
+            let kb_vel = (self.kb_x_vel * self.kb_x_vel + self.kb_y_vel * self.kb_y_vel).sqrt();
+            if kb_vel > 0.0 {
+                self.kb_x_dec = self.kb_x_vel / kb_vel * 0.051;
+                self.kb_y_dec = self.kb_y_vel / kb_vel * 0.051;
+            }
+        }
+    }
+
     pub fn pass_through_platform(&self, context: &mut StepContext, platform: &Surface) -> bool {
         let fighter_frame = &context.fighter.actions[self.action as usize].frames[self.frame as usize];
         platform.is_pass_through() && fighter_frame.pass_through && context.input[0].stick_y <= -0.56
@@ -2448,7 +2804,7 @@ impl Player {
         self.hitstun = 0.0;
         self.fastfalled = false;
         self.air_jumps_left = context.fighter.air_jumps;
-        self.hit_by = None;
+        self.reset_hit_by();
         self.location = Location::Surface { platform_i, x };
     }
 
@@ -2475,7 +2831,9 @@ impl Player {
             self.location = Location::Airbourne { x: respawn.x, y: respawn.y };
             self.face_right = respawn.face_right;
         }
-        self.damage = 0.0;
+        self.damage = self.handicap.starting_damage;
+        self.hp = Player::max_hp(&goal);
+        self.stamina_ko_timer = None;
         self.x_vel = 0.0;
         self.y_vel = 0.0;
         self.kb_x_vel = 0.0;
@@ -2485,13 +2843,18 @@ impl Player {
         self.hitstun = 0.0;
         self.hitlag = Hitlag::None;
 
+        // The last player to hit us is credited with the kill, unless it was too long ago, in which case it is a self-destruct.
+        // Landing or grabbing a ledge also clears the last hit, so the window only limits how long a player can be kept offstage.
+        let within_window = context.rules.last_hit_window_frames.map(|x| self.hit_by_timer <= x).unwrap_or(true);
         self.result.deaths.push(DeathRecord {
-            player: self.hit_by,
-            frame: game_frame,
+            player: if within_window { self.hit_by } else { None },
+            stage:  within_window && self.hit_by_stage,
+            frame:  game_frame,
         });
+        self.reset_hit_by();
 
         match goal {
-            Goal::LastManStanding => {
+            Goal::LastManStanding | Goal::Stamina { .. } => {
                 if let Some(mut stocks) = self.stocks {
                     stocks -= 1;
                     self.stocks = Some(stocks);
@@ -2511,7 +2874,19 @@ impl Player {
     }
 
     fn check_ledge_grab(&mut self, context: &mut StepContext, ledge_grab_box: &LedgeGrabBox) {
-        for (platform_i, platform) in context.surfaces.iter().enumerate() {
+        let rect = if let Location::Airbourne { x, y } = self.location {
+            Rect {
+                x1: x + self.relative_f(ledge_grab_box.x1),
+                y1: y + ledge_grab_box.y1,
+                x2: x + self.relative_f(ledge_grab_box.x2),
+                y2: y + ledge_grab_box.y2,
+            }
+        } else {
+            return;
+        };
+
+        for platform_i in context.stage.surfaces_near(&rect) {
+            let platform = &context.stage.surfaces[platform_i];
             let left_grab  = platform.left_grab()  && self.check_ledge_collision(ledge_grab_box, platform.left_ledge())  && context.players.iter().all(|x| !x.is_hogging_ledge(platform_i, true));
             let right_grab = platform.right_grab() && self.check_ledge_collision(ledge_grab_box, platform.right_ledge()) && context.players.iter().all(|x| !x.is_hogging_ledge(platform_i, false));
 
@@ -2529,7 +2904,7 @@ impl Player {
                 self.y_vel = 0.0;
                 self.fastfalled = false;
                 self.air_jumps_left = context.fighter.air_jumps;
-                self.hit_by = None;
+                self.reset_hit_by();
                 self.location = Location::GrabbedLedge { platform_i, d_x: -3.0, d_y: -24.0, logic: LedgeLogic::Hog };
                 self.set_action(context, Action::LedgeGrab);
             }
@@ -2691,6 +3066,7 @@ impl Player {
         RenderPlayer {
             team:        self.team,
             damage:      self.damage,
+            hp:          self.hp,
             stocks:      self.stocks,
             frame_data:  self.relative_frame(fighter, surfaces),
             particles:   self.particles.clone(),
@@ -2705,14 +3081,57 @@ impl Player {
         }
     }
 
+    /// Nobody is credited for a following death until the player is hit again
+    fn reset_hit_by(&mut self) {
+        self.hit_by = None;
+        self.hit_by_stage = false;
+    }
+
     pub fn result(&self) -> RawPlayerResult {
         let mut result = self.result.clone();
         result.final_damage = Some(self.damage);
+        result.final_hp = self.hp;
         result.ended_as_fighter = Some(self.fighter.clone());
         result.team = self.team;
         result
     }
 
+    fn max_hp(goal: &Goal) -> Option<f32> {
+        if let &Goal::Stamina { hp } = goal {
+            Some(hp)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the player has run out of hp and is waiting to die
+    pub fn is_stamina_ko(&self) -> bool {
+        self.stamina_ko_timer.is_some()
+    }
+
+    pub fn stamina_ko_particles(&mut self, context: &mut StepContext) {
+        let (x, y) = self.bps_xy(context);
+        for _ in 0..40 {
+            let angle = context.rng.gen_range(0.0, 2.0 * PI);
+            let vel = context.rng.gen_range(0.5, 2.0);
+            self.particles.push(Particle {
+                color:       graphics::get_team_color3(self.team),
+                counter:     0,
+                counter_max: 60,
+                x:           x,
+                y:           y + self.ecb.top / 2.0,
+                angle:       context.rng.gen_range(0.0, 2.0 * PI),
+                p_type:      ParticleType::Spark {
+                    x_vel:      angle.cos() * vel,
+                    y_vel:      angle.sin() * vel,
+                    size:       context.rng.gen_range(1.0, 4.0),
+                    angle_vel:  context.rng.gen_range(0.0, 1.0),
+                    background: false,
+                }
+            });
+        }
+    }
+
     pub fn hit_particles(&mut self, point: (f32, f32), hitbox: &HitBox) {
         self.particles.push(Particle {
             color:       graphics::get_team_color3(self.team),
@@ -2868,6 +3287,7 @@ pub struct RenderPlayer {
     pub team:              usize,
     pub debug:             DebugPlayer,
     pub damage:            f32,
+    pub hp:                Option<f32>,
     pub stocks:            Option<u64>,
     pub frame_data:        ActionFrame,
     pub fighter_color:     [f32; 3],
@@ -2932,75 +3352,3 @@ pub struct DebugPlayer {
     pub cam_area:       bool,
 }
 
-impl DebugPlayer {
-    pub fn step(&mut self, os_input: &WinitInputHelper<()>) {
-        if os_input.key_pressed(VirtualKeyCode::F1) {
-            self.physics = !self.physics;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F2) {
-            if os_input.held_shift() {
-                self.input_diff = !self.input_diff;
-            }
-            else {
-                self.input = !self.input;
-            }
-        }
-        if os_input.key_pressed(VirtualKeyCode::F3) {
-            self.action = !self.action;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F4) {
-            self.frame = !self.frame;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F5) {
-            self.stick_vector = !self.stick_vector;
-            self.c_stick_vector = !self.c_stick_vector;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F6) {
-            self.di_vector = !self.di_vector;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F7) {
-            self.hitbox_vectors = !self.hitbox_vectors;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F8) {
-            self.ecb = !self.ecb;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F9) {
-            self.fighter = match self.fighter {
-                RenderFighter::Normal => {
-                    RenderFighter::OnionSkin
-                }
-                RenderFighter::OnionSkin=> {
-                    RenderFighter::Debug
-                }
-                RenderFighter::Debug => {
-                    RenderFighter::None
-                }
-                RenderFighter::None => {
-                    RenderFighter::Normal
-                }
-            };
-        }
-        if os_input.key_pressed(VirtualKeyCode::F10) {
-            self.cam_area = !self.cam_area;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F11) {
-            *self = DebugPlayer {
-                physics:        true,
-                input:          true,
-                input_diff:     true,
-                action:         true,
-                frame:          true,
-                stick_vector:   true,
-                c_stick_vector: true,
-                di_vector:      true,
-                hitbox_vectors: true,
-                ecb:            true,
-                fighter:        RenderFighter::Debug,
-                cam_area:       true,
-            }
-        }
-        if os_input.key_pressed(VirtualKeyCode::F12) {
-            *self = DebugPlayer::default();
-        }
-    }
-}
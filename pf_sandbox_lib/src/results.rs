@@ -0,0 +1,40 @@
+use treeflection::{Node, NodeRunner, NodeToken};
+
+/// An individual players results: processed according to other players and current game mode
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Node)]
+pub struct PlayerResult {
+    pub fighter:          String,
+    pub team:             usize,
+    pub controller:       usize,
+    pub place:            usize,
+    pub kills:            Vec<DeathRecord>, // DeathRecord::player is the player that was killed
+    pub deaths:           Vec<DeathRecord>,
+    pub score:            Option<i64>, // only used by Goal::KillDeathScore
+    pub lcancel_percent:  f32,
+}
+
+/// A teams results: all players on the team share the place
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Node)]
+pub struct TeamResult {
+    pub team:  usize,
+    pub place: usize,
+}
+
+/// An individual players results: unprocessed
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Node)]
+pub struct RawPlayerResult {
+    pub team:             usize,
+    pub deaths:           Vec<DeathRecord>,
+    pub lcancel_attempts: u64,
+    pub lcancel_success:  u64,
+    pub final_damage:     Option<f32>,
+    pub final_hp:         Option<f32>,
+    pub ended_as_fighter: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Node)]
+pub struct DeathRecord {
+    pub player: Option<usize>, // the player credited with the kill, None indicates self-destruct unless stage is set
+    pub stage:  bool,          // the kill is credited to a stage hazard
+    pub frame:  usize,
+}
@@ -4,30 +4,46 @@ use crate::json_upgrade::engine_version;
 
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub struct Rules {
-    pub engine_version:     u64,
-    pub title:              String,
-    pub goal:               Goal,
-    pub stock_count:        Option<u64>,
-    pub time_limit_seconds: Option<u64>,
-    pub best_of:            u64,
-    pub pause:              Pause,
-    pub teams:              Teams,
-    pub grab_clang:         bool,
+    pub engine_version:         u64,
+    pub title:                  String,
+    pub goal:                   Goal,
+    pub stock_count:            Option<u64>,
+    pub time_limit_seconds:     Option<u64>,
+    pub tie_break:              TieBreak, // how a tie for first place is resolved
+    pub best_of:                u64,
+    pub pause:                  Pause,
+    pub teams:                  Teams,
+    pub grab_clang:             bool,
+    pub stage_hazards:          bool, // when disabled, stage hazards and hazardous surfaces never hit
+    pub last_hit_window_frames: Option<u64>, // how long the last player to hit you is credited for your death, None means until you land or grab a ledge
+    pub self_destruct_penalty:  u64, // points lost for a self-destruct in addition to the point lost for the death
+    pub starter_stages:         Vec<String>, // stages struck from for the first game of a set, when empty all stages are used
+    pub counterpick_stages:     Vec<String>, // stages that can be picked after the first game of a set in addition to the starter stages
+    pub counterpick_bans:       u64, // number of stages the winner of the previous game bans before the loser picks
+    pub daves_stupid_rule:      bool, // the loser cannot pick a stage they have already won on during the set
     //pub force_user_settings: User,
 }
 
 impl Default for Rules {
     fn default() -> Self {
         Rules {
-            engine_version:     engine_version(),
-            title:              "Base Game Mode".to_string(),
-            goal:               Goal::default(),
-            stock_count:        Some(4),
-            time_limit_seconds: Some(480),
-            best_of:            1,
-            pause:              Pause::default(),
-            teams:              Teams::default(),
-            grab_clang:         false,
+            engine_version:         engine_version(),
+            title:                  "Base Game Mode".to_string(),
+            goal:                   Goal::default(),
+            stock_count:            Some(4),
+            time_limit_seconds:     Some(480),
+            tie_break:              TieBreak::default(),
+            best_of:                1,
+            pause:                  Pause::default(),
+            teams:                  Teams::default(),
+            grab_clang:             false,
+            stage_hazards:          true,
+            last_hit_window_frames: Some(600),
+            self_destruct_penalty:  1,
+            starter_stages:         vec!(),
+            counterpick_stages:     vec!(),
+            counterpick_bans:       2,
+            daves_stupid_rule:      true,
         }
     }
 }
@@ -36,24 +52,47 @@ impl Rules {
     pub fn time_limit_frames(&self) -> Option<u64> {
         self.time_limit_seconds.map(|x| x * 60)
     }
+
+    pub fn teams_enabled(&self) -> bool {
+        match self.teams {
+            Teams::On { .. } => true,
+            Teams::Off       => false,
+        }
+    }
+
+    /// Returns true if a player on team_atk is able to hit a player on team_def
+    pub fn can_hit_team(&self, team_atk: usize, team_def: usize) -> bool {
+        match self.teams {
+            Teams::On { friendly_fire, .. } => friendly_fire || team_atk != team_def,
+            Teams::Off                      => true,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub enum Goal {
     KillDeathScore,
     LastManStanding,
+    Stamina { hp: f32 }, // Like LastManStanding but players are KO'd when their hp runs out
+}
+
+#[derive(Clone, Serialize, Deserialize, Node)]
+pub enum TieBreak {
+    SuddenDeath { damage: f32 },          // the tied players respawn with one stock at this damage and play until someone is KO'd
+    Overtime { time_limit_seconds: u64 }, // the tied players respawn with one stock for a shortened game, then lowest percent wins
+    Percent,                              // lowest percent wins, or highest hp in Stamina
 }
 
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub enum Pause {
     On,
     Off,
-    Hold,
+    Hold { frames: u64 }, // start must be held for this many frames to pause
 }
 
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub enum Teams {
-    On { friendly_fire: bool },
+    On { friendly_fire: bool, stock_steal: bool },
     Off,
 }
 
@@ -63,6 +102,12 @@ impl Default for Goal {
     }
 }
 
+impl Default for TieBreak {
+    fn default() -> Self {
+        TieBreak::SuddenDeath { damage: 300.0 }
+    }
+}
+
 impl Default for Pause {
     fn default() -> Self {
         Pause::On
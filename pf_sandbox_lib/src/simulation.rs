@@ -0,0 +1,550 @@
+use crate::collision::collision_check;
+use crate::fighter::Action;
+use crate::input::{ControllerInput, PlayerInput};
+use crate::package::Package;
+use crate::player::{Player, PlayerChecksum, StepContext, Handicap};
+use crate::results::{RawPlayerResult, PlayerResult, TeamResult, DeathRecord};
+use crate::rules::{Goal, Teams, TieBreak};
+use crate::stage::Stage;
+
+use bincode;
+use byteorder::{LittleEndian, WriteBytesExt};
+use num_traits::ToPrimitive;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaChaRng;
+use std::cmp::Ordering;
+use std::io;
+use treeflection::{Node, NodeRunner, NodeToken};
+
+/// The state of a match that is advanced one frame at a time by controller inputs.
+/// Has no dependencies on windowing, rendering or input devices so matches can be run headlessly.
+///
+/// Frame 0 refers to the initial state of the match.
+/// Any changes occur in the proceeding frames i.e. frames 1, 2, 3 ...
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct Simulation {
+    pub package:       Package,
+    pub init_seed:     u64,
+    pub current_frame: usize,
+    pub stage:         Stage,
+    pub players:       Vec<Player>,
+    pub controllers:   Vec<usize>, // index of the controller each player is using
+    pub sudden_deaths: Vec<SuddenDeath>, // the last sudden death is the one currently being played
+    input_history:     Vec<Vec<ControllerInput>>, // only used by step, the inputs of frame f are at index f - 1
+}
+
+impl Simulation {
+    pub fn new(package: Package, setup: SimulationSetup) -> Simulation {
+        let mut stage = package.stages[setup.stage.as_ref()].clone();
+        stage.step_surface_paths(0);
+        stage.rebuild_surface_grid();
+
+        // Stage can have less spawn points then players
+        let players = setup.players.iter().enumerate().map(|(i, player)|
+            Player::new(player.fighter.clone(), player.team, player.handicap.clone(), i, &stage, &package)
+        ).collect();
+
+        Simulation {
+            package,
+            init_seed:     setup.init_seed,
+            current_frame: 0,
+            stage,
+            players,
+            controllers:   setup.controllers,
+            sudden_deaths: vec!(),
+            input_history: vec!(),
+        }
+    }
+
+    pub fn get_seed(&self) -> [u8; 32] {
+        let mut seed = [0; 32];
+        (&mut seed[0..8]).write_u64::<LittleEndian>(self.init_seed).unwrap();
+        (&mut seed[8..16]).write_u64::<LittleEndian>(self.current_frame as u64).unwrap();
+        seed
+    }
+
+    /// Returns a checksum of every value that affects the following frames: the players, stage, sudden deaths and rng seed.
+    /// Values are serialized with bincode so floats are hashed bitwise, two runs of the same inputs must produce the same checksum on every frame.
+    /// Particles are excluded as they are visual only and are not resimulated by netplay rollback.
+    pub fn checksum(&self) -> u64 {
+        checksum(&self.players, &self.stage, &self.sudden_deaths, self.init_seed, self.current_frame)
+    }
+
+    /// Steps the simulation to the next frame with the passed controller inputs, indexed by controller.
+    /// Input history is kept by the simulation so that presses can be detected.
+    pub fn step(&mut self, inputs: &[ControllerInput]) -> SimulationState {
+        self.current_frame += 1;
+        self.input_history.truncate(self.current_frame - 1);
+        self.input_history.push(inputs.to_vec());
+
+        let player_inputs: Vec<PlayerInput> = (0..inputs.len())
+            .map(|i| PlayerInput::from_history(&self.input_history, i, self.current_frame as i64))
+            .collect();
+        self.step_frame(&player_inputs)
+    }
+
+    /// Simulates current_frame from the state of the previous frame.
+    /// Used directly when the caller manages its own input history, e.g. for netplay.
+    pub fn step_frame(&mut self, player_input: &[PlayerInput]) -> SimulationState {
+        // Surfaces are moved before the players so that players standing on or hanging off them are carried along.
+        self.stage.step_surface_paths(self.current_frame as u64);
+
+        {
+            let mut rng = ChaChaRng::from_seed(self.get_seed());
+
+            // To synchronize player stepping, we step through player logic in stages (action logic, physics logic, collision logic)
+            // Modified players are copied from the previous stage so that every player perceives themselves as being stepped first, within that stage.
+
+            // step each player action
+            let mut action_players: Vec<Player> = vec!();
+            for (i, player) in self.players.iter().enumerate() {
+                let mut player = player.clone();
+                let input = &player_input[self.controllers[i]];
+                let mut context = StepContext {
+                    players:  &self.players,
+                    fighters: &self.package.fighters,
+                    fighter:  &self.package.fighters[player.fighter.as_ref()],
+                    stage:    &self.stage,
+                    surfaces: &self.stage.surfaces,
+                    rules:    &self.package.rules,
+                    rng:      &mut rng,
+                    input,
+                };
+                player.action_hitlag_step(&mut context);
+                action_players.push(player);
+            }
+
+            // step each player physics
+            let mut physics_players: Vec<Player> = vec!();
+            for (i, player) in action_players.iter().enumerate() {
+                let mut player = player.clone();
+                let input = &player_input[self.controllers[i]];
+                let mut context = StepContext {
+                    players:  &self.players,
+                    fighters: &self.package.fighters,
+                    fighter:  &self.package.fighters[player.fighter.as_ref()],
+                    stage:    &self.stage,
+                    surfaces: &self.stage.surfaces,
+                    rules:    &self.package.rules,
+                    rng:      &mut rng,
+                    input,
+                };
+                player.physics_step(&mut context, i, self.current_frame, self.goal());
+                physics_players.push(player);
+            }
+
+            // check for hits and run hit logic
+            let mut collision_players: Vec<Player> = vec!();
+            let collision_results = collision_check(&physics_players, &self.package.fighters, &self.stage, &self.package.rules, self.current_frame);
+            for (i, player) in physics_players.iter().enumerate() {
+                let mut player = player.clone();
+                let input = &player_input[self.controllers[i]];
+                let mut context = StepContext {
+                    players:  &self.players,
+                    fighters: &self.package.fighters,
+                    fighter:  &self.package.fighters[player.fighter.as_ref()],
+                    stage:    &self.stage,
+                    surfaces: &self.stage.surfaces,
+                    rules:    &self.package.rules,
+                    rng:      &mut rng,
+                    input,
+                };
+                player.step_collision(&mut context, &collision_results[i]);
+                collision_players.push(player);
+            }
+
+            self.players = collision_players;
+        }
+
+        self.step_stock_steal(player_input);
+
+        // When teams are enabled, players on the same team are in the same pool.
+        let pools = self.pools();
+        let mut all_pools: Vec<usize> = pools.clone();
+        all_pools.sort();
+        all_pools.dedup();
+        let mut remaining_pools: Vec<usize> = pools.iter().zip(self.players.iter()).filter(|x| x.1.action != Action::Eliminated.to_u64().unwrap()).map(|x| *x.0).collect();
+        remaining_pools.sort();
+        remaining_pools.dedup();
+
+        if self.time_out() ||
+           (all_pools.len() == 1 && remaining_pools.len() == 0) ||
+           (all_pools.len() >  1 && remaining_pools.len() <= 1)
+        {
+            self.end_game()
+        } else {
+            SimulationState::Running
+        }
+    }
+
+    /// The goal currently being played.
+    /// A KillDeathScore sudden death is played as LastManStanding so that the first KO ends it.
+    pub fn goal(&self) -> Goal {
+        match (&self.package.rules.goal, self.sudden_deaths.last()) {
+            (&Goal::KillDeathScore, Some(_)) => Goal::LastManStanding,
+            (goal, _)                        => goal.clone(),
+        }
+    }
+
+    /// Either starts a sudden death between the players tied for first place or finishes the game.
+    fn end_game(&mut self) -> SimulationState {
+        let pools = self.pools();
+        let scores = self.scores(&self.kills());
+        let order = self.order(&pools, &scores);
+        let tied = self.tied_for_first(&order, &pools, &scores);
+        if tied.len() > 0 {
+            self.start_sudden_death(order, tied);
+            SimulationState::Running
+        } else {
+            SimulationState::Finished (order)
+        }
+    }
+
+    /// Respawns the tied players with one stock and eliminates everyone else.
+    fn start_sudden_death(&mut self, order: Vec<usize>, players: Vec<usize>) {
+        let (damage, time_limit_frames) = match self.package.rules.tie_break {
+            TieBreak::SuddenDeath { damage }          => (Some(damage), None),
+            TieBreak::Overtime { time_limit_seconds } => (None, Some(time_limit_seconds * 60)),
+            TieBreak::Percent                         => unreachable!(),
+        };
+
+        for (player_i, player) in self.players.iter_mut().enumerate() {
+            if players.contains(&player_i) {
+                let mut respawned = Player::new(player.fighter.clone(), player.team, player.handicap.clone(), player_i, &self.stage, &self.package);
+                respawned.result = player.result.clone();
+                respawned.stocks = Some(1);
+                if let Some(damage) = damage {
+                    respawned.damage = damage;
+                    if let Some(ref mut hp) = respawned.hp {
+                        *hp = (*hp - damage).max(1.0);
+                    }
+                }
+                *player = respawned;
+            }
+            else if player.action != Action::Eliminated.to_u64().unwrap() {
+                player.public_set_action(Action::Eliminated);
+            }
+        }
+
+        self.sudden_deaths.push(SuddenDeath {
+            start_frame: self.current_frame,
+            players,
+            order,
+            time_limit_frames,
+        });
+    }
+
+    /// Restores the state after the passed frame so the following frames can be resimulated e.g. with corrected netplay inputs
+    pub fn rewind(&mut self, frame: usize, players: Vec<Player>, stage: Stage) {
+        self.players = players;
+        self.stage = stage;
+        self.stage.rebuild_surface_grid();
+        self.rewind_sudden_deaths(frame);
+        self.current_frame = frame;
+    }
+
+    /// Forget any sudden deaths that started after the frame the game has been rewound to
+    pub fn rewind_sudden_deaths(&mut self, frame: usize) {
+        while self.sudden_deaths.last().map(|x| x.start_frame > frame).unwrap_or(false) {
+            self.sudden_deaths.pop();
+        }
+    }
+
+    /// Returns the pool each player scores to.
+    /// When teams are enabled players on the same team share a pool, otherwise each player has their own pool.
+    fn pools(&self) -> Vec<usize> {
+        if self.package.rules.teams_enabled() {
+            self.players.iter().map(|x| x.team).collect()
+        } else {
+            (0..self.players.len()).collect()
+        }
+    }
+
+    /// An eliminated player can press A to take a stock from the teammate with the most stocks, and respawn.
+    fn step_stock_steal(&mut self, player_input: &[PlayerInput]) {
+        if let Teams::On { stock_steal: true, .. } = self.package.rules.teams {
+            for thief_i in 0..self.players.len() {
+                let input = &player_input[self.controllers[thief_i]];
+                if self.players[thief_i].action == Action::Eliminated.to_u64().unwrap() && input.a.press {
+                    let team = self.players[thief_i].team;
+                    let mut victim: Option<usize> = None;
+                    for (player_i, player) in self.players.iter().enumerate() {
+                        let stocks = player.stocks.unwrap_or(0);
+                        let most_stocks = victim.map(|x| stocks > self.players[x].stocks.unwrap_or(0)).unwrap_or(true);
+                        if player_i != thief_i && player.team == team && stocks > 1 && most_stocks {
+                            victim = Some(player_i);
+                        }
+                    }
+
+                    if let Some(victim_i) = victim {
+                        self.players[victim_i].stocks = self.players[victim_i].stocks.map(|x| x - 1);
+                        self.players[thief_i].stocks = Some(1);
+                        self.players[thief_i].public_set_action(Action::ReSpawn);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The frame the game or the current sudden death runs out of time on
+    pub fn end_frame(&self) -> Option<u64> {
+        match self.sudden_deaths.last() {
+            Some(sudden_death) => sudden_death.time_limit_frames.map(|x| sudden_death.start_frame as u64 + x),
+            None               => self.package.rules.time_limit_frames(),
+        }
+    }
+
+    pub fn time_out(&self) -> bool {
+        if let Some(end_frame) = self.end_frame() {
+            self.current_frame as u64 > end_frame
+        } else {
+            false
+        }
+    }
+
+    /// The players killed by each player
+    fn kills(&self) -> Vec<Vec<DeathRecord>> {
+        let raw_player_results: Vec<RawPlayerResult> = self.players.iter().map(|x| x.result()).collect();
+        let pools = self.pools();
+
+        // Each death credited to another player is a kill for that player, killing a player in your own pool does not count.
+        (0..raw_player_results.len()).map(|killer_i| {
+            let mut kills = vec!();
+            for (victim_i, victim) in raw_player_results.iter().enumerate() {
+                if pools[victim_i] != pools[killer_i] {
+                    for death in &victim.deaths {
+                        if death.player == Some(killer_i) {
+                            kills.push(DeathRecord { player: Some(victim_i), stage: false, frame: death.frame });
+                        }
+                    }
+                }
+            }
+            kills
+        }).collect()
+    }
+
+    /// +1 for each kill, -1 for each death and an additional penalty for each self-destruct
+    /// Deaths to stage hazards are not self-destructs.
+    fn scores(&self, kills: &[Vec<DeathRecord>]) -> Vec<i64> {
+        self.players.iter().enumerate().map(|(i, player)| {
+            let deaths = &player.result.deaths;
+            let self_destructs = deaths.iter().filter(|x| x.player.is_none() && !x.stage).count() as i64;
+            kills[i].len() as i64 - deaths.len() as i64 - self_destructs * self.package.rules.self_destruct_penalty as i64
+        }).collect()
+    }
+
+    /// Compares two players by the goal, players that are tied are Equal.
+    fn compare_players(&self, a: usize, b: usize, pools: &[usize], scores: &[i64]) -> Ordering {
+        let a_player = &self.players[a];
+        let b_player = &self.players[b];
+        match self.goal() {
+            Goal::LastManStanding | Goal::Stamina { .. } => {
+                // most stocks remaining wins, without a stock count least deaths wins
+                // if both eliminated: who lost their last stock last wins
+                let a_deaths = &a_player.result.deaths;
+                let b_deaths = &b_player.result.deaths;
+                let stocks = match (a_player.stocks, b_player.stocks) {
+                    (Some(a_stocks), Some(b_stocks)) => b_stocks.cmp(&a_stocks),
+                    _                                => a_deaths.len().cmp(&b_deaths.len()),
+                };
+                let eliminated = Action::Eliminated.to_u64().unwrap();
+                stocks.then(
+                    if a_player.action == eliminated && b_player.action == eliminated {
+                        b_deaths.last().map(|x| x.frame).cmp(&a_deaths.last().map(|x| x.frame))
+                    } else {
+                        Ordering::Equal
+                    }
+                )
+            }
+            Goal::KillDeathScore => {
+                // highest score wins
+                // tie breaker: least deaths wins
+                // players in the same pool share their score and deaths
+                let deaths: Vec<i64> = self.players.iter().map(|x| x.result.deaths.len() as i64).collect();
+                let pool_sum = |values: &[i64], pool: usize| -> i64 {
+                    values.iter().zip(pools.iter()).filter(|x| *x.1 == pool).map(|x| *x.0).sum()
+                };
+                pool_sum(scores, pools[b]).cmp(&pool_sum(scores, pools[a])).then(
+                    pool_sum(&deaths, pools[a]).cmp(&pool_sum(&deaths, pools[b]))
+                )
+            }
+        }
+    }
+
+    /// Compares two players by percent, in Stamina highest hp is used instead.
+    fn compare_percent(&self, a: usize, b: usize) -> Ordering {
+        let a_player = &self.players[a];
+        let b_player = &self.players[b];
+        if let Goal::Stamina { .. } = self.goal() {
+            b_player.hp.partial_cmp(&a_player.hp).unwrap_or(Ordering::Equal)
+        } else {
+            a_player.damage.partial_cmp(&b_player.damage).unwrap_or(Ordering::Equal)
+        }
+    }
+
+    /// Orders players from first to last place.
+    /// Remaining ties are broken by percent and then by player index.
+    /// During a sudden death only its players are ordered, everyone else keeps the order from when the tie occurred.
+    fn order(&self, pools: &[usize], scores: &[i64]) -> Vec<usize> {
+        let mut order: Vec<usize> = match self.sudden_deaths.last() {
+            Some(sudden_death) => sudden_death.players.clone(),
+            None               => (0..self.players.len()).collect(),
+        };
+        order.sort_by(|a, b| self.compare_players(*a, *b, pools, scores).then(self.compare_percent(*a, *b)));
+
+        if let Some(sudden_death) = self.sudden_deaths.last() {
+            order.extend(sudden_death.order.iter().filter(|x| !sudden_death.players.contains(x)));
+        }
+        order
+    }
+
+    /// Returns every player in the pools tied for first place that need to play a sudden death.
+    /// Returns an empty vec if first place is decided or the tie-break rule decides it by percent.
+    fn tied_for_first(&self, order: &[usize], pools: &[usize], scores: &[i64]) -> Vec<usize> {
+        let sudden_death = match self.package.rules.tie_break {
+            TieBreak::SuddenDeath { .. } => true,
+            TieBreak::Overtime { .. }    => self.sudden_deaths.len() == 0, // overtime is only played once
+            TieBreak::Percent            => false,
+        };
+        if !sudden_death || order.len() == 0 {
+            return vec!();
+        }
+
+        let first = order[0];
+        let mut tied_pools: Vec<usize> = order.iter()
+            .filter(|x| self.compare_players(first, **x, pools, scores) == Ordering::Equal)
+            .map(|x| pools[*x])
+            .collect();
+        tied_pools.sort();
+        tied_pools.dedup();
+        if tied_pools.len() < 2 {
+            return vec!();
+        }
+
+        (0..self.players.len())
+            .filter(|x| tied_pools.contains(&pools[*x]))
+            .filter(|x| self.sudden_deaths.last().map(|sudden_death| sudden_death.players.contains(x)).unwrap_or(true))
+            .collect()
+    }
+
+
+    /// Returns the results of each player and team, when teams are disabled the team results are empty.
+    /// order contains the players from first to last place.
+    pub fn results(&self, order: &[usize]) -> (Vec<PlayerResult>, Vec<TeamResult>) {
+        let raw_player_results: Vec<RawPlayerResult> = self.players.iter().map(|x| x.result()).collect();
+        let pools = self.pools();
+        let kills = self.kills();
+        let scores = self.scores(&kills);
+
+        // Pools are placed in the order their best player appears, so teammates share their place.
+        // For LastManStanding this works out because the last team standing contains the last player standing.
+        let mut pool_order: Vec<usize> = vec!();
+        for player_i in order {
+            let pool = pools[*player_i];
+            if !pool_order.contains(&pool) {
+                pool_order.push(pool);
+            }
+        }
+        let places: Vec<usize> = pools.iter().map(|pool| pool_order.iter().position(|x| x == pool).unwrap()).collect();
+
+        let team_results: Vec<TeamResult> = if self.package.rules.teams_enabled() {
+            pool_order.iter().enumerate().map(|(place, team)| TeamResult { team: *team, place }).collect()
+        } else {
+            vec!()
+        };
+
+        let mut player_results: Vec<PlayerResult> = vec!();
+        for (i, raw_player_result) in raw_player_results.iter().enumerate() {
+            let lcancel_percent = if raw_player_result.lcancel_attempts == 0 {
+                100.0
+            }
+            else {
+                raw_player_result.lcancel_success as f32 / raw_player_result.lcancel_attempts as f32
+            };
+            player_results.push(PlayerResult {
+                fighter:         raw_player_result.ended_as_fighter.clone().unwrap(),
+                team:            raw_player_result.team,
+                controller:      self.controllers[i],
+                place:           places[i],
+                kills:           kills[i].clone(),
+                deaths:          raw_player_result.deaths.clone(),
+                score:           if let Goal::KillDeathScore = self.package.rules.goal { Some(scores[i]) } else { None },
+                lcancel_percent: lcancel_percent,
+            });
+        }
+        player_results.sort_by_key(|x| x.place);
+
+        (player_results, team_results)
+    }
+}
+
+/// Returns the checksum Simulation::checksum returns for the passed state, used to checksum frames stored in history.
+/// sudden_deaths must only contain the sudden deaths that started on or before the frame.
+pub fn checksum(players: &[Player], stage: &Stage, sudden_deaths: &[SuddenDeath], init_seed: u64, frame: usize) -> u64 {
+    let mut checksum = Checksum::new();
+    let players: Vec<PlayerChecksum> = players.iter().map(PlayerChecksum).collect();
+    let state = (&players, stage, sudden_deaths, init_seed, frame);
+    bincode::serialize_into(&mut checksum, &state).unwrap();
+    checksum.hash
+}
+
+/// 64 bit FNV-1a, stable across platforms and builds unlike std::collections::hash_map::DefaultHasher
+struct Checksum {
+    hash: u64,
+}
+
+impl Checksum {
+    fn new() -> Checksum {
+        Checksum { hash: 0xcbf2_9ce4_8422_2325 }
+    }
+}
+
+impl io::Write for Checksum {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Node)]
+pub enum SimulationState {
+    Running,
+    Finished (Vec<usize>), // players ordered from first to last place
+}
+
+impl Default for SimulationState {
+    fn default() -> SimulationState {
+        SimulationState::Running
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct SimulationSetup {
+    pub init_seed:   u64,
+    pub stage:       String,
+    pub players:     Vec<PlayerSetup>,
+    pub controllers: Vec<usize>, // index of the controller each player is using
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct PlayerSetup {
+    pub fighter:  String,
+    pub team:     usize,
+    pub handicap: Handicap,
+}
+
+/// A tie-break played on the same stage by the players that tied for first place
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct SuddenDeath {
+    pub start_frame:       usize,
+    pub players:           Vec<usize>, // players competing, everyone else is eliminated
+    pub order:             Vec<usize>, // placings when the tie occurred, the competing players are reordered by the result of the sudden death
+    pub time_limit_frames: Option<u64>,
+}
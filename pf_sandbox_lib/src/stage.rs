@@ -1,51 +1,71 @@
+use crate::fighter::HitBox;
 use crate::geometry::Rect;
-use winit_input_helper::WinitInputHelper;
+use crate::stage_analysis::StageAnalysis;
+use crate::surface_grid::SurfaceGrid;
 use crate::json_upgrade::engine_version;
 
 use treeflection::{Node, NodeRunner, NodeToken, ContextVec};
-use winit::event::VirtualKeyCode;
 
+#[NodeActions(
+    NodeAction(function="analyze", return_string),
+)]
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub struct Stage {
-    pub engine_version: u64,
-    pub name:           String,
-    pub surfaces:       ContextVec<Surface>,
-    pub blast:          Rect,
-    pub camera:         Rect,
-    pub spawn_points:   ContextVec<SpawnPoint>,
-    pub respawn_points: ContextVec<SpawnPoint>,
+    pub engine_version:      u64,
+    pub name:                String,
+    pub surfaces:            ContextVec<Surface>,
+    pub blast:               Rect,
+    pub camera:              Rect,
+    pub spawn_points:        ContextVec<SpawnPoint>,
+    pub respawn_points:      ContextVec<SpawnPoint>,
+    pub hazards:             ContextVec<Hazard>,
+    pub hazard_rehit_frames: u64, // after being hit by a hazard, a player cannot be hit by any hazard for this many frames
+    pub legal:               bool, // set by the analyze command, true if the stage passed every competitive check
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub surface_grid:        SurfaceGrid, // rebuild it after modifying surfaces, queries only fall back to every surface when surfaces were added or removed
 }
 
 impl Default for Stage {
     fn default() -> Stage {
         let main_platform = Surface {
-            x1:      -75.0,
-            y1:      0.0,
-            grab1:   true,
-            x2:      75.0,
-            y2:      0.0,
-            grab2:   false,
-            wall:    false,
-            ceiling: false,
+            x1:            -75.0,
+            y1:            0.0,
+            grab1:         true,
+            x2:            75.0,
+            y2:            0.0,
+            grab2:         false,
+            wall:          false,
+            ceiling:       false,
             floor: Some(Floor {
                 traction:     1.0,
+                conveyor:     0.0,
                 pass_through: false
-            })
+            }),
+            bounce:        0.0,
+            no_ledge_grab: false,
+            path:          None,
+            hazard:        None,
         };
 
         let second_platform = Surface {
-            x1:      25.0,
-            y1:      50.0,
-            grab1:   false,
-            x2:      75.0,
-            y2:      50.0,
-            grab2:   false,
-            wall:    false,
-            ceiling: false,
+            x1:            25.0,
+            y1:            50.0,
+            grab1:         false,
+            x2:            75.0,
+            y2:            50.0,
+            grab2:         false,
+            wall:          false,
+            ceiling:       false,
             floor: Some(Floor {
                 traction:     1.0,
+                conveyor:     0.0,
                 pass_through: true,
-            })
+            }),
+            bounce:        0.0,
+            no_ledge_grab: false,
+            path:          None,
+            hazard:        None,
         };
 
         let blast = Rect {
@@ -108,35 +128,71 @@ impl Default for Stage {
             },
         ));
 
-        Stage {
-            engine_version: engine_version(),
-            name:           "Base Stage".to_string(),
-            surfaces:       ContextVec::from_vec(vec!(main_platform, second_platform)),
-            blast:          blast,
-            camera:         camera,
-            spawn_points:   spawn_points,
-            respawn_points: respawn_points,
-        }
+        let mut stage = Stage {
+            engine_version:      engine_version(),
+            name:                "Base Stage".to_string(),
+            surfaces:            ContextVec::from_vec(vec!(main_platform, second_platform)),
+            blast:               blast,
+            camera:              camera,
+            spawn_points:        spawn_points,
+            respawn_points:      respawn_points,
+            hazards:             ContextVec::new(),
+            hazard_rehit_frames: 30,
+            legal:               false,
+            surface_grid:        SurfaceGrid::default(),
+        };
+        stage.rebuild_surface_grid();
+        stage
     }
 }
 
 impl Stage {
+    /// Moves every surface that has a path to where its path places it on the passed frame.
+    /// Only depends on the frame, so stepping is deterministic and can be resumed from any point in the stage history.
+    pub fn step_surface_paths(&mut self, frame: u64) {
+        for surface in self.surfaces.iter_mut() {
+            surface.step_path(frame);
+        }
+    }
+
+    pub fn rebuild_surface_grid(&mut self) {
+        self.surface_grid = SurfaceGrid::new(&self.surfaces);
+    }
+
+    /// Returns the indexes of all surfaces that might overlap the rect, in ascending order.
+    /// Every surface is returned if surfaces were added or removed since the grid was rebuilt.
+    pub fn surfaces_near(&self, rect: &Rect) -> Vec<usize> {
+        if self.surface_grid.is_stale(&self.surfaces) {
+            (0..self.surfaces.len()).collect()
+        } else {
+            self.surface_grid.query(rect)
+        }
+    }
+
+    /// Checks if the stage is suitable for competitive play, stores the result in legal and returns a report
+    pub fn analyze(&mut self) -> String {
+        let analysis = StageAnalysis::new(self);
+        self.legal = analysis.legal();
+        analysis.report()
+    }
+
     /// return indexes to the floors connected to the passed floor
+    /// Ledges within LEDGE_CONNECT_DISTANCE of each other are considered connected.
     pub fn connected_floors(&self, platform_i: usize) -> FloorInfo {
         let mut left_i = None;
         let mut right_i = None;
         if let Some(plat) = self.surfaces.get(platform_i) {
-            let (l_x, l_y) = plat.left_ledge();
-            let (r_x, r_y) = plat.right_ledge();
-            for (check_i, check_plat) in self.surfaces.iter().enumerate() {
+            let l = plat.left_ledge();
+            let r = plat.right_ledge();
+            let d = LEDGE_CONNECT_DISTANCE;
+            let rect = Rect { x1: l.0.min(r.0) - d, y1: l.1.min(r.1) - d, x2: l.0.max(r.0) + d, y2: l.1.max(r.1) + d };
+            for check_i in self.surfaces_near(&rect) {
+                let check_plat = &self.surfaces[check_i];
                 if platform_i != check_i && check_plat.floor.is_some() {
-                    let (check_l_x, check_l_y) = check_plat.left_ledge();
-                    let (check_r_x, check_r_y) = check_plat.right_ledge();
-
-                    if l_x == check_r_x && l_y == check_r_y {
+                    if left_i.is_none() && points_connect(l, check_plat.right_ledge()) {
                         left_i = Some(check_i);
                     }
-                    if r_x == check_l_x && r_y == check_l_y {
+                    if right_i.is_none() && points_connect(r, check_plat.left_ledge()) {
                         right_i = Some(check_i);
                     }
                 }
@@ -150,6 +206,12 @@ impl Stage {
     }
 }
 
+const LEDGE_CONNECT_DISTANCE: f32 = 0.01;
+
+fn points_connect(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() <= LEDGE_CONNECT_DISTANCE && (a.1 - b.1).abs() <= LEDGE_CONNECT_DISTANCE
+}
+
 pub struct FloorInfo {
     pub left_i:  Option<usize>,
     pub right_i: Option<usize>,
@@ -157,32 +219,129 @@ pub struct FloorInfo {
 
 #[derive(Clone, Default, Serialize, Deserialize, Node)]
 pub struct Surface {
-    pub x1:      f32,
-    pub y1:      f32,
-    pub grab1:   bool,
-    pub x2:      f32,
-    pub y2:      f32,
-    pub grab2:   bool,
-    pub wall:    bool,
-    pub ceiling: bool,
-    pub floor:   Option<Floor>,
+    pub x1:            f32,
+    pub y1:            f32,
+    pub grab1:         bool,
+    pub x2:            f32,
+    pub y2:            f32,
+    pub grab2:         bool,
+    pub wall:          bool,
+    pub ceiling:       bool,
+    pub floor:         Option<Floor>,
+    pub bounce:        f32, // fraction of velocity kept when bouncing off the wall or ceiling, 0.0 stops the player against the surface
+    pub no_ledge_grab: bool, // ledges cannot be grabbed regardless of grab1 and grab2
+    pub path:          Option<SurfacePath>, // animates the surface during a match
+    pub hazard:        Option<HitBox>, // hits players touching the surface
 }
 
-// TODO: coloring
-// r = if floor and pass_through { 0.5 } else if floor { 0.25 } else { 0.0 }
-// g = if ceiling { 0.5 } else { 0.0 }
-// b = if wall { 0.5 } else { 0.0 }
-
 #[derive(Clone, Serialize, Deserialize, Node)]
 pub struct Floor {
-    pub traction:     f32,
+    pub traction:     f32, // multiplies friction and dash/run acceleration, values below 1.0 are slippery
+    pub conveyor:     f32, // x velocity applied to grounded players every frame
     pub pass_through: bool,
 }
 
+/// Keyframed movement of a surface.
+/// Keyframe positions are offsets from where the surface was placed in the stage editor.
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct SurfacePath {
+    pub keyframes: Vec<PathKeyframe>, // must be in order of frame
+    pub repeat:    PathRepeat,
+    pub offset_x:  f32, // offset currently applied to the surface
+    pub offset_y:  f32,
+    pub vel_x:     f32, // distance the surface moved on the last frame
+    pub vel_y:     f32,
+    pub base:      Option<PathBase>, // stored on the first step, None until then
+}
+
+/// The position of a surface before its path is applied
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct PathBase {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct PathKeyframe {
+    pub frame: u64,
+    pub x:     f32,
+    pub y:     f32,
+    pub ease:  PathEase, // how the surface moves from the previous keyframe to this one
+}
+
+#[derive(Clone, Serialize, Deserialize, Node)]
+pub enum PathEase {
+    Linear,
+    EaseInOut,
+}
+
+impl Default for PathEase {
+    fn default() -> PathEase {
+        PathEase::Linear
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Node)]
+pub enum PathRepeat {
+    Loop,     // jumps back to the first keyframe after the last keyframe
+    PingPong, // travels back through the keyframes in reverse after the last keyframe
+}
+
+impl Default for PathRepeat {
+    fn default() -> PathRepeat {
+        PathRepeat::Loop
+    }
+}
+
+impl SurfacePath {
+    /// Returns the offset from the surfaces editor position on the passed frame
+    pub fn offset_at(&self, frame: u64) -> (f32, f32) {
+        let (first, last) = match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return (0.0, 0.0)
+        };
+
+        let duration = last.frame;
+        let frame = if duration == 0 {
+            0
+        } else {
+            match self.repeat {
+                PathRepeat::Loop => frame % duration,
+                PathRepeat::PingPong => {
+                    let frame = frame % (duration * 2);
+                    if frame > duration { duration * 2 - frame } else { frame }
+                }
+            }
+        };
+
+        if frame <= first.frame {
+            return (first.x, first.y);
+        }
+        for pair in self.keyframes.windows(2) {
+            let (prev, next) = (&pair[0], &pair[1]);
+            if frame <= next.frame {
+                let progress = (frame - prev.frame) as f32 / (next.frame - prev.frame) as f32;
+                let progress = match next.ease {
+                    PathEase::Linear    => progress,
+                    PathEase::EaseInOut => progress * progress * (3.0 - 2.0 * progress),
+                };
+                return (
+                    prev.x + (next.x - prev.x) * progress,
+                    prev.y + (next.y - prev.y) * progress,
+                );
+            }
+        }
+        (last.x, last.y)
+    }
+}
+
 impl Default for Floor {
     fn default() -> Floor {
         Floor {
             traction:     1.0,
+            conveyor:     0.0,
             pass_through: true,
         }
     }
@@ -195,6 +354,7 @@ impl Surface {
         let floor = if floor {
             Some(Floor {
                 traction:     1.0,
+                conveyor:     0.0,
                 pass_through: true
             })
         } else {
@@ -209,8 +369,56 @@ impl Surface {
             wall,
             ceiling,
             floor,
-            grab1: false,
-            grab2: false,
+            grab1:         false,
+            grab2:         false,
+            bounce:        0.0,
+            no_ledge_grab: false,
+            path:          None,
+            hazard:        None,
+        }
+    }
+
+    /// Moves the surface to where its path places it on the passed frame.
+    /// The position is set from the base position rather than accumulated, so it does not drift over a long match.
+    pub fn step_path(&mut self, frame: u64) {
+        if let Some(ref mut path) = self.path {
+            if path.base.is_none() {
+                path.base = Some(PathBase {
+                    x1: self.x1 - path.offset_x,
+                    y1: self.y1 - path.offset_y,
+                    x2: self.x2 - path.offset_x,
+                    y2: self.y2 - path.offset_y,
+                });
+            }
+
+            let (x, y) = path.offset_at(frame);
+            path.vel_x = x - path.offset_x;
+            path.vel_y = y - path.offset_y;
+            path.offset_x = x;
+            path.offset_y = y;
+
+            let base = path.base.as_ref().unwrap();
+            self.x1 = base.x1 + x;
+            self.y1 = base.y1 + y;
+            self.x2 = base.x2 + x;
+            self.y2 = base.y2 + y;
+        }
+    }
+
+    /// Returns the distance the surface moved on the last frame
+    pub fn velocity(&self) -> (f32, f32) {
+        if let Some(ref path) = self.path {
+            (path.vel_x, path.vel_y)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    pub fn conveyor(&self) -> f32 {
+        if let &Some(ref floor) = &self.floor {
+            floor.conveyor
+        } else {
+            0.0
         }
     }
 
@@ -308,7 +516,9 @@ impl Surface {
     }
 
     pub fn left_grab(&self) -> bool {
-        if self.x1 < self.x2 {
+        if self.no_ledge_grab {
+            false
+        } else if self.x1 < self.x2 {
             self.grab1
         } else {
             self.grab2
@@ -324,7 +534,9 @@ impl Surface {
     }
 
     pub fn right_grab(&self) -> bool {
-        if self.x1 > self.x2 {
+        if self.no_ledge_grab {
+            false
+        } else if self.x1 > self.x2 {
             self.grab1
         } else {
             self.grab2
@@ -357,38 +569,57 @@ impl SpawnPoint {
     }
 }
 
+/// A free-floating hitbox owned by the stage
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct Hazard {
+    pub x:        f32,
+    pub y:        f32,
+    pub radius:   f32,
+    pub hitbox:   HitBox,
+    pub schedule: Option<HazardSchedule>, // None means the hazard is always active
+}
+
+impl Hazard {
+    pub fn new(x: f32, y: f32) -> Hazard {
+        Hazard {
+            x,
+            y,
+            radius:   5.0,
+            hitbox:   HitBox::default(),
+            schedule: None,
+        }
+    }
+
+    pub fn is_active(&self, frame: u64) -> bool {
+        if let Some(ref schedule) = self.schedule {
+            schedule.is_active(frame)
+        } else {
+            true
+        }
+    }
+}
+
+/// Repeating window of frames in which a hazard is active
+#[derive(Clone, Default, Serialize, Deserialize, Node)]
+pub struct HazardSchedule {
+    pub period: u64, // length of one cycle in frames
+    pub start:  u64, // frame within the cycle the hazard activates
+    pub end:    u64, // frame within the cycle the hazard deactivates
+}
+
+impl HazardSchedule {
+    pub fn is_active(&self, frame: u64) -> bool {
+        let frame = if self.period == 0 { frame } else { frame % self.period };
+        self.start <= frame && frame < self.end
+    }
+}
+
 #[derive(Clone, Default, Serialize, Deserialize, Node)]
 pub struct DebugStage {
     pub blast:          bool,
     pub camera:         bool,
     pub spawn_points:   bool,
     pub respawn_points: bool,
+    pub hazards:        bool,
 }
 
-impl DebugStage {
-    pub fn step(&mut self, os_input: &WinitInputHelper<()>) {
-        if os_input.key_pressed(VirtualKeyCode::F1) {
-            self.blast = !self.blast;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F2) {
-            self.camera = !self.camera;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F3) {
-            self.spawn_points = !self.spawn_points;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F4) {
-            self.respawn_points = !self.respawn_points;
-        }
-        if os_input.key_pressed(VirtualKeyCode::F11) {
-            *self = DebugStage {
-                blast:          true,
-                camera:         true,
-                spawn_points:   true,
-                respawn_points: true,
-            }
-        }
-        if os_input.key_pressed(VirtualKeyCode::F12) {
-            *self = DebugStage::default();
-        }
-    }
-}
@@ -0,0 +1,235 @@
+use crate::stage::{Stage, Surface, SpawnPoint};
+
+const TOLERANCE: f32 = 0.1; // distance within which points are considered equal
+
+/// Checks whether a stage is suitable for competitive play and measures its layout.
+/// Moving surfaces are analyzed at their current position.
+pub struct StageAnalysis {
+    pub asymmetric_surfaces: Vec<usize>, // surfaces without a mirrored counterpart across x = 0
+    pub asymmetric_spawns:   Vec<usize>,
+    pub asymmetric_respawns: Vec<usize>,
+    pub invalid_spawns:      Vec<InvalidSpawn>,
+    pub camera_in_blast:     bool,
+    pub ungrabbable_ledges:  Vec<Ledge>, // ledges of solid floors that are not connected to another floor and cannot be grabbed
+    pub platform_heights:    Vec<PlatformHeight>,
+    pub ledge_distances:     Vec<LedgeDistance>,
+}
+
+pub struct InvalidSpawn {
+    pub respawn:       bool, // index refers to respawn_points instead of spawn_points
+    pub index:         usize,
+    pub outside_blast: bool,
+    pub no_floor:      bool,
+}
+
+pub struct Ledge {
+    pub surface_i: usize,
+    pub left:      bool,
+}
+
+pub struct PlatformHeight {
+    pub surface_i: usize,
+    pub height:    f32, // distance above the floor below the middle of the platform
+}
+
+pub struct LedgeDistance {
+    pub ledge:  Ledge,
+    pub side:   f32, // horizontal distance to the blast zone on the ledges side
+    pub bottom: f32, // vertical distance to the bottom blast zone
+}
+
+impl StageAnalysis {
+    pub fn new(stage: &Stage) -> StageAnalysis {
+        let surfaces = &stage.surfaces;
+        let blast = &stage.blast;
+        let camera = &stage.camera;
+
+        let asymmetric_surfaces: Vec<usize> = (0..surfaces.len())
+            .filter(|i| !surfaces.iter().any(|check| surfaces_mirror(&surfaces[*i], check)))
+            .collect();
+
+        let mut invalid_spawns = vec!();
+        for (respawn, points) in [(false, &stage.spawn_points), (true, &stage.respawn_points)].iter() {
+            for (index, point) in points.iter().enumerate() {
+                let outside_blast = !blast.contains_point(point.x, point.y);
+                let no_floor = floor_below(surfaces, point.x, point.y).is_none();
+                if outside_blast || no_floor {
+                    invalid_spawns.push(InvalidSpawn { respawn: *respawn, index, outside_blast, no_floor });
+                }
+            }
+        }
+
+        let camera_in_blast = camera.left() >= blast.left() && camera.right() <= blast.right() &&
+                              camera.bot()  >= blast.bot()  && camera.top()   <= blast.top();
+
+        let mut ungrabbable_ledges = vec!();
+        let mut ledge_distances = vec!();
+        let mut platform_heights = vec!();
+        for (surface_i, surface) in surfaces.iter().enumerate() {
+            if surface.floor.is_none() {
+                continue;
+            }
+
+            let connected = stage.connected_floors(surface_i);
+            if !surface.is_pass_through() {
+                if connected.left_i.is_none() && !surface.left_grab() {
+                    ungrabbable_ledges.push(Ledge { surface_i, left: true });
+                }
+                if connected.right_i.is_none() && !surface.right_grab() {
+                    ungrabbable_ledges.push(Ledge { surface_i, left: false });
+                }
+            }
+
+            if surface.left_grab() {
+                let (x, y) = surface.left_ledge();
+                ledge_distances.push(LedgeDistance {
+                    ledge:  Ledge { surface_i, left: true },
+                    side:   x - blast.left(),
+                    bottom: y - blast.bot(),
+                });
+            }
+            if surface.right_grab() {
+                let (x, y) = surface.right_ledge();
+                ledge_distances.push(LedgeDistance {
+                    ledge:  Ledge { surface_i, left: false },
+                    side:   blast.right() - x,
+                    bottom: y - blast.bot(),
+                });
+            }
+
+            if surface.is_pass_through() {
+                let x = (surface.x1 + surface.x2) / 2.0;
+                let y = surface.world_x_to_world_y(x);
+                let below = surfaces.iter().enumerate()
+                    .filter(|(check_i, check)| *check_i != surface_i && check.floor.is_some() && check.world_x_in_bounds(x))
+                    .map(|(_, check)| check.world_x_to_world_y(x))
+                    .filter(|check_y| *check_y < y)
+                    .fold(None, |highest: Option<f32>, check_y| Some(highest.map_or(check_y, |h| h.max(check_y))));
+                if let Some(below) = below {
+                    platform_heights.push(PlatformHeight { surface_i, height: y - below });
+                }
+            }
+        }
+
+        StageAnalysis {
+            asymmetric_surfaces,
+            asymmetric_spawns:   asymmetric_points(&stage.spawn_points),
+            asymmetric_respawns: asymmetric_points(&stage.respawn_points),
+            invalid_spawns,
+            camera_in_blast,
+            ungrabbable_ledges,
+            platform_heights,
+            ledge_distances,
+        }
+    }
+
+    pub fn symmetric(&self) -> bool {
+        self.asymmetric_surfaces.is_empty() && self.asymmetric_spawns.is_empty() && self.asymmetric_respawns.is_empty()
+    }
+
+    /// Returns true if the stage passes every check
+    pub fn legal(&self) -> bool {
+        self.symmetric() && self.invalid_spawns.is_empty() && self.camera_in_blast && self.ungrabbable_ledges.is_empty()
+    }
+
+    pub fn report(&self) -> String {
+        let mut lines = vec!();
+        lines.push(format!("Legal: {}", self.legal()));
+
+        if self.symmetric() {
+            lines.push(String::from("Symmetric: true"));
+        } else {
+            lines.push(String::from("Symmetric: false"));
+            for i in &self.asymmetric_surfaces {
+                lines.push(format!("    surface {} has no mirrored surface", i));
+            }
+            for i in &self.asymmetric_spawns {
+                lines.push(format!("    spawn point {} has no mirrored spawn point", i));
+            }
+            for i in &self.asymmetric_respawns {
+                lines.push(format!("    respawn point {} has no mirrored respawn point", i));
+            }
+        }
+
+        lines.push(format!("Valid spawns: {}", self.invalid_spawns.is_empty()));
+        for spawn in &self.invalid_spawns {
+            let name = if spawn.respawn { "respawn point" } else { "spawn point" };
+            if spawn.outside_blast {
+                lines.push(format!("    {} {} is outside the blast zone", name, spawn.index));
+            }
+            if spawn.no_floor {
+                lines.push(format!("    {} {} is not above a floor", name, spawn.index));
+            }
+        }
+
+        lines.push(format!("Camera inside blast zone: {}", self.camera_in_blast));
+
+        lines.push(format!("Grabbable ledges: {}", self.ungrabbable_ledges.is_empty()));
+        for ledge in &self.ungrabbable_ledges {
+            lines.push(format!("    {} ledge of surface {} cannot be grabbed", ledge.side_name(), ledge.surface_i));
+        }
+
+        lines.push(String::from("Platform heights:"));
+        for platform in &self.platform_heights {
+            lines.push(format!("    surface {}: {:.2}", platform.surface_i, platform.height));
+        }
+
+        lines.push(String::from("Ledge to blast zone distances:"));
+        for distance in &self.ledge_distances {
+            lines.push(format!("    {} ledge of surface {}: side {:.2}, bottom {:.2}", distance.ledge.side_name(), distance.ledge.surface_i, distance.side, distance.bottom));
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl Ledge {
+    fn side_name(&self) -> &'static str {
+        if self.left { "left" } else { "right" }
+    }
+}
+
+fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() <= TOLERANCE && (a.1 - b.1).abs() <= TOLERANCE
+}
+
+/// Returns true if b is a mirror image of a across x = 0
+fn surfaces_mirror(a: &Surface, b: &Surface) -> bool {
+    let m1 = (-a.x1, a.y1);
+    let m2 = (-a.x2, a.y2);
+    let grabs_match = if close(m1, b.p1()) && close(m2, b.p2()) {
+        a.grab1 == b.grab1 && a.grab2 == b.grab2
+    } else if close(m1, b.p2()) && close(m2, b.p1()) {
+        a.grab1 == b.grab2 && a.grab2 == b.grab1
+    } else {
+        return false;
+    };
+
+    grabs_match &&
+    a.wall          == b.wall &&
+    a.ceiling       == b.ceiling &&
+    a.floor.is_some() == b.floor.is_some() &&
+    a.is_pass_through() == b.is_pass_through() &&
+    a.no_ledge_grab == b.no_ledge_grab
+}
+
+/// Returns the indexes of points without a mirrored point facing the other way.
+/// Points on x = 0 mirror themselves.
+fn asymmetric_points(points: &[SpawnPoint]) -> Vec<usize> {
+    points.iter().enumerate()
+        .filter(|(_, a)| {
+            a.x.abs() > TOLERANCE &&
+            !points.iter().any(|b| close((-a.x, a.y), (b.x, b.y)) && a.face_right != b.face_right)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Returns the index of a floor directly below or touching the passed point
+fn floor_below(surfaces: &[Surface], x: f32, y: f32) -> Option<usize> {
+    surfaces.iter().position(|surface|
+        surface.floor.is_some() &&
+        surface.world_x_in_bounds(x) &&
+        surface.world_x_to_world_y(x) <= y + TOLERANCE
+    )
+}
@@ -0,0 +1,120 @@
+use crate::rules::Rules;
+
+/// A game played during a set, players are referred to by the pool they are in
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetGame {
+    pub stage:  String,
+    pub winner: usize,
+    pub loser:  usize,
+}
+
+/// Stage striking for the first game of a set, bans and counterpicks for the following games.
+#[derive(Clone)]
+pub struct StageStrike {
+    pub stages: Vec<StrikeStage>,
+    pub bans:   usize, // number of stages the previous winner strikes before the previous loser picks
+}
+
+#[derive(Clone)]
+pub struct StrikeStage {
+    pub key:    String,
+    pub struck: bool,
+    pub dsr:    bool, // cannot be picked or struck because of daves stupid rule
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StrikeTurn {
+    Strike { pool: usize },
+    Pick   { pool: usize },
+    Done   { stage: String },
+}
+
+impl StageStrike {
+    /// games are the games already played in the set, stage_keys are the stages in the package
+    pub fn new(games: &[SetGame], rules: &Rules, stage_keys: Vec<String>) -> StageStrike {
+        let mut candidates: Vec<&String> = rules.starter_stages.iter().collect();
+        if games.len() > 0 {
+            candidates.extend(rules.counterpick_stages.iter());
+        }
+
+        let mut keys: Vec<String> = vec!();
+        for key in candidates {
+            if stage_keys.contains(key) && !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+        if keys.len() == 0 {
+            keys = stage_keys;
+        }
+
+        let mut stages: Vec<StrikeStage> = keys.into_iter().map(|key| {
+            let dsr = rules.daves_stupid_rule && games.last().map(|last| {
+                games.iter().any(|x| x.winner == last.loser && x.stage == key)
+            }).unwrap_or(false);
+            StrikeStage { key, struck: false, dsr }
+        }).collect();
+
+        // If the loser has won on every stage, daves stupid rule would leave nothing to pick.
+        if stages.iter().all(|x| x.dsr) {
+            for stage in &mut stages {
+                stage.dsr = false;
+            }
+        }
+
+        let pickable = stages.iter().filter(|x| !x.dsr).count();
+        let bans = (rules.counterpick_bans as usize).min(pickable.saturating_sub(1));
+        StageStrike { stages, bans }
+    }
+
+    /// pools contains every pool in the set in ascending order
+    pub fn turn(&self, games: &[SetGame], pools: &[usize]) -> StrikeTurn {
+        let strikes = self.stages.iter().filter(|x| x.struck).count();
+        if let Some(last) = games.last() {
+            if strikes < self.bans {
+                StrikeTurn::Strike { pool: last.winner }
+            } else {
+                StrikeTurn::Pick { pool: last.loser }
+            }
+        } else {
+            // The first pool strikes one stage, then each pool takes turns striking two stages until one stage remains.
+            let remaining: Vec<&StrikeStage> = self.stages.iter().filter(|x| !x.struck).collect();
+            if remaining.len() <= 1 {
+                StrikeTurn::Done { stage: remaining.get(0).map(|x| x.key.clone()).unwrap_or_default() }
+            } else {
+                let turn = if strikes == 0 { 0 } else { 1 + (strikes - 1) / 2 };
+                StrikeTurn::Strike { pool: pools.get(turn % pools.len().max(1)).cloned().unwrap_or(0) }
+            }
+        }
+    }
+
+    /// Strike or pick the stage at stage_i depending on whose turn it is.
+    /// Stages that are already struck or ruled out by daves stupid rule cannot be selected.
+    /// Returns the stage to play once striking is complete.
+    pub fn select(&mut self, games: &[SetGame], pools: &[usize], stage_i: usize) -> Option<String> {
+        match self.turn(games, pools) {
+            StrikeTurn::Strike { .. } => {
+                if let Some(stage) = self.stages.get_mut(stage_i) {
+                    if stage.struck || stage.dsr {
+                        return None;
+                    }
+                    stage.struck = true;
+                }
+                if let StrikeTurn::Done { stage } = self.turn(games, pools) {
+                    return Some(stage);
+                }
+                None
+            }
+            StrikeTurn::Pick { .. } => {
+                self.stages.get(stage_i).filter(|x| x.selectable()).map(|x| x.key.clone())
+            }
+            StrikeTurn::Done { stage } => Some(stage),
+        }
+    }
+}
+
+impl StrikeStage {
+    /// Returns true if the stage can still be struck or picked
+    pub fn selectable(&self) -> bool {
+        !self.struck && !self.dsr
+    }
+}
@@ -0,0 +1,513 @@
+//! Converts between stages and SVG drawings so stages can be drawn in a vector graphics editor.
+//!
+//! Conventions used by the importer and exporter:
+//! *   `<path>`, `<line>`, `<polyline>` and `<polygon>` elements become surfaces, one per segment.
+//!     Curves are replaced by a straight segment to their end point.
+//! *   The surface type is chosen by the class tokens `floor`, `pass-through`, `wall`, `ceiling` and `untyped`.
+//!     Without a class the stroke colour is used: black is a floor, green is a pass-through floor, blue is a wall, red is a ceiling
+//!     and magenta is a surface without a type.
+//!     Elements without either are decoration and are skipped.
+//! *   The class tokens `grab1` and `grab2` make the first and last point of the element grabbable, `grab` does both.
+//!     `no-ledge-grab` sets no_ledge_grab.
+//! *   The attributes `data-traction`, `data-conveyor` and `data-bounce` set the matching surface properties.
+//! *   `<rect id="blast">` and `<rect id="camera">` become the blast zone and camera.
+//! *   `<circle class="spawn">` and `<circle class="respawn">` become spawn points, facing left if the class contains `face-left`.
+//! *   `<title>` becomes the stage name.
+//!
+//! SVG y coordinates point down, so they are negated. Transforms are not supported.
+//! Surface paths, surface hazards and free-floating hazards are not exported.
+
+use crate::geometry::Rect;
+use crate::stage::{Stage, Surface, Floor, SpawnPoint};
+
+use treeflection::ContextVec;
+
+const SPAWN_RADIUS: f32 = 2.0;
+
+pub fn import(svg: &str) -> Result<Stage, String> {
+    let mut stage = Stage::default();
+    let mut surfaces = vec!();
+    let mut spawn_points = vec!();
+    let mut respawn_points = vec!();
+    let mut blast = None;
+    let mut camera = None;
+
+    for element in parse_elements(svg)? {
+        let classes: Vec<&str> = element.attribute("class").map(|x| x.split_whitespace().collect()).unwrap_or_default();
+        match element.name.as_ref() {
+            "title" => {
+                stage.name = element.text.trim().to_string();
+            }
+            "rect" => {
+                let rect = element.rect()?;
+                match element.attribute("id") {
+                    Some("blast")  => blast = Some(rect),
+                    Some("camera") => camera = Some(rect),
+                    _ => { }
+                }
+            }
+            "circle" => {
+                let point = SpawnPoint {
+                    x:          element.number("cx")?,
+                    y:          -element.number("cy")?,
+                    face_right: !classes.contains(&"face-left"),
+                };
+                if classes.contains(&"spawn") {
+                    spawn_points.push(point);
+                } else if classes.contains(&"respawn") {
+                    respawn_points.push(point);
+                }
+            }
+            "path" | "line" | "polyline" | "polygon" => {
+                if let Some(template) = element.surface_template(&classes)? {
+                    for points in element.polylines()? {
+                        add_surfaces(&mut surfaces, &template, &classes, &points);
+                    }
+                }
+            }
+            _ => { }
+        }
+    }
+
+    stage.surfaces = ContextVec::from_vec(surfaces);
+    stage.spawn_points = ContextVec::from_vec(spawn_points);
+    stage.respawn_points = ContextVec::from_vec(respawn_points);
+    stage.blast = blast.ok_or_else(|| String::from("SVG has no rect with id=\"blast\""))?;
+    stage.camera = camera.ok_or_else(|| String::from("SVG has no rect with id=\"camera\""))?;
+    stage.rebuild_surface_grid();
+    Ok(stage)
+}
+
+pub fn export(stage: &Stage) -> String {
+    let blast = &stage.blast;
+    let mut lines = vec!();
+    lines.push(format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        blast.left(), -blast.top(), blast.right() - blast.left(), blast.top() - blast.bot()
+    ));
+    lines.push(format!("  <title>{}</title>", escape(&stage.name)));
+    lines.push(export_rect("blast", &stage.blast));
+    lines.push(export_rect("camera", &stage.camera));
+
+    for surface in stage.surfaces.iter() {
+        let mut classes = vec!();
+        let mut data = String::new();
+        if let Some(ref floor) = surface.floor {
+            classes.push(if floor.pass_through { "pass-through" } else { "floor" });
+            data.push_str(&format!(r#" data-traction="{}" data-conveyor="{}""#, floor.traction, floor.conveyor));
+        }
+        if surface.wall    { classes.push("wall") }
+        if surface.ceiling { classes.push("ceiling") }
+        if classes.is_empty() { classes.push("untyped") }
+        if surface.grab1   { classes.push("grab1") }
+        if surface.grab2   { classes.push("grab2") }
+        if surface.no_ledge_grab { classes.push("no-ledge-grab") }
+        if surface.bounce != 0.0 {
+            data.push_str(&format!(r#" data-bounce="{}""#, surface.bounce));
+        }
+
+        lines.push(format!(
+            r#"  <line class="{}" x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}"{}/>"#,
+            classes.join(" "), surface.x1, -surface.y1, surface.x2, -surface.y2, stroke(surface), data
+        ));
+    }
+
+    for (class, points) in [("spawn", &stage.spawn_points), ("respawn", &stage.respawn_points)].iter() {
+        for point in points.iter() {
+            let face = if point.face_right { "" } else { " face-left" };
+            lines.push(format!(r#"  <circle class="{}{}" cx="{}" cy="{}" r="{}"/>"#, class, face, point.x, -point.y, SPAWN_RADIUS));
+        }
+    }
+
+    lines.push(String::from("</svg>"));
+    lines.join("\n")
+}
+
+fn export_rect(id: &str, rect: &Rect) -> String {
+    format!(
+        r#"  <rect id="{}" x="{}" y="{}" width="{}" height="{}" fill="none" stroke="grey"/>"#,
+        id, rect.left(), -rect.top(), rect.right() - rect.left(), rect.top() - rect.bot()
+    )
+}
+
+fn stroke(surface: &Surface) -> &'static str {
+    if surface.is_pass_through() {
+        "#00ff00"
+    } else if surface.floor.is_some() {
+        "#000000"
+    } else if surface.wall {
+        "#0000ff"
+    } else if surface.ceiling {
+        "#ff0000"
+    } else {
+        "#ff00ff"
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Creates a surface for every segment of the polyline, taking everything but the position from the template
+fn add_surfaces(surfaces: &mut Vec<Surface>, template: &Surface, classes: &[&str], points: &[(f32, f32)]) {
+    let grab_start = classes.contains(&"grab") || classes.contains(&"grab1");
+    let grab_end   = classes.contains(&"grab") || classes.contains(&"grab2");
+    let segments: Vec<_> = points.windows(2).filter(|x| x[0] != x[1]).collect();
+    for (i, segment) in segments.iter().enumerate() {
+        surfaces.push(Surface {
+            x1:    segment[0].0,
+            y1:    -segment[0].1,
+            x2:    segment[1].0,
+            y2:    -segment[1].1,
+            grab1: grab_start && i == 0,
+            grab2: grab_end && i == segments.len() - 1,
+            .. template.clone()
+        });
+    }
+}
+
+struct Element {
+    name:       String,
+    attributes: Vec<(String, String)>,
+    text:       String, // text up to the next tag
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|x| x.0 == name).map(|x| x.1.as_ref())
+    }
+
+    fn number(&self, name: &str) -> Result<f32, String> {
+        let value = self.attribute(name).ok_or_else(|| format!("<{}> is missing attribute {}", self.name, name))?;
+        parse_number(value).ok_or_else(|| format!("<{}> has invalid number in attribute {}: {}", self.name, name, value))
+    }
+
+    fn optional_number(&self, name: &str) -> Result<Option<f32>, String> {
+        match self.attribute(name) {
+            Some(_) => self.number(name).map(Some),
+            None    => Ok(None),
+        }
+    }
+
+    /// The stroke colour from either the stroke attribute or the style attribute
+    fn stroke(&self) -> Option<String> {
+        if let Some(stroke) = self.attribute("stroke") {
+            return Some(stroke.trim().to_lowercase());
+        }
+        let style = self.attribute("style")?;
+        style.split(';')
+            .filter_map(|x| {
+                let mut split = x.splitn(2, ':');
+                match (split.next(), split.next()) {
+                    (Some(key), Some(value)) if key.trim() == "stroke" => Some(value.trim().to_lowercase()),
+                    _ => None
+                }
+            })
+            .next()
+    }
+
+    /// Returns a surface with the type and properties of this element, None if this element is not a surface
+    fn surface_template(&self, classes: &[&str]) -> Result<Option<Surface>, String> {
+        let mut floor        = classes.contains(&"floor");
+        let mut pass_through = classes.contains(&"pass-through");
+        let mut wall         = classes.contains(&"wall");
+        let mut ceiling      = classes.contains(&"ceiling");
+        let untyped          = classes.contains(&"untyped");
+
+        if !floor && !pass_through && !wall && !ceiling && !untyped {
+            match self.stroke().as_ref().map(|x| x.as_ref()) {
+                Some("#000000") | Some("#000") | Some("black")   => floor = true,
+                Some("#00ff00") | Some("#0f0") | Some("lime")    => pass_through = true,
+                Some("#0000ff") | Some("#00f") | Some("blue")    => wall = true,
+                Some("#ff0000") | Some("#f00") | Some("red")     => ceiling = true,
+                Some("#ff00ff") | Some("#f0f") | Some("magenta") => { }
+                _ => return Ok(None)
+            }
+        }
+
+        let floor = if floor || pass_through {
+            Some(Floor {
+                traction:     self.optional_number("data-traction")?.unwrap_or(1.0),
+                conveyor:     self.optional_number("data-conveyor")?.unwrap_or(0.0),
+                pass_through,
+            })
+        } else {
+            None
+        };
+
+        Ok(Some(Surface {
+            wall,
+            ceiling,
+            floor,
+            bounce:        self.optional_number("data-bounce")?.unwrap_or(0.0),
+            no_ledge_grab: classes.contains(&"no-ledge-grab"),
+            .. Surface::default()
+        }))
+    }
+
+    fn rect(&self) -> Result<Rect, String> {
+        let x = self.optional_number("x")?.unwrap_or(0.0);
+        let y = self.optional_number("y")?.unwrap_or(0.0);
+        let width = self.number("width")?;
+        let height = self.number("height")?;
+        Ok(Rect {
+            x1: x,
+            y1: -y - height,
+            x2: x + width,
+            y2: -y,
+        })
+    }
+
+    /// Returns the points of every polyline drawn by this element in SVG coordinates
+    fn polylines(&self) -> Result<Vec<Vec<(f32, f32)>>, String> {
+        match self.name.as_ref() {
+            "line" => {
+                Ok(vec!(vec!(
+                    (self.number("x1")?, self.number("y1")?),
+                    (self.number("x2")?, self.number("y2")?),
+                )))
+            }
+            "polyline" | "polygon" => {
+                let values = self.attribute("points").unwrap_or("");
+                let numbers = parse_numbers(values).ok_or_else(|| format!("<{}> has invalid points: {}", self.name, values))?;
+                let mut points: Vec<(f32, f32)> = numbers.chunks(2).filter(|x| x.len() == 2).map(|x| (x[0], x[1])).collect();
+                if self.name == "polygon" {
+                    if let Some(first) = points.first().cloned() {
+                        points.push(first);
+                    }
+                }
+                Ok(vec!(points))
+            }
+            _ => parse_path(self.attribute("d").unwrap_or(""))
+        }
+    }
+}
+
+/// Returns every element in the document in order, ignoring nesting
+fn parse_elements(svg: &str) -> Result<Vec<Element>, String> {
+    let mut elements = vec!();
+    let mut rest = svg;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if rest.starts_with("!--") {
+            let end = rest.find("-->").ok_or_else(|| String::from("Unterminated comment"))?;
+            rest = &rest[end + 3..];
+            continue;
+        }
+
+        let end = find_tag_end(rest).ok_or_else(|| String::from("Unterminated tag"))?;
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('/') || tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        elements.push(Element {
+            name:       tag[..name_end].to_string(),
+            attributes: parse_attributes(&tag[name_end..])?,
+            text:       unescape(&rest[..text_end]),
+        });
+    }
+    Ok(elements)
+}
+
+/// Returns the index of the > that ends the tag, skipping over any > inside quoted attribute values
+fn find_tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q             => quote = None,
+            Some(_)                       => { }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>'              => return Some(i),
+            None                          => { }
+        }
+    }
+    None
+}
+
+fn parse_attributes(mut text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut attributes = vec!();
+    loop {
+        text = text.trim_start();
+        if text.is_empty() {
+            return Ok(attributes);
+        }
+
+        let equals = text.find('=').ok_or_else(|| format!("Attribute without value: {}", text))?;
+        let name = text[..equals].trim().to_string();
+        text = text[equals + 1..].trim_start();
+
+        let quote = text.chars().next().filter(|x| *x == '"' || *x == '\'').ok_or_else(|| format!("Unquoted value for attribute {}", name))?;
+        text = &text[1..];
+        let end = text.find(quote).ok_or_else(|| format!("Unterminated value for attribute {}", name))?;
+        attributes.push((name, unescape(&text[..end])));
+        text = &text[end + 1..];
+    }
+}
+
+fn parse_number(text: &str) -> Option<f32> {
+    let numbers = parse_numbers(text)?;
+    if numbers.len() == 1 { Some(numbers[0]) } else { None }
+}
+
+/// Parses a list of numbers separated by whitespace and/or commas, or only by signs e.g. "1-2.5"
+fn parse_numbers(text: &str) -> Option<Vec<f32>> {
+    let mut tokenizer = PathTokenizer { chars: text.chars().collect(), i: 0 };
+    let mut numbers = vec!();
+    while let Some(token) = tokenizer.next() {
+        match token {
+            PathToken::Number (number) => numbers.push(number),
+            PathToken::Command (_)     => return None,
+        }
+    }
+    Some(numbers)
+}
+
+/// Parses the d attribute of a path into polylines, one per subpath
+fn parse_path(d: &str) -> Result<Vec<Vec<(f32, f32)>>, String> {
+    let mut tokenizer = PathTokenizer { chars: d.chars().collect(), i: 0 };
+    let mut polylines: Vec<Vec<(f32, f32)>> = vec!();
+    let mut current = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    let mut command = None;
+    let mut args = vec!();
+
+    loop {
+        let token = tokenizer.next();
+        if let Some(PathToken::Number (number)) = token {
+            args.push(number);
+        }
+
+        // Run the pending command whenever enough arguments have been collected, this also handles implicitly repeated commands.
+        if let Some(c) = command {
+            let count = argument_count(c);
+            if args.len() == count {
+                let relative = c.is_lowercase();
+                let offset = if relative { current } else { (0.0, 0.0) };
+                current = match c.to_ascii_uppercase() {
+                    'H' => (args[0] + offset.0, current.1),
+                    'V' => (current.0, args[0] + offset.1),
+                    _   => (args[count - 2] + offset.0, args[count - 1] + offset.1),
+                };
+
+                if c == 'M' || c == 'm' {
+                    start = current;
+                    polylines.push(vec!(current));
+                    // following coordinate pairs are implicit lineto commands
+                    command = Some(if relative { 'l' } else { 'L' });
+                } else {
+                    polylines.last_mut().ok_or_else(|| String::from("Path does not start with a moveto"))?.push(current);
+                }
+                args.clear();
+            }
+        }
+
+        match token {
+            Some(PathToken::Command (c)) => {
+                if !args.is_empty() {
+                    return Err(format!("Path has too few arguments before command {}", c));
+                }
+                if !"MmLlHhVvCcSsQqTtAaZz".contains(c) {
+                    return Err(format!("Unsupported path command: {}", c));
+                }
+                if c == 'Z' || c == 'z' {
+                    current = start;
+                    if let Some(polyline) = polylines.last_mut() {
+                        polyline.push(start);
+                    }
+                    command = None;
+                } else {
+                    command = Some(c);
+                }
+            }
+            Some(PathToken::Number (_)) => {
+                if command.is_none() {
+                    return Err(String::from("Path has a number without a command"));
+                }
+            }
+            None => {
+                if !args.is_empty() {
+                    return Err(String::from("Path ended with too few arguments"));
+                }
+                return Ok(polylines);
+            }
+        }
+    }
+}
+
+/// Returns the number of arguments taken by a path command, curves and arcs are reduced to their end point
+fn argument_count(command: char) -> usize {
+    match command.to_ascii_uppercase() {
+        'H' | 'V'             => 1,
+        'S' | 'Q'             => 4,
+        'C'                   => 6,
+        'A'                   => 7,
+        _                     => 2, // M, L and T
+    }
+}
+
+enum PathToken {
+    Command (char),
+    Number (f32),
+}
+
+struct PathTokenizer {
+    chars: Vec<char>,
+    i:     usize,
+}
+
+impl PathTokenizer {
+    fn next(&mut self) -> Option<PathToken> {
+        while self.i < self.chars.len() && (self.chars[self.i].is_whitespace() || self.chars[self.i] == ',') {
+            self.i += 1;
+        }
+
+        let c = *self.chars.get(self.i)?;
+        if c.is_ascii_alphabetic() && c != 'e' && c != 'E' {
+            self.i += 1;
+            return Some(PathToken::Command (c));
+        }
+
+        let start = self.i;
+        let mut seen_dot = false;
+        let mut seen_exponent = false;
+        while let Some(&c) = self.chars.get(self.i) {
+            let after_exponent = seen_exponent && (self.chars[self.i - 1] == 'e' || self.chars[self.i - 1] == 'E');
+            let accept = if c == '-' || c == '+' {
+                self.i == start || after_exponent
+            } else if c == '.' {
+                !seen_dot && !seen_exponent
+            } else if c == 'e' || c == 'E' {
+                !seen_exponent && self.i > start
+            } else {
+                c.is_ascii_digit()
+            };
+            if !accept {
+                break;
+            }
+            seen_dot      |= c == '.';
+            seen_exponent |= c == 'e' || c == 'E';
+            self.i += 1;
+        }
+
+        if self.i == start {
+            // skip unknown characters so they are reported as an invalid command
+            self.i += 1;
+            return Some(PathToken::Command (c));
+        }
+
+        let text: String = self.chars[start..self.i].iter().collect();
+        match text.parse() {
+            Ok(number) => Some(PathToken::Number (number)),
+            Err(_)     => Some(PathToken::Command (c)),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::geometry::Rect;
+use crate::stage::Surface;
+
+use treeflection::{Node, NodeRunner};
+
+const CELL_SIZE: f32 = 20.0;
+const MAX_CELLS_PER_AXIS: usize = 256;
+
+/// Uniform grid over the surfaces of a stage, used as a broadphase for surface queries.
+/// Surfaces with a path move during a match, so they are kept out of the grid and returned by every query.
+/// This is a cache derived from the surfaces, so it is neither stored nor accessible via treeflection.
+/// It is rebuilt whenever the surfaces may have been modified: when pausing, editing, loading a frame from history and creating a simulation.
+#[derive(Clone, Default)]
+pub struct SurfaceGrid {
+    surface_count: usize, // number of surfaces the grid was built from, so a removed surface is never returned
+    cell_size:     f32,
+    left:          f32,
+    bot:           f32,
+    width:         usize, // cells along the x axis
+    height:        usize, // cells along the y axis
+    cells:         Vec<Vec<usize>>, // surface indexes overlapping each cell, stored row by row from the bottom left
+    dynamic:       Vec<usize>, // surface indexes that are not in the grid
+}
+
+impl SurfaceGrid {
+    pub fn new(surfaces: &[Surface]) -> SurfaceGrid {
+        let mut dynamic = vec!();
+        let mut left  = std::f32::MAX;
+        let mut right = std::f32::MIN;
+        let mut bot   = std::f32::MAX;
+        let mut top   = std::f32::MIN;
+        for (i, surface) in surfaces.iter().enumerate() {
+            if surface.path.is_some() {
+                dynamic.push(i);
+            } else {
+                left  = left.min(surface.x1.min(surface.x2));
+                right = right.max(surface.x1.max(surface.x2));
+                bot   = bot.min(surface.y1.min(surface.y2));
+                top   = top.max(surface.y1.max(surface.y2));
+            }
+        }
+
+        if dynamic.len() == surfaces.len() {
+            return SurfaceGrid {
+                surface_count: surfaces.len(),
+                cell_size:     CELL_SIZE,
+                left:          0.0,
+                bot:           0.0,
+                width:         0,
+                height:        0,
+                cells:         vec!(),
+                dynamic,
+            };
+        }
+
+        // large stages use larger cells to keep memory bounded
+        let span = (right - left).max(top - bot);
+        let cell_size = CELL_SIZE.max(span / MAX_CELLS_PER_AXIS as f32);
+        let width  = ((right - left) / cell_size) as usize + 1;
+        let height = ((top - bot) / cell_size) as usize + 1;
+
+        let mut grid = SurfaceGrid {
+            surface_count: surfaces.len(),
+            cell_size,
+            left,
+            bot,
+            width,
+            height,
+            cells: vec!(vec!(); width * height),
+            dynamic,
+        };
+
+        for (i, surface) in surfaces.iter().enumerate() {
+            if surface.path.is_none() {
+                let rect = Rect::from_tuples(surface.p1(), surface.p2());
+                if let Some((x1, y1, x2, y2)) = grid.cell_range(&rect) {
+                    for y in y1..=y2 {
+                        for x in x1..=x2 {
+                            grid.cells[y * width + x].push(i);
+                        }
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    /// Returns true if surfaces were added or removed since the grid was built.
+    /// Moved or resized surfaces are not detected, as that would cost as much as not using the grid.
+    pub fn is_stale(&self, surfaces: &[Surface]) -> bool {
+        self.surface_count != surfaces.len()
+    }
+
+    /// Returns the indexes of all surfaces that might overlap the passed rect, in ascending order.
+    pub fn query(&self, rect: &Rect) -> Vec<usize> {
+        let mut result = self.dynamic.clone();
+        if let Some((x1, y1, x2, y2)) = self.cell_range(rect) {
+            for y in y1..=y2 {
+                for x in x1..=x2 {
+                    result.extend_from_slice(&self.cells[y * self.width + x]);
+                }
+            }
+        }
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    /// Returns the inclusive range of cells overlapping the rect, None if the rect is entirely outside the grid
+    fn cell_range(&self, rect: &Rect) -> Option<(usize, usize, usize, usize)> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let x1 = ((rect.left()  - self.left) / self.cell_size).floor();
+        let x2 = ((rect.right() - self.left) / self.cell_size).floor();
+        let y1 = ((rect.bot()   - self.bot)  / self.cell_size).floor();
+        let y2 = ((rect.top()   - self.bot)  / self.cell_size).floor();
+
+        let max_x = (self.width  - 1) as f32;
+        let max_y = (self.height - 1) as f32;
+        if x2 < 0.0 || y2 < 0.0 || x1 > max_x || y1 > max_y {
+            return None;
+        }
+
+        Some((
+            x1.max(0.0) as usize,
+            y1.max(0.0) as usize,
+            x2.min(max_x) as usize,
+            y2.min(max_y) as usize,
+        ))
+    }
+}
+
+impl Node for SurfaceGrid {
+    fn node_step(&mut self, _: NodeRunner) -> String {
+        String::from("SurfaceGrid is not accessible via treeflection, it is rebuilt from the surfaces.")
+    }
+}
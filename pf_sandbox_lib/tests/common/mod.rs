@@ -0,0 +1,40 @@
+// each test crate only uses some of the fixtures
+#![allow(dead_code)]
+
+use pf_sandbox_lib::fighter::Fighter;
+use pf_sandbox_lib::input::ControllerInput;
+use pf_sandbox_lib::package::Package;
+use pf_sandbox_lib::player::Handicap;
+use pf_sandbox_lib::simulation::{Simulation, SimulationSetup, PlayerSetup};
+use pf_sandbox_lib::stage::Stage;
+
+/// A simulation of a blank package with each player on their own team, all 4 controllers are plugged in
+pub fn simulation(players: usize) -> Simulation {
+    let mut package = Package::blank("unittest");
+    package.stages.push(String::from("stage"), Stage::default());
+    package.fighters.push(String::from("fighter"), Fighter::default());
+
+    let players = (0..players).map(|team| PlayerSetup {
+        fighter:  String::from("fighter"),
+        team,
+        handicap: Handicap::default(),
+    }).collect();
+    Simulation::new(package, SimulationSetup {
+        init_seed:   42,
+        stage:       String::from("stage"),
+        controllers: (0..4).collect(),
+        players,
+    })
+}
+
+/// Inputs for all 4 controllers that keep the players running back and forth, attacking and jumping
+pub fn inputs(frame: usize) -> Vec<ControllerInput> {
+    (0..4).map(|i| {
+        let mut input = ControllerInput::empty();
+        input.plugged_in = true;
+        input.stick_x = if (frame + i * 10) % 40 < 20 { 1.0 } else { -1.0 };
+        input.a = (frame + i) % 15 == 0;
+        input.y = (frame + i) % 90 == 0;
+        input
+    }).collect()
+}
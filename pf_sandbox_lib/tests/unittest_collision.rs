@@ -0,0 +1,128 @@
+mod common;
+
+use common::simulation;
+use pf_sandbox_lib::collision::{collision_check, CollisionResult};
+use pf_sandbox_lib::fighter::{Fighter, CollisionBox, CollisionBoxRole, HitBox};
+use pf_sandbox_lib::player::{HitlistEntry, Location, Player};
+use pf_sandbox_lib::simulation::Simulation;
+use treeflection::ContextVec;
+
+fn hitbox(point: (f32, f32), id: u64, group: u64, priority: u64, damage: f32) -> CollisionBox {
+    CollisionBox {
+        point,
+        radius: 2.0,
+        role:   CollisionBoxRole::Hit (HitBox { id, group, priority, damage, .. HitBox::default() }),
+    }
+}
+
+/// Player 0 attacks with the hitboxes on every frame of every action, player 1 stands over the top of player 0 with a single large hurtbox
+fn attack(hitboxes: Vec<CollisionBox>) -> (Simulation, Vec<Player>) {
+    let mut sim = simulation(2);
+
+    let mut attacker = Fighter::default();
+    let mut defender = Fighter::default();
+    for action in attacker.actions.iter_mut() {
+        for frame in action.frames.iter_mut() {
+            frame.colboxes = ContextVec::from_vec(hitboxes.clone());
+        }
+    }
+    for action in defender.actions.iter_mut() {
+        for frame in action.frames.iter_mut() {
+            frame.colboxes = ContextVec::from_vec(vec!(CollisionBox { radius: 10.0, .. CollisionBox::default() }));
+        }
+    }
+    sim.package.fighters.push(String::from("attacker"), attacker);
+    sim.package.fighters.push(String::from("defender"), defender);
+
+    let mut players = sim.players.clone();
+    for (player, fighter) in players.iter_mut().zip(["attacker", "defender"].iter()) {
+        player.fighter = fighter.to_string();
+        player.location = Location::Airbourne { x: 0.0, y: 0.0 };
+        player.action = 0;
+        player.frame = 0;
+        player.hitlist.clear();
+    }
+    (sim, players)
+}
+
+fn check(sim: &Simulation, players: &[Player]) -> Vec<Vec<CollisionResult>> {
+    collision_check(players, &sim.package.fighters, &sim.stage, &sim.package.rules, 0)
+}
+
+/// Returns the hitboxes that hit the defender
+fn hits(results: &[Vec<CollisionResult>]) -> Vec<HitBox> {
+    results[1].iter().filter_map(|x| match x {
+        CollisionResult::HitDef { hitbox, player_atk_i: 0, .. } => Some(hitbox.clone()),
+        _ => None
+    }).collect()
+}
+
+#[test]
+fn group_hits_once() {
+    let (sim, players) = attack(vec!(
+        hitbox((1.0,  0.0), 0, 0, 0, 5.0),
+        hitbox((-1.0, 0.0), 1, 0, 0, 5.0),
+    ));
+    let results = check(&sim, &players);
+    assert_eq!(hits(&results).len(), 1);
+    assert_eq!(results[0].iter().filter(|x| matches!(x, CollisionResult::HitAtk { player_def_i: 1, .. })).count(), 1);
+}
+
+#[test]
+fn each_group_hits() {
+    let (sim, players) = attack(vec!(
+        hitbox((1.0,  0.0), 0, 0, 0, 5.0),
+        hitbox((-1.0, 0.0), 1, 1, 0, 7.0),
+        hitbox((0.0,  1.0), 2, 1, 0, 9.0),
+    ));
+    let hits = hits(&check(&sim, &players));
+    assert_eq!(hits.iter().map(|x| x.group).collect::<Vec<_>>(), vec!(0, 1));
+    assert_eq!(hits.iter().map(|x| x.id).collect::<Vec<_>>(), vec!(0, 1));
+}
+
+#[test]
+fn highest_priority_hits() {
+    let (sim, players) = attack(vec!(
+        hitbox((1.0,  0.0), 0, 0, 1, 5.0),
+        hitbox((-1.0, 0.0), 1, 0, 3, 12.0),
+        hitbox((0.0,  1.0), 2, 0, 2, 8.0),
+    ));
+    let hits = hits(&check(&sim, &players));
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, 1);
+    assert_eq!(hits[0].damage, 12.0);
+}
+
+#[test]
+fn priority_tie_uses_lowest_id() {
+    let (sim, players) = attack(vec!(
+        hitbox((1.0,  0.0), 5, 0, 2, 5.0),
+        hitbox((-1.0, 0.0), 3, 0, 2, 7.0),
+        hitbox((0.0,  1.0), 4, 0, 2, 9.0),
+    ));
+    let hits = hits(&check(&sim, &players));
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, 3);
+}
+
+#[test]
+fn missing_hitbox_does_not_block_lower_priority() {
+    let (sim, players) = attack(vec!(
+        hitbox((100.0, 0.0), 0, 0, 5, 12.0), // out of range
+        hitbox((1.0,   0.0), 1, 0, 1, 5.0),
+    ));
+    let hits = hits(&check(&sim, &players));
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].id, 1);
+}
+
+#[test]
+fn hitlist_prevents_rehit() {
+    let (sim, mut players) = attack(vec!(
+        hitbox((1.0,  0.0), 0, 0, 0, 5.0),
+        hitbox((-1.0, 0.0), 1, 1, 0, 5.0),
+    ));
+    players[0].hitlist.push(HitlistEntry { group: 0, player_i: 1 });
+    let hits = hits(&check(&sim, &players));
+    assert_eq!(hits.iter().map(|x| x.group).collect::<Vec<_>>(), vec!(1));
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::simulation;
+use pf_sandbox_lib::divergence::{diff_values, Divergence};
+use pf_sandbox_lib::player::Location;
+
+use serde_json::json;
+
+fn diff(expected: serde_json::Value, actual: serde_json::Value) -> Vec<String> {
+    let mut fields = vec!();
+    diff_values("", &expected, &actual, &mut fields);
+    fields
+}
+
+#[test]
+fn diff_identical() {
+    let value = json!({ "a": 1, "b": [1, 2, { "c": null }] });
+    assert!(diff(value.clone(), value).is_empty());
+}
+
+#[test]
+fn diff_nested_objects() {
+    let expected = json!({ "a": { "b": { "c": 1, "d": 2 }, "e": 3 }, "f": 4 });
+    let actual   = json!({ "a": { "b": { "c": 1, "d": 5 }, "e": 3 }, "f": 6 });
+    assert_eq!(diff(expected, actual), vec!("a.b.d", "f"));
+}
+
+#[test]
+fn diff_missing_and_extra_keys() {
+    let expected = json!({ "a": { "b": 1, "c": 2 } });
+    let actual   = json!({ "a": { "b": 1, "d": 2 } });
+    assert_eq!(diff(expected, actual), vec!("a.c", "a.d"));
+}
+
+#[test]
+fn diff_arrays() {
+    let expected = json!({ "a": [1, { "b": 2 }, 3] });
+    let actual   = json!({ "a": [1, { "b": 4 }, 3] });
+    assert_eq!(diff(expected, actual), vec!("a[1].b"));
+}
+
+#[test]
+fn diff_arrays_different_lengths() {
+    let expected = json!({ "a": [1, 2, 3], "b": [[1], [2]] });
+    let actual   = json!({ "a": [1, 2],    "b": [[1], [2, 3]] });
+    assert_eq!(diff(expected, actual), vec!("a", "b[1]"));
+}
+
+#[test]
+fn compare_identical_players() {
+    let players = simulation(2).players;
+    assert!(Divergence::compare(10, &players, &players).is_none());
+}
+
+#[test]
+fn compare_player_fields() {
+    let expected = simulation(2).players;
+    let mut actual = expected.clone();
+    actual[1].location = Location::Airbourne { x: 1234.5, y: -987.0 };
+    actual[1].damage = 42.0;
+
+    let divergence = Divergence::compare(10, &expected, &actual).unwrap();
+    assert_eq!(divergence.frame, 10);
+    assert_eq!(divergence.player, Some(1));
+    assert!(divergence.fields.contains(&String::from("damage")));
+    assert!(divergence.fields.iter().any(|x| x.starts_with("location.")));
+    assert!(divergence.fields.iter().all(|x| x == "damage" || x.starts_with("location.")));
+}
+
+#[test]
+fn compare_player_count_mismatch() {
+    let expected = simulation(3).players;
+    let actual = simulation(2).players;
+
+    let divergence = Divergence::compare(10, &expected, &actual).unwrap();
+    assert_eq!(divergence.frame, 10);
+    assert_eq!(divergence.player, None);
+    assert_eq!(divergence.fields, vec!("2 players instead of 3"));
+    assert_eq!(divergence.description(), "diverged on frame 10: 2 players instead of 3");
+}
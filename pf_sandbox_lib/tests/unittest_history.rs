@@ -0,0 +1,143 @@
+mod common;
+
+use common::{inputs, simulation};
+use pf_sandbox_lib::files;
+use pf_sandbox_lib::history::History;
+use pf_sandbox_lib::player::Player;
+use pf_sandbox_lib::stage::Stage;
+
+use std::mem;
+
+#[test]
+fn get_matches_pushed() {
+    let mut history = History::new();
+    let mut values = vec!();
+    for frame in 0..200u32 {
+        let value: Vec<u32> = (0..frame % 7).map(|x| x * frame).collect();
+        history.push(&value);
+        values.push(value);
+    }
+
+    assert_eq!(history.len(), 200);
+    for (frame, value) in values.iter().enumerate() {
+        assert_eq!(history.get(frame).as_ref(), Some(value));
+    }
+    assert_eq!(history.get(200), None);
+}
+
+#[test]
+fn truncate_then_push() {
+    let mut history = History::new();
+    for frame in 0..150u32 {
+        history.push(&frame);
+    }
+
+    history.truncate(61);
+    assert_eq!(history.len(), 61);
+    assert_eq!(history.last(), Some(60));
+
+    history.push(&1000);
+    assert_eq!(history.get(61), Some(1000));
+    assert_eq!(history.get(62), None);
+    assert_eq!(history.range(59..63), vec!(59, 60, 1000));
+}
+
+#[test]
+fn grow_and_shrink_within_chunk() {
+    let mut history = History::new();
+    let mut values = vec!();
+    for frame in 0..120u32 {
+        // grows to 30 elements then shrinks back to 0, twice within each chunk
+        let len = if frame % 60 < 30 { frame % 30 } else { 30 - frame % 30 };
+        let value: Vec<u32> = (0..len).map(|x| x + frame % 3).collect();
+        history.push(&value);
+        values.push(value);
+    }
+
+    for (frame, value) in values.iter().enumerate() {
+        assert_eq!(history.get(frame).as_ref(), Some(value));
+    }
+}
+
+#[test]
+fn max_bytes_drops_oldest() {
+    let mut history = History::new();
+    history.set_max_bytes(Some(1000));
+    for frame in 0..1000u64 {
+        history.push(&vec!(frame; 10));
+    }
+
+    assert_eq!(history.len(), 1000);
+    assert!(history.first_frame() > 0);
+    assert_eq!(history.get(0), None);
+    assert_eq!(history.get(999), Some(vec!(999; 10)));
+    assert_eq!(history.get(history.first_frame()), Some(vec!(history.first_frame() as u64; 10)));
+}
+
+#[test]
+fn players_round_trip() {
+    let mut sim = simulation(2);
+    let mut history = History::new();
+    let mut players = vec!();
+    for frame in 0..120 {
+        history.push(&sim.players);
+        players.push(serde_json::to_string(&sim.players).unwrap());
+        sim.step(&inputs(frame));
+    }
+
+    for (frame, players) in players.iter().enumerate() {
+        let stored: Vec<Player> = history.get(frame).unwrap();
+        assert_eq!(&serde_json::to_string(&stored).unwrap(), players);
+    }
+}
+
+/// Measures the memory used by the player and stage history of an 8 minute 4 player match compared to storing every frame in full.
+/// Print the figures with: cargo test --release --test unittest_history -- --nocapture measure_8_minute_match
+#[test]
+fn measure_8_minute_match() {
+    let mut sim = simulation(4);
+    let mut player_history = History::new();
+    let mut stage_history = History::new();
+    let mut player_bytes = 0;
+    let mut stage_bytes = 0;
+    let mut vec_bytes = 0;
+    for frame in 0..8 * 60 * 60 {
+        player_history.push(&sim.players);
+        stage_history.push(&sim.stage);
+        player_bytes += bincode::serialized_size(&sim.players).unwrap() as usize;
+        stage_bytes += bincode::serialized_size(&sim.stage).unwrap() as usize;
+        // only counts the Player structs themselves, not the heap memory they own, so the real Vec<Vec<Player>> is larger
+        vec_bytes += mem::size_of::<Vec<Player>>() + mem::size_of::<Player>() * sim.players.len();
+        sim.step(&inputs(frame));
+    }
+
+    println!("players: {} bytes serialized, at least {} bytes as Vec<Vec<Player>>, {} bytes delta compressed",
+        player_bytes, vec_bytes, player_history.bytes());
+    println!("stage: {} bytes serialized, at least {} bytes as Vec<Stage>, {} bytes delta compressed",
+        stage_bytes, mem::size_of::<Stage>() * 8 * 60 * 60, stage_history.bytes());
+
+    assert!(player_history.bytes() * 2 < player_bytes);
+    assert!(player_history.bytes() * 2 < vec_bytes);
+    assert!(stage_history.bytes() * 10 < stage_bytes);
+}
+
+/// Measures the size of a 2 minute 4 player replay when stored as inputs compared to storing the player and stage history.
+/// Print the figures with: cargo test --release --test unittest_history -- --nocapture measure_replay_cache
+#[test]
+fn measure_replay_cache() {
+    let mut sim = simulation(4);
+    let mut player_history = History::new();
+    let mut stage_history = History::new();
+    let mut input_history = vec!();
+    for frame in 0..2 * 60 * 60 {
+        player_history.push(&sim.players);
+        stage_history.push(&sim.stage);
+        input_history.push(inputs(frame));
+        sim.step(&inputs(frame));
+    }
+
+    let compact = files::compressed_size(&input_history);
+    let cached = files::compressed_size(&(&player_history, &stage_history)) + compact;
+    println!("compact: {} bytes, cached: {} bytes, compact is {:.1}x smaller", compact, cached, cached as f64 / compact as f64);
+    assert!(compact * 2 < cached);
+}
@@ -0,0 +1,108 @@
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate serde_json;
+
+use pf_sandbox_lib::input::ControllerInput;
+use pf_sandbox_lib::json_upgrade;
+use pf_sandbox_lib::player::Handicap;
+use pf_sandbox_lib::simulation::PlayerSetup;
+
+use chrono::{DateTime, Local};
+use serde_json::Value;
+
+// Mirrors the Replay struct of pf_sandbox, which cannot be used from here.
+// The cache and set info only contain types owned by pf_sandbox so they are left as json.
+#[derive(Deserialize)]
+struct Replay {
+    header:               ReplayHeader,
+    metadata:             ReplayMetadata,
+    init_seed:            u64,
+    timestamp:            DateTime<Local>,
+    input_history:        Vec<Vec<ControllerInput>>,
+    checksums:            Vec<Option<u64>>,
+    selected_controllers: Vec<usize>,
+    selected_players:     Vec<PlayerSetup>,
+    selected_ais:         Vec<usize>,
+    selected_stage:       String,
+    cache:                Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ReplayHeader {
+    format_version: u64,
+    engine_version: u64,
+    build_version:  String,
+    package_hash:   String,
+}
+
+#[derive(Deserialize)]
+struct ReplayMetadata {
+    name:        String,
+    fighters:    Vec<String>,
+    stage:       String,
+    player_tags: Vec<String>,
+    winner:      Option<usize>,
+    duration:    usize,
+    set:         Option<Value>,
+    tags:        Vec<String>,
+}
+
+/// A replay as saved by the version of PF Sandbox before replays had a header
+fn replay0() -> Value {
+    let input = serde_json::to_value(ControllerInput::empty()).unwrap();
+    json!({
+        "init_seed":            42,
+        "timestamp":            "2019-03-02T14:05:09.123+10:00",
+        "input_history":        [[input.clone(), input.clone()], [input.clone(), input]],
+        "player_history":       [[], []],
+        "stage_history":        [{}, {}],
+        "selected_controllers": [0, 1],
+        "selected_players":     [
+            { "fighter": "fighter1", "team": 0 },
+            { "fighter": "fighter2", "team": 1 },
+        ],
+        "selected_ais":         [],
+        "selected_stage":       "stage",
+    })
+}
+
+#[test]
+fn upgrade_replay0() {
+    let mut json = replay0();
+    json_upgrade::upgrade_to_latest_replay(&mut json).unwrap();
+    let replay: Replay = serde_json::from_value(json).unwrap();
+
+    assert_eq!(replay.header.format_version, json_upgrade::replay_format_version());
+    assert_eq!(replay.header.engine_version, 0);
+    assert_eq!(replay.header.build_version, "");
+    assert_eq!(replay.header.package_hash, "");
+    assert_eq!(replay.init_seed, 42);
+    assert_eq!(replay.timestamp, DateTime::parse_from_rfc3339("2019-03-02T14:05:09.123+10:00").unwrap());
+    assert_eq!(replay.input_history.len(), 2);
+    assert!(replay.checksums.is_empty());
+    assert_eq!(replay.selected_controllers, vec!(0, 1));
+    assert!(replay.selected_ais.is_empty());
+    assert_eq!(replay.selected_stage, "stage");
+    assert!(replay.cache.is_none());
+
+    assert_eq!(replay.selected_players.len(), 2);
+    let handicap = serde_json::to_value(Handicap::default()).unwrap();
+    for player in &replay.selected_players {
+        assert_eq!(serde_json::to_value(&player.handicap).unwrap(), handicap);
+    }
+
+    assert_eq!(replay.metadata.name, "2019-03-02 14:05:09");
+    assert_eq!(replay.metadata.fighters, vec!("fighter1", "fighter2"));
+    assert_eq!(replay.metadata.stage, "stage");
+    assert_eq!(replay.metadata.player_tags, vec!("", ""));
+    assert_eq!(replay.metadata.winner, None);
+    assert_eq!(replay.metadata.duration, 2);
+    assert!(replay.metadata.set.is_none());
+    assert!(replay.metadata.tags.is_empty());
+}
+
+#[test]
+fn upgrade_newer_replay_fails() {
+    let mut json = replay0();
+    json["header"] = json!({ "format_version": json_upgrade::replay_format_version() + 1 });
+    assert!(json_upgrade::upgrade_to_latest_replay(&mut json).is_err());
+}
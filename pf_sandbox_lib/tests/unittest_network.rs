@@ -0,0 +1,96 @@
+use pf_sandbox_lib::input::ControllerInput;
+use pf_sandbox_lib::network::NetplayInputs;
+
+fn held(a: bool) -> Vec<ControllerInput> {
+    vec!(ControllerInput { plugged_in: true, a, .. ControllerInput::empty() })
+}
+
+/// One remote peer whose inputs for frames 1 and 2 arrived before any frames were simulated
+fn two_confirmed(max_rollback: usize) -> NetplayInputs {
+    let mut inputs = NetplayInputs::new(0, max_rollback);
+    inputs.add_peer();
+    inputs.receive(0, 1, held(false));
+    inputs.receive(0, 2, held(false));
+    inputs.confirm(0);
+    inputs
+}
+
+#[test]
+fn misprediction_rolls_back() {
+    let mut inputs = two_confirmed(10);
+    assert_eq!(inputs.frames_to_step(3), 1);
+
+    // frames 3 and 4 were predicted to repeat frame 2 but the peer pressed a on frame 3
+    inputs.receive(0, 3, held(true));
+    inputs.confirm(4);
+    assert_eq!(inputs.confirmed_frame(4), 3);
+    assert_eq!(inputs.frames_to_step(4), 2);
+
+    // the rollback only applies to the step that received the input
+    inputs.confirm(4);
+    assert_eq!(inputs.frames_to_step(4), 1);
+}
+
+#[test]
+fn correct_prediction_does_not_roll_back() {
+    let mut inputs = two_confirmed(10);
+    inputs.receive(0, 3, held(false));
+    inputs.confirm(4);
+    assert_eq!(inputs.confirmed_frame(4), 3);
+    assert_eq!(inputs.frames_to_step(4), 1);
+}
+
+#[test]
+fn unsimulated_frame_does_not_roll_back() {
+    let mut inputs = two_confirmed(10);
+    inputs.receive(0, 3, held(true));
+    inputs.confirm(2);
+    assert_eq!(inputs.frames_to_step(2), 1);
+}
+
+#[test]
+fn out_of_order_inputs_wait_for_earlier_frames() {
+    let mut inputs = two_confirmed(10);
+    inputs.receive(0, 4, held(true));
+    inputs.confirm(5);
+    assert_eq!(inputs.confirmed_frame(5), 2);
+    assert_eq!(inputs.frames_to_step(5), 1);
+
+    inputs.receive(0, 3, held(true));
+    inputs.confirm(5);
+    assert_eq!(inputs.confirmed_frame(5), 4);
+    assert_eq!(inputs.frames_to_step(5), 3);
+
+    // resent inputs are ignored
+    inputs.receive(0, 3, held(false));
+    inputs.confirm(5);
+    assert_eq!(inputs.confirmed_frame(5), 4);
+    assert_eq!(inputs.frames_to_step(5), 1);
+}
+
+#[test]
+fn stall_at_max_rollback() {
+    let mut inputs = NetplayInputs::new(0, 2);
+    inputs.add_peer();
+    assert!(!inputs.stall(0));
+    assert!(!inputs.stall(1));
+    assert!(inputs.stall(2));
+
+    inputs.receive(0, 1, held(false));
+    inputs.confirm(2);
+    assert!(!inputs.stall(2));
+    assert!(inputs.stall(3));
+}
+
+#[test]
+fn input_delay_pads_with_empty_inputs() {
+    let mut inputs = NetplayInputs::new(2, 10);
+    assert_eq!(inputs.add_local(1, held(true)), 1..=3);
+    assert!(inputs.local(1) == Some(&vec!(ControllerInput::empty())));
+    assert!(inputs.local(2) == Some(&vec!(ControllerInput::empty())));
+    assert!(inputs.local(3) == Some(&held(true)));
+
+    assert_eq!(inputs.add_local(2, held(false)), 4..=4);
+    assert!(inputs.local(4) == Some(&held(false)));
+    assert!(inputs.local(5).is_none());
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::{inputs, simulation};
+use pf_sandbox_lib::history::History;
+use pf_sandbox_lib::particle::{Particle, ParticleType};
+use pf_sandbox_lib::results::DeathRecord;
+use pf_sandbox_lib::rules::Goal;
+use pf_sandbox_lib::simulation::{checksum, SimulationState};
+
+#[test]
+fn step_headless() {
+    let mut sim = simulation(2);
+    for frame in 0..120 {
+        if let SimulationState::Finished (_) = sim.step(&inputs(frame)) {
+            panic!("game finished on frame {}", sim.current_frame);
+        }
+    }
+    assert_eq!(sim.current_frame, 120);
+    assert_eq!(sim.players.len(), 2);
+}
+
+#[test]
+fn step_deterministic() {
+    let mut sim1 = simulation(2);
+    let mut sim2 = simulation(2);
+    for frame in 0..120 {
+        sim1.step(&inputs(frame));
+        sim2.step(&inputs(frame));
+    }
+    assert_eq!(serde_json::to_string(&sim1.players).unwrap(), serde_json::to_string(&sim2.players).unwrap());
+}
+
+#[test]
+fn checksum_matches_state() {
+    let mut sim1 = simulation(2);
+    let mut sim2 = simulation(2);
+    assert_eq!(sim1.checksum(), sim2.checksum());
+
+    let initial = sim1.checksum();
+    for frame in 0..60 {
+        sim1.step(&inputs(frame));
+        sim2.step(&inputs(frame));
+        assert_eq!(sim1.checksum(), sim2.checksum());
+    }
+    assert_ne!(sim1.checksum(), initial);
+
+    sim2.players[0].damage += 1.0;
+    assert_ne!(sim1.checksum(), sim2.checksum());
+}
+
+#[test]
+fn checksum_from_history() {
+    let mut sim = simulation(2);
+    let mut player_history = History::new();
+    let mut stage_history = History::new();
+    let mut checksums = vec!();
+    for frame in 0..120 {
+        player_history.push(&sim.players);
+        stage_history.push(&sim.stage);
+        checksums.push(sim.checksum());
+        sim.step(&inputs(frame));
+    }
+
+    for (frame, expected) in checksums.iter().enumerate() {
+        let players = player_history.get(frame).unwrap();
+        let stage = stage_history.get(frame).unwrap();
+        assert_eq!(checksum(&players, &stage, &[], sim.init_seed, frame), *expected);
+    }
+}
+
+#[test]
+fn checksum_excludes_particles() {
+    let sim1 = simulation(2);
+    let mut sim2 = simulation(2);
+    sim2.players[0].particles.push(Particle {
+        color:       [1.0, 1.0, 1.0],
+        counter:     0,
+        counter_max: 10,
+        x:           0.0,
+        y:           0.0,
+        angle:       0.0,
+        p_type:      ParticleType::AirJump,
+    });
+    assert_eq!(sim1.checksum(), sim2.checksum());
+
+    // particles are still serialized outside of the checksum
+    let json = serde_json::to_value(&sim2.players[0]).unwrap();
+    assert_eq!(json["particles"].as_array().map(|x| x.len()), Some(1));
+}
+
+/// Netplay predicts a late input by repeating the last input received, then rolls back and resimulates once the real input arrives
+#[test]
+fn rollback_matches_true_inputs() {
+    let true_inputs: Vec<_> = (0..120).map(inputs).collect();
+    let mut expected = simulation(2);
+    for frame_inputs in &true_inputs {
+        expected.step(frame_inputs);
+    }
+
+    // the inputs of the second controller are late from this frame onwards
+    let late_frame = 60;
+    let mut sim = simulation(2);
+    let mut player_history = History::new();
+    let mut stage_history = History::new();
+    for (i, frame_inputs) in true_inputs.iter().enumerate() {
+        player_history.push(&sim.players);
+        stage_history.push(&sim.stage);
+        let mut predicted = frame_inputs.clone();
+        if i >= late_frame {
+            predicted[1] = true_inputs[late_frame - 1][1].clone();
+        }
+        sim.step(&predicted);
+    }
+    assert_ne!(sim.checksum(), expected.checksum());
+
+    sim.rewind(late_frame, player_history.get(late_frame).unwrap(), stage_history.get(late_frame).unwrap());
+    for frame_inputs in &true_inputs[late_frame..] {
+        sim.step(frame_inputs);
+    }
+    assert_eq!(sim.current_frame, expected.current_frame);
+    assert_eq!(sim.checksum(), expected.checksum());
+}
+
+/// Killers are credited by the death records of the players they killed, self-destructs cost the penalty on top of the death
+#[test]
+fn kills_and_scores() {
+    let mut sim = simulation(3);
+    sim.package.rules.goal = Goal::KillDeathScore;
+    sim.package.rules.self_destruct_penalty = 2;
+
+    sim.players[0].result.deaths.push(DeathRecord { player: Some(1), stage: false, frame: 10 });
+    sim.players[1].result.deaths.push(DeathRecord { player: None,    stage: false, frame: 20 }); // self-destruct
+    sim.players[2].result.deaths.push(DeathRecord { player: None,    stage: true,  frame: 30 }); // stage hazard
+    sim.players[2].result.deaths.push(DeathRecord { player: Some(0), stage: false, frame: 40 });
+
+    let (results, _) = sim.results(&[0, 1, 2]);
+    let kills: Vec<Vec<(Option<usize>, usize)>> = results.iter().map(|x| x.kills.iter().map(|x| (x.player, x.frame)).collect()).collect();
+    assert_eq!(kills, vec!(vec!((Some(2), 40)), vec!((Some(0), 10)), vec!()));
+
+    let scores: Vec<Option<i64>> = results.iter().map(|x| x.score).collect();
+    assert_eq!(scores, vec!(Some(0), Some(-2), Some(-2)));
+}
@@ -0,0 +1,161 @@
+use pf_sandbox_lib::geometry::Rect;
+use pf_sandbox_lib::stage::{Stage, Surface, SpawnPoint, SurfacePath, PathKeyframe, PathEase, PathRepeat};
+use pf_sandbox_lib::stage_analysis::StageAnalysis;
+use treeflection::ContextVec;
+
+fn stage(surfaces: Vec<Surface>) -> Stage {
+    let mut stage = Stage::default();
+    stage.surfaces = ContextVec::from_vec(surfaces);
+    stage.rebuild_surface_grid();
+    stage
+}
+
+#[test]
+fn connected_floors_tolerate_float_error() {
+    let stage = stage(vec!(
+        Surface::new(-10.0, 0.0, 0.0,    0.0, true, false, false),
+        Surface::new(0.0001, 0.0, 10.0,  0.0, true, false, false),
+        Surface::new(10.5,   0.0, 20.0,  0.0, true, false, false),
+    ));
+
+    let floors = stage.connected_floors(1);
+    assert_eq!(floors.left_i, Some(0));
+    assert_eq!(floors.right_i, None);
+}
+
+#[test]
+fn surface_grid_query() {
+    let stage = stage(vec!(
+        Surface::new(-100.0, 0.0, -90.0, 0.0, true, false, false),
+        Surface::new(90.0,   0.0, 100.0, 0.0, true, false, false),
+        Surface::new(-100.0, 0.0, 100.0, 0.0, true, false, false),
+    ));
+
+    let near_left = stage.surfaces_near(&Rect { x1: -96.0, y1: -1.0, x2: -94.0, y2: 1.0 });
+    assert!(near_left.contains(&0));
+    assert!(near_left.contains(&2));
+    assert!(!near_left.contains(&1));
+
+    let outside = stage.surfaces_near(&Rect { x1: 500.0, y1: 500.0, x2: 510.0, y2: 510.0 });
+    assert!(outside.is_empty());
+}
+
+#[test]
+fn surface_grid_rebuilt_after_modifying_surfaces() {
+    let mut stage = stage(vec!(
+        Surface::new(-100.0, 0.0, -90.0, 0.0, true, false, false),
+        Surface::new(90.0,   0.0, 100.0, 0.0, true, false, false),
+    ));
+    let rect = Rect { x1: -96.0, y1: -1.0, x2: -94.0, y2: 1.0 };
+    assert!(!stage.surfaces_near(&rect).contains(&1));
+
+    // moving a surface requires a rebuild
+    stage.surfaces[1].x1 = -100.0;
+    stage.rebuild_surface_grid();
+    assert!(stage.surfaces_near(&rect).contains(&1));
+
+    // a removed surface is never returned, even before the rebuild
+    stage.surfaces.remove(1);
+    assert!(stage.surface_grid.is_stale(&stage.surfaces));
+    assert_eq!(stage.surfaces_near(&rect), vec!(0));
+
+    stage.rebuild_surface_grid();
+    assert!(!stage.surface_grid.is_stale(&stage.surfaces));
+    assert_eq!(stage.surfaces_near(&rect), vec!(0));
+}
+
+#[test]
+fn analyze_symmetric_stage() {
+    let mut main = Surface::new(-50.0, 0.0, 50.0, 0.0, true, false, false);
+    main.floor.as_mut().unwrap().pass_through = false;
+    main.grab1 = true;
+    main.grab2 = true;
+    let mut stage = stage(vec!(
+        main,
+        Surface::new(-40.0, 30.0, -20.0, 30.0, true, false, false),
+        Surface::new(20.0,  30.0, 40.0,  30.0, true, false, false),
+    ));
+    stage.spawn_points = ContextVec::from_vec(vec!(
+        SpawnPoint { x: -30.0, y: 1.0, face_right: true },
+        SpawnPoint { x: 30.0,  y: 1.0, face_right: false },
+    ));
+    stage.respawn_points = stage.spawn_points.clone();
+
+    let analysis = StageAnalysis::new(&stage);
+    assert!(analysis.legal());
+    assert_eq!(analysis.platform_heights.len(), 2);
+    assert_eq!(analysis.platform_heights[0].height, 30.0);
+    assert_eq!(analysis.ledge_distances[0].side, 150.0);
+
+    stage.analyze();
+    assert!(stage.legal);
+
+    stage.spawn_points[1].face_right = true;
+    stage.respawn_points[0].y = -10.0;
+    let analysis = StageAnalysis::new(&stage);
+    assert!(!analysis.legal());
+    assert_eq!(analysis.asymmetric_spawns, vec!(0, 1));
+    assert_eq!(analysis.invalid_spawns.len(), 1);
+}
+
+fn path(ease: PathEase, repeat: PathRepeat) -> SurfacePath {
+    SurfacePath {
+        keyframes: vec!(
+            PathKeyframe { frame: 0,  x: 0.0,  y: 0.0,  ease: PathEase::Linear },
+            PathKeyframe { frame: 10, x: 20.0, y: -10.0, ease },
+        ),
+        repeat,
+        .. SurfacePath::default()
+    }
+}
+
+#[test]
+fn path_linear_loop() {
+    let path = path(PathEase::Linear, PathRepeat::Loop);
+    assert_eq!(path.offset_at(0),  (0.0, 0.0));
+    assert_eq!(path.offset_at(5),  (10.0, -5.0));
+    assert_eq!(path.offset_at(10), (0.0, 0.0));
+    assert_eq!(path.offset_at(12), (4.0, -2.0));
+    assert_eq!(path.offset_at(9),  (18.0, -9.0));
+}
+
+#[test]
+fn path_linear_ping_pong() {
+    let path = path(PathEase::Linear, PathRepeat::PingPong);
+    assert_eq!(path.offset_at(5),  (10.0, -5.0));
+    assert_eq!(path.offset_at(10), (20.0, -10.0));
+    assert_eq!(path.offset_at(12), (16.0, -8.0));
+    assert_eq!(path.offset_at(20), (0.0, 0.0));
+    assert_eq!(path.offset_at(25), (10.0, -5.0));
+}
+
+#[test]
+fn path_ease_in_out() {
+    let path = path(PathEase::EaseInOut, PathRepeat::PingPong);
+    assert_eq!(path.offset_at(0),  (0.0, 0.0));
+    assert_eq!(path.offset_at(5),  (10.0, -5.0));
+    assert_eq!(path.offset_at(10), (20.0, -10.0));
+
+    // starts and ends slower than linear
+    let (x, _) = path.offset_at(2);
+    assert!(x < 4.0);
+    let (x, _) = path.offset_at(8);
+    assert!(x > 16.0);
+}
+
+#[test]
+fn path_without_keyframes() {
+    let path = SurfacePath::default();
+    assert_eq!(path.offset_at(7), (0.0, 0.0));
+}
+
+#[test]
+fn step_path_does_not_drift() {
+    let mut surface = Surface::new(-10.1, 3.3, 10.7, 3.3, true, false, false);
+    surface.path = Some(path(PathEase::EaseInOut, PathRepeat::PingPong));
+    for frame in 0..100_001 {
+        surface.step_path(frame);
+    }
+    // frame 100_000 is the start of the path
+    assert_eq!((surface.x1, surface.y1, surface.x2, surface.y2), (-10.1, 3.3, 10.7, 3.3));
+}
@@ -0,0 +1,113 @@
+use pf_sandbox_lib::rules::Rules;
+use pf_sandbox_lib::stage_strike::{SetGame, StageStrike, StrikeTurn};
+
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|x| x.to_string()).collect()
+}
+
+fn rules(starters: &[&str], counterpicks: &[&str], bans: u64) -> Rules {
+    Rules {
+        starter_stages:     keys(starters),
+        counterpick_stages: keys(counterpicks),
+        counterpick_bans:   bans,
+        daves_stupid_rule:  true,
+        .. Rules::default()
+    }
+}
+
+fn game(stage: &str, winner: usize, loser: usize) -> SetGame {
+    SetGame { stage: stage.to_string(), winner, loser }
+}
+
+#[test]
+fn first_game_strike_order() {
+    let rules = rules(&["a", "b", "c", "d", "e"], &["f"], 2);
+    let pools = [0, 1];
+    let mut strike = StageStrike::new(&[], &rules, keys(&["a", "b", "c", "d", "e", "f", "g"]));
+    assert_eq!(strike.stages.iter().map(|x| x.key.as_str()).collect::<Vec<_>>(), vec!("a", "b", "c", "d", "e"));
+
+    // 1 strike, then 2 strikes each
+    assert_eq!(strike.turn(&[], &pools), StrikeTurn::Strike { pool: 0 });
+    assert_eq!(strike.select(&[], &pools, 0), None);
+    assert_eq!(strike.turn(&[], &pools), StrikeTurn::Strike { pool: 1 });
+    assert_eq!(strike.select(&[], &pools, 0), None); // already struck
+    assert_eq!(strike.select(&[], &pools, 1), None);
+    assert_eq!(strike.turn(&[], &pools), StrikeTurn::Strike { pool: 1 });
+    assert_eq!(strike.select(&[], &pools, 2), None);
+    assert_eq!(strike.turn(&[], &pools), StrikeTurn::Strike { pool: 0 });
+    assert_eq!(strike.select(&[], &pools, 4), Some(String::from("d")));
+    assert_eq!(strike.turn(&[], &pools), StrikeTurn::Done { stage: String::from("d") });
+}
+
+#[test]
+fn missing_stages_fall_back_to_every_stage() {
+    let rules = rules(&["missing"], &[], 2);
+    let strike = StageStrike::new(&[], &rules, keys(&["a", "b"]));
+    assert_eq!(strike.stages.iter().map(|x| x.key.as_str()).collect::<Vec<_>>(), vec!("a", "b"));
+}
+
+#[test]
+fn counterpick_bans_then_pick() {
+    let rules = rules(&["a", "b", "c"], &["d"], 2);
+    let pools = [0, 1];
+    let games = [game("a", 1, 0)];
+    let mut strike = StageStrike::new(&games, &rules, keys(&["a", "b", "c", "d"]));
+    assert_eq!(strike.stages.len(), 4);
+    assert_eq!(strike.bans, 2);
+
+    assert_eq!(strike.turn(&games, &pools), StrikeTurn::Strike { pool: 1 });
+    assert_eq!(strike.select(&games, &pools, 0), None);
+    assert_eq!(strike.select(&games, &pools, 1), None);
+    assert_eq!(strike.turn(&games, &pools), StrikeTurn::Pick { pool: 0 });
+    assert_eq!(strike.select(&games, &pools, 0), None); // struck
+    assert_eq!(strike.select(&games, &pools, 3), Some(String::from("d")));
+}
+
+#[test]
+fn daves_stupid_rule() {
+    let rules = rules(&["a", "b", "c"], &[], 1);
+    let pools = [0, 1];
+    // pool 0 won on a, then lost on b
+    let games = [game("a", 0, 1), game("b", 1, 0)];
+    let mut strike = StageStrike::new(&games, &rules, keys(&["a", "b", "c"]));
+    assert_eq!(strike.stages.iter().map(|x| x.dsr).collect::<Vec<_>>(), vec!(true, false, false));
+    assert_eq!(strike.bans, 1);
+
+    // the winner cannot waste their ban on a stage the loser cannot pick
+    assert_eq!(strike.turn(&games, &pools), StrikeTurn::Strike { pool: 1 });
+    assert_eq!(strike.select(&games, &pools, 0), None);
+    assert!(!strike.stages[0].struck);
+    assert_eq!(strike.turn(&games, &pools), StrikeTurn::Strike { pool: 1 });
+    assert_eq!(strike.select(&games, &pools, 1), None);
+
+    assert_eq!(strike.turn(&games, &pools), StrikeTurn::Pick { pool: 0 });
+    assert_eq!(strike.select(&games, &pools, 0), None);
+    assert_eq!(strike.select(&games, &pools, 2), Some(String::from("c")));
+}
+
+#[test]
+fn daves_stupid_rule_limits_bans() {
+    let rules = rules(&["a", "b", "c"], &[], 2);
+    let games = [game("a", 0, 1), game("b", 1, 0)];
+    let strike = StageStrike::new(&games, &rules, keys(&["a", "b", "c"]));
+    // only b and c can be picked, so a single ban leaves the loser one choice
+    assert_eq!(strike.bans, 1);
+}
+
+#[test]
+fn daves_stupid_rule_ignored_when_every_stage_was_won() {
+    let rules = rules(&["a", "b"], &[], 1);
+    let games = [game("a", 0, 1), game("b", 0, 1), game("a", 1, 0)];
+    let strike = StageStrike::new(&games, &rules, keys(&["a", "b"]));
+    assert!(strike.stages.iter().all(|x| !x.dsr));
+    assert_eq!(strike.bans, 1);
+}
+
+#[test]
+fn daves_stupid_rule_disabled() {
+    let mut rules = rules(&["a", "b", "c"], &[], 1);
+    rules.daves_stupid_rule = false;
+    let games = [game("a", 0, 1), game("b", 1, 0)];
+    let strike = StageStrike::new(&games, &rules, keys(&["a", "b", "c"]));
+    assert!(strike.stages.iter().all(|x| !x.dsr));
+}
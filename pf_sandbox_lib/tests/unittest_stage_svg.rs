@@ -0,0 +1,91 @@
+use pf_sandbox_lib::stage::{Stage, Surface};
+use pf_sandbox_lib::stage_svg;
+use treeflection::ContextVec;
+
+#[test]
+fn import_drawing() {
+    let svg = r##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg">
+  <title>Drawn Stage</title>
+  <rect id="blast" x="-200" y="-200" width="400" height="300"/>
+  <rect id="camera" x="-150" y="-150" width="300" height="200"/>
+  <!-- <line class="floor" x1="0" y1="0" x2="1" y2="1"/> -->
+  <path class="floor grab" d="M -50,0 L 0 10 50,0 v 20"/>
+  <polyline points="-30,-30 -10,-30" style="fill:none; stroke:#00ff00"/>
+  <line x1="0" y1="0" x2="10" y2="10" stroke="purple"/>
+  <circle class="spawn face-left" cx="10" cy="-1" r="2"/>
+  <circle class="respawn" cx="-10" cy="-20" r="2"/>
+</svg>"##;
+
+    let stage = stage_svg::import(svg).unwrap();
+    assert_eq!(stage.name, "Drawn Stage");
+    assert_eq!(stage.blast.y1, -100.0);
+    assert_eq!(stage.blast.y2, 200.0);
+    assert_eq!(stage.surfaces.len(), 4);
+
+    assert_eq!(stage.surfaces[0].p1(), (-50.0, 0.0));
+    assert_eq!(stage.surfaces[0].p2(), (0.0, -10.0));
+    assert!(stage.surfaces[0].grab1);
+    assert!(!stage.surfaces[0].grab2);
+    assert_eq!(stage.surfaces[2].p2(), (50.0, -20.0));
+    assert!(stage.surfaces[2].grab2);
+    assert!(stage.surfaces[3].is_pass_through());
+    assert_eq!(stage.surfaces[3].y1, 30.0);
+
+    assert_eq!(stage.spawn_points.len(), 1);
+    assert!(!stage.spawn_points[0].face_right);
+    assert_eq!(stage.respawn_points[0].y, 20.0);
+}
+
+#[test]
+fn round_trip() {
+    let stage = Stage::default();
+    let imported = stage_svg::import(&stage_svg::export(&stage)).unwrap();
+
+    assert_eq!(imported.name, stage.name);
+    assert_eq!(imported.surfaces.len(), stage.surfaces.len());
+    for (a, b) in imported.surfaces.iter().zip(stage.surfaces.iter()) {
+        assert_eq!(a.p1(), b.p1());
+        assert_eq!(a.p2(), b.p2());
+        assert_eq!(a.grab1, b.grab1);
+        assert_eq!(a.grab2, b.grab2);
+        assert_eq!(a.is_pass_through(), b.is_pass_through());
+    }
+    assert_eq!(imported.spawn_points.len(), stage.spawn_points.len());
+    assert_eq!(imported.respawn_points[3].y, stage.respawn_points[3].y);
+    assert_eq!(imported.camera.x1, stage.camera.x1);
+}
+
+#[test]
+fn attribute_containing_greater_than() {
+    let svg = r##"<svg xmlns="http://www.w3.org/2000/svg">
+  <title>Quoted</title>
+  <rect id="blast" x="-200" y="-200" width="400" height="300" data-note="a > b"/>
+  <rect id="camera" x="-150" y="-150" width="300" height="200"/>
+  <line class="floor" x1="-10" y1="0" x2="10" y2="0" data-label='x > y'/>
+</svg>"##;
+
+    let stage = stage_svg::import(svg).unwrap();
+    assert_eq!(stage.blast.x1, -200.0);
+    assert_eq!(stage.camera.x1, -150.0);
+    assert_eq!(stage.surfaces.len(), 1);
+    assert_eq!(stage.surfaces[0].p2(), (10.0, 0.0));
+}
+
+#[test]
+fn round_trip_surface_types() {
+    let mut stage = Stage::default();
+    stage.surfaces = ContextVec::from_vec(vec!(
+        Surface::new(-10.0, 20.0, 10.0, 20.0, false, false, false),
+        Surface::new(-20.0, 0.0, -20.0, 10.0, false, true, false),
+        Surface::new(-20.0, 40.0, 20.0, 40.0, false, false, true),
+    ));
+
+    let imported = stage_svg::import(&stage_svg::export(&stage)).unwrap();
+    assert_eq!(imported.surfaces.len(), 3);
+    for (a, b) in imported.surfaces.iter().zip(stage.surfaces.iter()) {
+        assert_eq!(a.floor.is_some(), b.floor.is_some());
+        assert_eq!(a.wall, b.wall);
+        assert_eq!(a.ceiling, b.ceiling);
+    }
+}